@@ -0,0 +1,292 @@
+use std::error::Error;
+use std::thread;
+use std::time::Duration;
+
+use crate::{
+    account_request, convert_datetime_input_to_unix_time, flight_identifiers_request,
+    flight_summary_request, handle_response, json, monitor_seat_availability_request, parse_money,
+    parse_seat_class, parse_watched_fields, print_error, reserve_seats_request,
+    search_flights_request, Connection, SearchFilters, Session, Watch, WATCH_SEATS,
+};
+
+const LOGIN_SERVICE_ID: u8 = 19;
+const SECONDS_IN_YEAR: u32 = 31536000;
+// How long to keep monitoring after the subscription expires, for the server to say that it has.
+const EXPIRY_GRACE: Duration = Duration::from_secs(2);
+
+const COMMANDS: [&str; 5] = ["ids", "summary", "search", "reserve", "monitor"];
+const COMMAND_USAGE: &str = "Commands:
+  ids --from SOURCE --to DESTINATION
+  summary FLIGHT_ID [--currency CODE] [--user USERNAME --password PASSWORD]
+  search [--from SOURCE] [--to DESTINATION] [--after \"YYYY-MM-DD HH:MM\"] [--before \"YYYY-MM-DD HH:MM\"]
+         [--class CLASS] [--min-seats N] [--max-airfare \"AMOUNT CODE\"] [--min-baggage KG]
+         [--sort departure|airfare|seats] [--page N] [--currency CODE]
+  reserve FLIGHT_ID --seats N [--class CLASS] [--quote QUOTE_ID] --user USERNAME --password PASSWORD
+  monitor FLIGHT_ID --for SECONDS [--fields seats,baggage,airfare,status] [--seats-below N]
+          [--baggage-below KG] [--airfare-below \"AMOUNT CODE\"] --user USERNAME --password PASSWORD";
+
+pub fn is_command(arg: &str) -> bool {
+    COMMANDS.contains(&arg)
+}
+
+// Removes the option and the value after it from the arguments, returning the value if the option was given.
+pub fn take_option(args: &mut Vec<String>, name: &str) -> Result<Option<String>, Box<dyn Error>> {
+    match args.iter().position(|arg| arg == name) {
+        Some(i) if i + 1 < args.len() => {
+            let value = args.remove(i + 1);
+            args.remove(i);
+            Ok(Some(value))
+        }
+        Some(_) => Err(format!("{} needs a value", name).into()),
+        None => Ok(None),
+    }
+}
+
+// A command with its arguments parsed. Commands that act for a user log in first.
+enum Command {
+    Anonymous(Vec<u8>),
+    Summary {
+        login: Option<(String, String)>,
+        flight_id: u32,
+        display_currency: Option<String>,
+    },
+    Reserve {
+        login: (String, String),
+        flight_id: u32,
+        seats: u32,
+        seat_class: u8,
+        quote_id: Option<u32>,
+    },
+    Monitor {
+        login: (String, String),
+        flight_id: u32,
+        monitor_interval: u32,
+        watch: Watch,
+    },
+}
+
+// Runs the command in the arguments, printing the response like the menu does. Returns false if the arguments
+// are invalid or the server refused the request.
+pub fn run(args: Vec<String>, connection: &mut Connection) -> Result<bool, Box<dyn Error>> {
+    let command = match parse_command(args) {
+        Ok(command) => command,
+        Err(e) => {
+            print_error(&e.to_string());
+            if !json::enabled() {
+                println!("{}", COMMAND_USAGE);
+            }
+            return Ok(false);
+        }
+    };
+
+    let mut session = None;
+    match command {
+        Command::Anonymous(request) => send(connection, &request, &mut session),
+        // Logged in users are given a quote for the fares.
+        Command::Summary {
+            login,
+            flight_id,
+            display_currency,
+        } => {
+            let mut session = match login {
+                Some(login) => Some(log_in(connection, &login, &mut session)?),
+                None => None,
+            };
+            let request =
+                flight_summary_request(session.as_ref(), flight_id, display_currency.as_deref());
+            send(connection, &request, &mut session)
+        }
+        Command::Reserve {
+            login,
+            flight_id,
+            seats,
+            seat_class,
+            quote_id,
+        } => {
+            let session = log_in(connection, &login, &mut session)?;
+            let request = reserve_seats_request(&session, flight_id, seats, seat_class, quote_id);
+            send(connection, &request, &mut Some(session))
+        }
+        Command::Monitor {
+            login,
+            flight_id,
+            monitor_interval,
+            watch,
+        } => {
+            let session = log_in(connection, &login, &mut session)?;
+            let request =
+                monitor_seat_availability_request(&session, flight_id, monitor_interval, &watch);
+            if !send(connection, &request, &mut Some(session))? {
+                return Ok(false);
+            }
+
+            // Updates are printed by the background thread as they arrive, until the subscription expires.
+            thread::sleep(Duration::from_secs(monitor_interval.into()) + EXPIRY_GRACE);
+            Ok(true)
+        }
+    }
+}
+
+fn parse_command(mut args: Vec<String>) -> Result<Command, Box<dyn Error>> {
+    let command = match args.remove(0).as_str() {
+        "ids" => {
+            let source = require_option(&mut args, "--from")?;
+            let destination = require_option(&mut args, "--to")?;
+            if !source.chars().all(|c| c.is_alphabetic())
+                || !destination.chars().all(|c| c.is_alphabetic())
+            {
+                return Err("Source and destination must be made up of only letters".into());
+            }
+            Command::Anonymous(flight_identifiers_request(&source, &destination))
+        }
+        "summary" => Command::Summary {
+            display_currency: take_option(&mut args, "--currency")?,
+            login: match take_option(&mut args, "--user")? {
+                Some(username) => Some((username, require_option(&mut args, "--password")?)),
+                None => None,
+            },
+            flight_id: take_flight_id(&mut args)?,
+        },
+        "search" => Command::Anonymous(search_flights_request(&take_search_filters(&mut args)?)),
+        "reserve" => Command::Reserve {
+            seats: parse_number(&require_option(&mut args, "--seats")?, "number of seats")?,
+            seat_class: match take_option(&mut args, "--class")? {
+                Some(input) => parse_seat_class(&input)?,
+                None => 0,
+            },
+            quote_id: take_number(&mut args, "--quote", "quote ID")?,
+            login: take_login(&mut args)?,
+            flight_id: take_flight_id(&mut args)?,
+        },
+        "monitor" => {
+            let monitor_interval =
+                parse_number(&require_option(&mut args, "--for")?, "monitor interval")?;
+            if monitor_interval > SECONDS_IN_YEAR {
+                return Err("Monitor interval is too big.".into());
+            }
+            let fields = match take_option(&mut args, "--fields")? {
+                Some(input) => parse_watched_fields(&input)?,
+                None => WATCH_SEATS,
+            };
+            Command::Monitor {
+                watch: Watch {
+                    fields,
+                    seats_below: take_number(&mut args, "--seats-below", "number of seats")?,
+                    baggage_kg_below: take_number(
+                        &mut args,
+                        "--baggage-below",
+                        "baggage capacity",
+                    )?,
+                    airfare_below: take_option(&mut args, "--airfare-below")?
+                        .map(|input| parse_money(&input))
+                        .transpose()?,
+                },
+                login: take_login(&mut args)?,
+                flight_id: take_flight_id(&mut args)?,
+                monitor_interval,
+            }
+        }
+        _ => unreachable!("only commands are run"),
+    };
+
+    // Any arguments left over were not understood.
+    match args.first() {
+        Some(arg) => Err(format!("Unknown argument {}", arg).into()),
+        None => Ok(command),
+    }
+}
+
+// Sends the request until answered and prints the response.
+fn send(
+    connection: &mut Connection,
+    request: &[u8],
+    session: &mut Option<Session>,
+) -> Result<bool, Box<dyn Error>> {
+    let mut receive_buf = [0; 2048];
+    match connection.send_until_answered(request, &mut receive_buf) {
+        Some(received_amt) => Ok(handle_response(
+            &receive_buf[..received_amt],
+            connection,
+            session,
+        )),
+        None => Err("The server did not answer.".into()),
+    }
+}
+
+// Logs in as the user, for commands that act for one.
+fn log_in(
+    connection: &mut Connection,
+    (username, password): &(String, String),
+    session: &mut Option<Session>,
+) -> Result<Session, Box<dyn Error>> {
+    let request = account_request(LOGIN_SERVICE_ID, username, password, None);
+    if !send(connection, &request, session)? {
+        return Err("Could not log in".into());
+    }
+    session.clone().ok_or_else(|| "Could not log in".into())
+}
+
+fn take_search_filters(args: &mut Vec<String>) -> Result<SearchFilters, Box<dyn Error>> {
+    let sort_by = match take_option(args, "--sort")?.as_deref() {
+        None | Some("departure") => 0,
+        Some("airfare") => 1,
+        Some("seats") => 2,
+        Some(_) => return Err("Sort by departure, airfare or seats".into()),
+    };
+    Ok(SearchFilters {
+        source: take_option(args, "--from")?,
+        destination: take_option(args, "--to")?,
+        departure_after: take_option(args, "--after")?
+            .map(|input| convert_datetime_input_to_unix_time(&input))
+            .transpose()?,
+        departure_before: take_option(args, "--before")?
+            .map(|input| convert_datetime_input_to_unix_time(&input))
+            .transpose()?,
+        seat_class: take_option(args, "--class")?
+            .map(|input| parse_seat_class(&input))
+            .transpose()?,
+        min_seats: take_number(args, "--min-seats", "number of seats")?,
+        max_airfare: take_option(args, "--max-airfare")?
+            .map(|input| parse_money(&input))
+            .transpose()?,
+        min_baggage_capacity_kg: take_number(args, "--min-baggage", "baggage capacity")?,
+        sort_by,
+        page_number: take_number(args, "--page", "page number")?.unwrap_or(1),
+        display_currency: take_option(args, "--currency")?.map(|input| input.to_ascii_uppercase()),
+    })
+}
+
+fn take_login(args: &mut Vec<String>) -> Result<(String, String), Box<dyn Error>> {
+    Ok((
+        require_option(args, "--user")?,
+        require_option(args, "--password")?,
+    ))
+}
+
+// The flight ID is the only argument that is not an option, so it is taken after the options.
+fn take_flight_id(args: &mut Vec<String>) -> Result<u32, Box<dyn Error>> {
+    if args.is_empty() || args[0].starts_with("--") {
+        return Err("The command needs a flight ID".into());
+    }
+    parse_number(&args.remove(0), "flight identifier")
+}
+
+fn take_number(
+    args: &mut Vec<String>,
+    name: &str,
+    what: &str,
+) -> Result<Option<u32>, Box<dyn Error>> {
+    take_option(args, name)?
+        .map(|input| parse_number(&input, what))
+        .transpose()
+}
+
+fn require_option(args: &mut Vec<String>, name: &str) -> Result<String, Box<dyn Error>> {
+    take_option(args, name)?.ok_or_else(|| format!("The command needs {}", name).into())
+}
+
+fn parse_number(input: &str, what: &str) -> Result<u32, Box<dyn Error>> {
+    input
+        .parse::<u32>()
+        .map_err(|_| format!("Invalid {}", what).into())
+}
@@ -0,0 +1,165 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use marshaling::Money;
+
+// With --output json, every response, error and event is printed as one JSON object per line instead of text.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// A JSON object, written one field at a time. Objects that are printed start with their type, e.g.
+// {"type":"flight_ids","flight_ids":[1,2]}.
+pub struct Object {
+    json: String,
+}
+
+impl Object {
+    pub fn new(kind: &str) -> Object {
+        Object::nested().string("type", kind)
+    }
+
+    // An object inside another, which has no type.
+    pub fn nested() -> Object {
+        Object {
+            json: String::from("{"),
+        }
+    }
+
+    pub fn string(self, key: &str, value: &str) -> Object {
+        self.value(key, &quote(value))
+    }
+
+    pub fn optional_string(self, key: &str, value: Option<&str>) -> Object {
+        match value {
+            Some(value) => self.string(key, value),
+            None => self.value(key, "null"),
+        }
+    }
+
+    pub fn number(self, key: &str, value: impl Into<u64>) -> Object {
+        self.value(key, &value.into().to_string())
+    }
+
+    pub fn optional_number(self, key: &str, value: Option<impl Into<u64>>) -> Object {
+        match value {
+            Some(value) => self.number(key, value),
+            None => self.value(key, "null"),
+        }
+    }
+
+    pub fn bool(self, key: &str, value: bool) -> Object {
+        self.value(key, if value { "true" } else { "false" })
+    }
+
+    // The amount is a decimal string, so that it stays exact, e.g. {"amount":"10.10","currency":"SGD"}.
+    pub fn money(self, key: &str, money: Money) -> Object {
+        let amount = money.to_string();
+        let amount = amount.split(' ').next().unwrap_or_default();
+        let money = Object::nested()
+            .string("amount", amount)
+            .string("currency", money.currency.as_str());
+        self.object(key, money)
+    }
+
+    pub fn object(self, key: &str, object: Object) -> Object {
+        self.value(key, &object.finish())
+    }
+
+    pub fn numbers(self, key: &str, values: &[u32]) -> Object {
+        let values: Vec<String> = values.iter().map(u32::to_string).collect();
+        self.value(key, &format!("[{}]", values.join(",")))
+    }
+
+    pub fn strings(self, key: &str, values: &[String]) -> Object {
+        let values: Vec<String> = values.iter().map(|value| quote(value)).collect();
+        self.value(key, &format!("[{}]", values.join(",")))
+    }
+
+    pub fn objects(self, key: &str, objects: Vec<Object>) -> Object {
+        let objects: Vec<String> = objects.into_iter().map(Object::finish).collect();
+        self.value(key, &format!("[{}]", objects.join(",")))
+    }
+
+    fn value(mut self, key: &str, value: &str) -> Object {
+        if self.json.len() > 1 {
+            self.json.push(',');
+        }
+        self.json.push_str(&quote(key));
+        self.json.push(':');
+        self.json.push_str(value);
+        self
+    }
+
+    pub fn finish(mut self) -> String {
+        self.json.push('}');
+        self.json
+    }
+
+    // Prints the object on its own line.
+    pub fn print(self) {
+        println!("{}", self.finish());
+    }
+}
+
+// Quotes the string, escaping the characters JSON does not allow in strings.
+fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_and_backslashes_are_escaped() {
+        assert_eq!(quote(""), r#""""#);
+        assert_eq!(quote(r#"say "hi""#), r#""say \"hi\"""#);
+        assert_eq!(quote(r"C:\temp\"), r#""C:\\temp\\""#);
+        assert_eq!(quote(r#"\""#), r#""\\\"""#);
+    }
+
+    #[test]
+    fn control_characters_are_escaped() {
+        assert_eq!(quote("a\nb\rc\td"), r#""a\nb\rc\td""#);
+        assert_eq!(
+            quote("\u{0}\u{1}\u{1b}\u{1f}"),
+            r#""\u0000\u0001\u001b\u001f""#
+        );
+        // Everything else, including non-ASCII, is allowed in JSON strings as it is.
+        assert_eq!(quote(" ~\u{7f}José 東京"), "\" ~\u{7f}José 東京\"");
+    }
+
+    #[test]
+    fn keys_and_values_are_quoted_in_objects() {
+        let json = Object::new("a \"b\"")
+            .string("c\\d", "e\nf")
+            .strings("g", &["h\"".to_string()])
+            .optional_string("i", None)
+            .number("j", 1u32)
+            .finish();
+        assert_eq!(
+            json,
+            r#"{"type":"a \"b\"","c\\d":"e\nf","g":["h\""],"i":null,"j":1}"#
+        );
+    }
+}
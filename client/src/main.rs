@@ -1,28 +1,191 @@
 use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use std::collections::{BTreeSet, HashMap};
 use std::error::Error;
 use std::io::{self, BufRead, Lines, StdinLock};
-use std::net::{SocketAddr, UdpSocket};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
 use std::time::{Duration, Instant};
 
 use marshaling::{
-    self, marshal_string, marshal_u32, marshal_u8, unmarshal_f32, unmarshal_string, unmarshal_u32,
-    unmarshal_u32_array, unmarshal_u8,
+    self, marshal_money, marshal_optional_money, marshal_optional_string, marshal_optional_u32,
+    marshal_optional_u8, marshal_string, marshal_u32, marshal_u32_array, marshal_u8,
+    unmarshal_long_string, unmarshal_string, unmarshal_u32, unmarshal_u32_array, unmarshal_u8,
+    Currency, Money,
 };
-use networking;
+
+mod cli;
+mod json;
 
 const DEFAULT_TIMEOUT: u32 = 3;
 
-fn main() -> std::io::Result<()> {
-    let server_addr = SocketAddr::from(([127, 0, 0, 1], 7878));
+// How often the background thread checks for resyncs to resend while waiting for datagrams.
+const RESYNC_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// The status byte of a response refusing a request because the user's role may not use the service.
+const FORBIDDEN: u8 = 255;
+
+// A logged in user, and the traveller they are acting for if they are an agent.
+#[derive(Clone)]
+struct Session {
+    token: String,
+    acting_for: Option<String>,
+}
+
+// The fields a subscription can watch, as bits. Updates use the same bits to say which fields changed.
+const WATCH_SEATS: u8 = 1;
+const WATCH_BAGGAGE: u8 = 2;
+const WATCH_AIRFARE: u8 = 4;
+const WATCH_STATUS: u8 = 8;
+const WATCHED_FIELDS: [(u8, &str); 4] = [
+    (WATCH_SEATS, "seats"),
+    (WATCH_BAGGAGE, "baggage"),
+    (WATCH_AIRFARE, "airfare"),
+    (WATCH_STATUS, "status"),
+];
+
+const SEAT_CLASSES: [(u8, &str); 3] = [(0, "economy"), (1, "premium"), (2, "business")];
+const SEAT_CLASS_PROMPT: &str =
+    "Enter seat class: 0 = economy (default), 1 = premium, 2 = business";
+
+fn main() -> std::io::Result<ExitCode> {
+    const USAGE: &str = "Usage: cargo run --package client [--plaintext] [--server host:port] [--timeout seconds] [--retries count] [--output text|json] [key_file key_id] [command [arguments]]";
+    let mut args: Vec<String> = std::env::args().collect();
+
+    // Requests and responses are encrypted unless --plaintext is given, which is easier to debug.
+    let plaintext = args.iter().any(|arg| arg == "--plaintext");
+    args.retain(|arg| arg != "--plaintext");
+
+    // Where the server is, how long to wait for each response and how many times to resend a request that is
+    // not answered. Requests are resent until answered unless --retries is given.
+    let options = match (
+        cli::take_option(&mut args, "--server"),
+        cli::take_option(&mut args, "--timeout"),
+        cli::take_option(&mut args, "--retries"),
+        cli::take_option(&mut args, "--output"),
+    ) {
+        (Ok(server), Ok(timeout), Ok(retries), Ok(output)) => (server, timeout, retries, output),
+        _ => {
+            println!("{USAGE}");
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+
+    // With --output json, responses, errors and events are printed as one JSON object per line for scripts.
+    match options.3.as_deref() {
+        None | Some("text") => {}
+        Some("json") => json::enable(),
+        Some(_) => {
+            println!("{USAGE}");
+            return Ok(ExitCode::FAILURE);
+        }
+    }
+
+    let server_addr = match options
+        .0
+        .as_deref()
+        .unwrap_or("127.0.0.1:7878")
+        .to_socket_addrs()
+    {
+        Ok(mut addrs) => match addrs.next() {
+            Some(server_addr) => server_addr,
+            None => {
+                print_error("The server address does not resolve to any address.");
+                return Ok(ExitCode::FAILURE);
+            }
+        },
+        Err(e) => {
+            print_error(&format!("Invalid server address: {e}"));
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+    let timeout = match options.1.map(|timeout| timeout.parse::<u32>()) {
+        None => DEFAULT_TIMEOUT,
+        Some(Ok(timeout)) if timeout > 0 => timeout,
+        Some(_) => {
+            print_error("The timeout must be a whole number of seconds, at least 1.");
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+    let max_attempts = match options.2.map(|retries| retries.parse::<u32>()) {
+        None => None,
+        Some(Ok(retries)) => Some(retries.saturating_add(1)),
+        Some(Err(_)) => {
+            print_error("The number of retries must be a whole number.");
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+
+    // A command runs one service and exits instead of showing the menu, so that scripts can use the client.
+    // It comes after the key file and key ID, if any.
+    let command_at = if args.get(1).is_some_and(|arg| cli::is_command(arg)) {
+        1
+    } else {
+        args.len().min(3)
+    };
+    let command_args = args.split_off(command_at);
+    let interactive = command_args.is_empty();
+
+    // Only the menu shows how the client is connected, and only as text.
+    let show_banners = interactive && !json::enabled();
+    networking::set_logging(show_banners);
+
+    // With a key file and a key ID, every datagram is signed with that key, as a server with the same key
+    // file requires.
+    match (args.get(1), args.get(2)) {
+        (Some(key_file), Some(key_id)) => {
+            let key = match networking::load_key_file(key_file) {
+                Ok(mut keys) => match keys.remove(key_id) {
+                    Some(key) => key,
+                    None => {
+                        print_error(&format!("The key file {key_file} has no key {key_id}."));
+                        return Ok(ExitCode::FAILURE);
+                    }
+                },
+                Err(error_message) => {
+                    print_error(&error_message);
+                    return Ok(ExitCode::FAILURE);
+                }
+            };
+            networking::enable_authentication(networking::Authenticator::for_client(key_id, key));
+            if show_banners {
+                println!("-- Datagrams are authenticated with the key {key_id}");
+            }
+        }
+        (None, None) => {}
+        _ => {
+            println!("{USAGE}");
+            return Ok(ExitCode::FAILURE);
+        }
+    }
+
     let socket = UdpSocket::bind("0.0.0.0:0")?; // Bind to any available port
-    println!("-- Client is listening on port: {}", socket.local_addr().unwrap().port());
+    if show_banners {
+        println!(
+            "-- Client is listening on port: {}",
+            socket.local_addr().unwrap().port()
+        );
+    }
     socket
-        .set_read_timeout(Some(Duration::new(3, 0)))
+        .set_read_timeout(Some(Duration::new(timeout.into(), 0)))
         .expect("Failed to set read timeout");
     socket
-        .connect(&server_addr)
+        .connect(server_addr)
         .expect("couldn't connect to address");
 
+    if !plaintext {
+        networking::start_encrypted_session(&socket, &server_addr)?;
+    }
+    if show_banners {
+        if plaintext {
+            println!("-- Requests and responses are sent in plaintext");
+        } else {
+            println!("-- Requests and responses are encrypted");
+        }
+    }
+
     let stdin = io::stdin();
 
     // Acquire a lock on stdin
@@ -34,8 +197,22 @@ fn main() -> std::io::Result<()> {
     // Create a buffer to store the data received from the server
     let mut receive_buf = [0; 2048];
 
-    // Counter to keep track of the number of requests sent
-    let mut request_id: u32 = 0;
+    // Keep track of the number of requests sent, and print callbacks in the background as they arrive
+    let mut connection = Connection::new(socket, server_addr, timeout, max_attempts)?;
+
+    if !interactive {
+        return match cli::run(command_args, &mut connection) {
+            Ok(true) => Ok(ExitCode::SUCCESS),
+            Ok(false) => Ok(ExitCode::FAILURE),
+            Err(e) => {
+                print_error(&e.to_string());
+                Ok(ExitCode::FAILURE)
+            }
+        };
+    }
+
+    // The session from logging in, sent with the services that act for a user
+    let mut session: Option<Session> = None;
 
     loop {
         // reset buf
@@ -54,9 +231,27 @@ fn main() -> std::io::Result<()> {
         print_padded_string("Additional Services");
         println!("5. Get Earliest Flight Identifiers");
         println!("6. Reserve Baggage");
+        println!("7. Search Flights");
+        println!("8. Get Next Departures");
+        println!("12. Get Seat Map");
+        println!("13. Reserve Specific Seats");
+        println!("14. Join Waitlist");
+        println!("15. Cancel Booking");
+        println!("16. Add Passenger Details");
+        println!("17. Check In");
+        println!("26. Monitor Route");
+        print_padded_string("Account Services");
+        println!("18. Register");
+        println!("19. Log In");
+        println!("20. My Bookings");
+        println!("22. Act For Traveller (agents)");
+        println!("23. Stop Monitoring");
+        println!("24. My Subscriptions");
+        print_padded_string("Admin Services");
+        println!("9. Manage Flights");
+        println!("21. Set User Role");
         print_padded_string("Danger Zone");
-        println!("7. Exit");
-
+        println!("0. Exit");
 
         // Read input from stdin and interpret it as a u32
         let service_choice = lines
@@ -72,55 +267,172 @@ fn main() -> std::io::Result<()> {
                 continue;
             }
         };
-                       
 
         // Match the service choice to the appropriate service
         // Each service will return a byte array that will be sent to the server. Size is at most 2048 bytes.
-        let mut time_out_duration = DEFAULT_TIMEOUT;
         let buffer_to_send: Vec<u8> = match service_choice {
             1 => match prepare_get_flight_identifiers(&mut lines) {
                 Ok(buffer) => buffer,
                 Err(e) => {
-                    println!("Error: {}", e);
+                    print_error(&e.to_string());
                     continue;
                 }
             },
-            2 => match prepare_get_flight_summary(&mut lines) {
+            2 => match prepare_get_flight_summary(&mut lines, session.as_ref()) {
                 Ok(buffer) => buffer,
                 Err(e) => {
-                    println!("Error: {}", e);
+                    print_error(&e.to_string());
                     continue;
                 }
             },
-            3 => match prepare_reserve_seats(&mut lines) {
+            3 => match prepare_reserve_seats(&mut lines, session.as_ref()) {
                 Ok(buffer) => buffer,
                 Err(e) => {
-                    println!("Error: {}", e);
+                    print_error(&e.to_string());
                     continue;
                 }
             },
-            4 => match prepare_monitor_seat_availability(&mut lines, &mut time_out_duration) {
+            4 => match prepare_monitor_seat_availability(&mut lines, session.as_ref()) {
                 Ok(buffer) => buffer,
                 Err(e) => {
-                    println!("Error: {}", e);
+                    print_error(&e.to_string());
                     continue;
                 }
             },
             5 => match prepare_earliest_flight_ids(&mut lines) {
                 Ok(buffer) => buffer,
                 Err(e) => {
-                    println!("Error: {}", e);
+                    print_error(&e.to_string());
+                    continue;
+                }
+            },
+            6 => match prepare_reserve_baggage(&mut lines, session.as_ref()) {
+                Ok(buffer) => buffer,
+                Err(e) => {
+                    print_error(&e.to_string());
+                    continue;
+                }
+            },
+            7 => match prepare_search_flights(&mut lines) {
+                Ok(buffer) => buffer,
+                Err(e) => {
+                    print_error(&e.to_string());
+                    continue;
+                }
+            },
+            8 => match prepare_next_departures(&mut lines) {
+                Ok(buffer) => buffer,
+                Err(e) => {
+                    print_error(&e.to_string());
+                    continue;
+                }
+            },
+            9 => match prepare_admin(&mut lines, session.as_ref()) {
+                Ok(buffer) => buffer,
+                Err(e) => {
+                    print_error(&e.to_string());
+                    continue;
+                }
+            },
+            12 => match prepare_get_seat_map(&mut lines) {
+                Ok(buffer) => buffer,
+                Err(e) => {
+                    print_error(&e.to_string());
+                    continue;
+                }
+            },
+            13 => match prepare_reserve_specific_seats(&mut lines, session.as_ref()) {
+                Ok(buffer) => buffer,
+                Err(e) => {
+                    print_error(&e.to_string());
+                    continue;
+                }
+            },
+            14 => match prepare_join_waitlist(&mut lines, session.as_ref()) {
+                Ok(buffer) => buffer,
+                Err(e) => {
+                    print_error(&e.to_string());
+                    continue;
+                }
+            },
+            15 => match prepare_cancel_booking(&mut lines, session.as_ref()) {
+                Ok(buffer) => buffer,
+                Err(e) => {
+                    print_error(&e.to_string());
+                    continue;
+                }
+            },
+            16 => match prepare_set_passenger(&mut lines, session.as_ref()) {
+                Ok(buffer) => buffer,
+                Err(e) => {
+                    print_error(&e.to_string());
+                    continue;
+                }
+            },
+            17 => match prepare_check_in(&mut lines, session.as_ref()) {
+                Ok(buffer) => buffer,
+                Err(e) => {
+                    print_error(&e.to_string());
+                    continue;
+                }
+            },
+            18 => match prepare_account(&mut lines, 18) {
+                Ok(buffer) => buffer,
+                Err(e) => {
+                    print_error(&e.to_string());
+                    continue;
+                }
+            },
+            19 => match prepare_account(&mut lines, 19) {
+                Ok(buffer) => buffer,
+                Err(e) => {
+                    print_error(&e.to_string());
+                    continue;
+                }
+            },
+            20 => match prepare_list_bookings(&mut lines, session.as_ref()) {
+                Ok(buffer) => buffer,
+                Err(e) => {
+                    print_error(&e.to_string());
+                    continue;
+                }
+            },
+            21 => match prepare_set_role(&mut lines, session.as_ref()) {
+                Ok(buffer) => buffer,
+                Err(e) => {
+                    print_error(&e.to_string());
+                    continue;
+                }
+            },
+            22 => {
+                // Only changes the requests that follow, so there is nothing to send.
+                if let Err(e) = choose_acting_for(&mut lines, session.as_mut()) {
+                    print_error(&e.to_string());
+                }
+                continue;
+            }
+            23 => match prepare_unsubscribe(&mut lines, session.as_ref()) {
+                Ok(buffer) => buffer,
+                Err(e) => {
+                    print_error(&e.to_string());
+                    continue;
+                }
+            },
+            24 => match prepare_list_subscriptions(session.as_ref()) {
+                Ok(buffer) => buffer,
+                Err(e) => {
+                    print_error(&e.to_string());
                     continue;
                 }
             },
-            6 => match prepare_reserve_baggage(&mut lines) {
+            26 => match prepare_monitor_route(&mut lines, session.as_ref()) {
                 Ok(buffer) => buffer,
                 Err(e) => {
-                    println!("Error: {}", e);
+                    print_error(&e.to_string());
                     continue;
                 }
             },
-            7 => {
+            0 => {
                 // Exit the program
                 break;
             }
@@ -133,206 +445,2153 @@ fn main() -> std::io::Result<()> {
             }
         };
 
-        // Increment the request ID
-        request_id += 1;
-        let mut received_amt = 0;
-
-        while received_amt == 0 {
-            // Send the buffer to the communication service which will handle communication with the server. Specify the request ID, the buffer to send and the socket.
-            networking::send_request(request_id, buffer_to_send.clone(), &socket, &server_addr);
-            match socket.recv_from(&mut receive_buf) {
-                Ok((amt, _)) => {
-                    received_amt = amt;
-                }
-                Err(e) => {
-                    match e.kind() {
-                        io::ErrorKind::TimedOut => {
-                            println!("[client] Client timed out waiting for a response!");
-                        }
-                        _ => {
-                            println!("Error: {}", e);
-                        }
-                    }
-                    continue;
-                }
-            };
+        match connection.send_until_answered(&buffer_to_send, &mut receive_buf) {
+            Some(received_amt) => {
+                handle_response(&receive_buf[..received_amt], &connection, &mut session);
+            }
+            None => print_error("The server did not answer."),
         }
+    }
 
-        let i: usize = 0;
-        let (received_request_id, i) = unmarshal_u32(&receive_buf, i);
-        // Check if request ID is the same as the one we sent, if not, keep waiting.
-        if received_request_id != request_id {
-            println!("Request ID not the same as the one we sent. Continuing to wait.");
-            continue;
+    Ok(ExitCode::SUCCESS)
+}
+
+// Prints the response to a request, keeping the session from logging in and the subscriptions made. Returns
+// false if the server refused the request.
+fn handle_response(buf: &[u8], connection: &Connection, session: &mut Option<Session>) -> bool {
+    // Skip the request ID, which receive_response has already checked.
+    let i: usize = 4;
+
+    // Check next byte and call specific handler
+    let (handler_byte, i) = unmarshal_u8(buf, i);
+    match handler_byte {
+        0 => {
+            // Next bytes will be a string which is the error message.
+            let (error_message, _) = unmarshal_string(buf, i);
+            print_error(&error_message);
+            return false;
+        }
+        1 => {
+            parse_get_flight_identifiers_response(&buf[i..]);
         }
+        2 => {
+            parse_get_flight_summary_response(&buf[i..]);
+        }
+        3 => parse_reserve_seats_response(&buf[i..]),
+        4 | 26 => {
+            let subscription_id = if handler_byte == 4 {
+                parse_monitor_seat_availability_response(&buf[i..])
+            } else {
+                parse_monitor_route_response(&buf[i..])
+            };
 
-        // Check next byte and call specific handler
-        let (handler_byte, i) = unmarshal_u8(&receive_buf, i);
-        match handler_byte {
-            0 => {
-                // Next bytes will be a string which is the error message.
-                let (error_message, _) = unmarshal_string(&receive_buf, i);
-                println!("Error: {}", error_message);
+            // Monitoring needs a session, so there is one.
+            if let (Some(subscription_id), Some(session)) = (subscription_id, &session) {
+                connection.track(subscription_id, session);
             }
-            1 => {
-                parse_get_flight_identifiers_response(&receive_buf[i..received_amt]);
-            }
-            2 => {
-                parse_get_flight_summary_response(&receive_buf[i..received_amt]);
+        }
+        5 => parse_get_earliest_flight_ids_response(&buf[i..]),
+        6 => parse_reserve_baggage_response(&buf[i..]),
+        7 => parse_search_flights_response(&buf[i..]),
+        8 => parse_get_next_departures_response(&buf[i..]),
+        9 => parse_admin_response(&buf[i..]),
+        12 => parse_get_seat_map_response(&buf[i..]),
+        13 => parse_reserve_seats_response(&buf[i..]),
+        14 => parse_join_waitlist_response(&buf[i..]),
+        15 => parse_cancel_booking_response(&buf[i..]),
+        16 => parse_set_passenger_response(&buf[i..]),
+        17 => parse_check_in_response(&buf[i..]),
+        18 => parse_register_response(&buf[i..]),
+        19 => *session = Some(parse_login_response(&buf[i..])),
+        20 => parse_list_bookings_response(&buf[i..]),
+        21 => parse_set_role_response(&buf[i..]),
+        23 => {
+            let subscription_id = parse_unsubscribe_response(&buf[i..]);
+            connection.untrack(subscription_id);
+        }
+        24 => parse_list_subscriptions_response(&buf[i..]),
+        FORBIDDEN => {
+            // Next bytes will be a string which says what the role may not do.
+            let (error_message, _) = unmarshal_string(buf, i);
+            if json::enabled() {
+                json::Object::new("forbidden")
+                    .string("message", &error_message)
+                    .print();
+            } else {
+                println!("Forbidden: {}", error_message);
             }
-            3 => parse_reserve_seats_response(&receive_buf[i..received_amt]),
-            4 => {
-                parse_monitor_seat_availability_response(
-                    &receive_buf[i..received_amt],
-                    time_out_duration,
-                    &socket,
-                );
+            return false;
+        }
+        _ => {
+            print_error("Invalid handler byte");
+            return false;
+        }
+    }
+    true
+}
+
+// The socket to the server and the ID of the last request sent on it. Datagrams from the server are received
+// on a background thread, which prints callbacks and passes responses on.
+struct Connection {
+    socket: UdpSocket,
+    server_addr: SocketAddr,
+    request_id: Arc<AtomicU32>,
+    responses: mpsc::Receiver<Vec<u8>>,
+    // How long to wait for each response, and how many times to send a request before giving up, if ever.
+    timeout: Duration,
+    max_attempts: Option<u32>,
+    // The session each subscription monitored from this client was made with, to resync it after missed
+    // updates.
+    monitors: Arc<Mutex<HashMap<u32, Session>>>,
+}
+
+impl Connection {
+    // Starts receiving from the server in the background.
+    fn new(
+        socket: UdpSocket,
+        server_addr: SocketAddr,
+        timeout_seconds: u32,
+        max_attempts: Option<u32>,
+    ) -> io::Result<Connection> {
+        let request_id = Arc::new(AtomicU32::new(0));
+        let monitors = Arc::new(Mutex::new(HashMap::new()));
+        let (response_sender, responses) = mpsc::channel();
+
+        let receiver = CallbackReceiver {
+            socket: socket.try_clone()?,
+            server_addr,
+            request_id: Arc::clone(&request_id),
+            monitors: Arc::clone(&monitors),
+            monitor_updates: MonitorUpdates::new(),
+            pending_resyncs: HashMap::new(),
+        };
+        thread::spawn(move || receiver.run(response_sender));
+
+        Ok(Connection {
+            socket,
+            server_addr,
+            request_id,
+            responses,
+            timeout: Duration::from_secs(timeout_seconds.into()),
+            max_attempts,
+            monitors,
+        })
+    }
+
+    // Sends the request with the next request ID until the server answers it, and returns the length of the
+    // response. Returns None if the server did not answer any of the attempts.
+    fn send_until_answered(
+        &mut self,
+        buffer_to_send: &[u8],
+        receive_buf: &mut [u8],
+    ) -> Option<usize> {
+        // Increment the request ID
+        let request_id = self.request_id.fetch_add(1, Ordering::SeqCst) + 1;
+        let mut num_attempts = 0;
+        while self
+            .max_attempts
+            .is_none_or(|max_attempts| num_attempts < max_attempts)
+        {
+            num_attempts += 1;
+            // Send the buffer to the communication service which will handle communication with the server. Specify the request ID, the buffer to send and the socket.
+            networking::send_request(
+                request_id,
+                buffer_to_send.to_vec(),
+                &self.socket,
+                &self.server_addr,
+            );
+            match self.receive_response(request_id) {
+                Some(response) => {
+                    receive_buf[..response.len()].copy_from_slice(&response);
+                    return Some(response.len());
+                }
+                None => eprintln!("[client] Client timed out waiting for a response!"),
             }
-            5 => parse_get_earliest_flight_ids_response(&receive_buf[i..received_amt]),
-            6 => parse_reserve_baggage_response(&receive_buf[i..received_amt]),
-            _ => {
-                println!("Invalid handler byte");
+        }
+        None
+    }
+
+    // Waits for the response to the request. Responses to earlier requests are dropped, so that they are not
+    // mistaken for the response.
+    fn receive_response(&self, request_id: u32) -> Option<Vec<u8>> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let response = self.responses.recv_timeout(timeout).ok()?;
+            if unmarshal_u32(&response, 0).0 == request_id {
+                return Some(response);
             }
+            eprintln!("Request ID not the same as the one we sent. Continuing to wait.");
         }
     }
 
-    Ok(())
-}
+    // Starts resyncing the subscription with the session when it misses updates.
+    fn track(&self, subscription_id: u32, session: &Session) {
+        self.monitors
+            .lock()
+            .unwrap()
+            .insert(subscription_id, session.clone());
+    }
 
-fn parse_get_flight_identifiers_response(buf: &[u8]) {
-    let (flight_ids, _) = unmarshal_u32_array(buf, 0);
-    println!("Flight IDs: {:#?}", flight_ids);
+    fn untrack(&self, subscription_id: u32) {
+        self.monitors.lock().unwrap().remove(&subscription_id);
+    }
 }
 
-fn parse_get_flight_summary_response(buf: &[u8]) {
-    let (departure_time, i) = unmarshal_u32(buf, 0);
-    let (airfare, i) = unmarshal_f32(buf, i);
-    let (seats, i) = unmarshal_u32(buf, i);
-    let (remaining_baggage_capacity_kg, _) = unmarshal_u32(buf, i);
-    println!(
-        "Departure time: {}",
-        convert_unix_time_to_datetime(departure_time).to_string()
-    );
-    println!("Airfare: {}", airfare);
-    println!("Seats: {}", seats);
-    println!("Remaining baggage capacity: {} kg", remaining_baggage_capacity_kg);
+// A resync request sent from the background thread, waiting for its response.
+struct PendingResync {
+    subscription_id: u32,
+    request: Vec<u8>,
+    resend_at: Instant,
 }
 
-fn parse_reserve_seats_response(buf: &[u8]) {
-    let (has_succeeded, _) = unmarshal_u8(buf, 0);
-    if has_succeeded == 1 {
-        println!("Reservation succeeded");
-    } else {
-        // This should not be reachable because any error will be already caught by the handler byte being 0.
-        println!("Reservation failed");
-    }
+// Receives every datagram from the server on the background thread. Callbacks are printed as they arrive,
+// while the menu waits for input, and responses are passed on to the menu, except those to resyncs.
+struct CallbackReceiver {
+    socket: UdpSocket,
+    server_addr: SocketAddr,
+    // Shared with the menu, so that resyncs and the menu's requests have different request IDs.
+    request_id: Arc<AtomicU32>,
+    monitors: Arc<Mutex<HashMap<u32, Session>>>,
+    monitor_updates: MonitorUpdates,
+    // By request ID.
+    pending_resyncs: HashMap<u32, PendingResync>,
 }
 
-fn parse_monitor_seat_availability_response(buf: &[u8], monitor_interval: u32, socket: &UdpSocket) {
-    let (has_succeeded, _) = unmarshal_u8(buf, 0);
-    if has_succeeded == 1 {
-        // Only after the subscription has succeeded, we can set the read timeout and continue waiting for the next message.
-        println!("Subscription succeeded. Now listening for {monitor_interval} seconds...");
+impl CallbackReceiver {
+    // Receives until the menu exits, waking up regularly to resend resyncs that were not answered.
+    fn run(mut self, responses: mpsc::Sender<Vec<u8>>) {
+        self.socket
+            .set_read_timeout(Some(RESYNC_CHECK_INTERVAL))
+            .expect("Failed to set read timeout");
         let mut receive_buf = [0; 2048];
-
-        // Loop until monitor_interval duration has passed.
-        let start_time = Instant::now();
-        while start_time.elapsed().as_secs() < monitor_interval.into() {
-            match socket.recv_from(&mut receive_buf) {
-                Ok(_) => {
-                    // Buffer should have the following format:
-                    // Note: There is no Request ID because this is a subscription response.
-                    // 1 Byte: Handler byte, should be equal to 4.
-                    // 4 Byte: Flight ID
-                    // 4 Byte: Num_seats
-
-                    let (handler_byte, i) = unmarshal_u8(&receive_buf, 0);
-                    if handler_byte != 4 {
-                        println!("Invalid handler byte");
+        loop {
+            match networking::receive(&self.socket, &mut receive_buf) {
+                Ok((amt, _)) => {
+                    // Callbacks carry the reserved callback request ID instead of a real one.
+                    let (received_request_id, i) = unmarshal_u32(&receive_buf, 0);
+                    if received_request_id == networking::CALLBACK_REQUEST_ID {
+                        self.handle_callback(&receive_buf[i..amt]);
+                    } else if let Some(pending) = self.pending_resyncs.remove(&received_request_id)
+                    {
+                        self.handle_resync_response(pending.subscription_id, &receive_buf[..amt]);
+                    } else if responses.send(receive_buf[..amt].to_vec()).is_err() {
+                        // The menu has exited.
                         return;
                     }
+                }
+                // The read timed out. Missed updates are still resynced below while the server is quiet.
+                Err(error)
+                    if matches!(
+                        error.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) => {}
+                // Errors such as an unreachable server only fail this read, so keep receiving.
+                Err(error) => eprintln!("[client] Failed to receive from the server: {error}"),
+            }
+            self.resync_missed();
+            self.resend_due_resyncs();
+        }
+    }
 
-                    let (flight_id, i) = unmarshal_u32(&receive_buf, i);
-
-                    let (num_seats, _) = unmarshal_u32(&receive_buf, i);
-
-                    println!("EVENT: Flight {} has {} seats left", flight_id, num_seats);
+    // Prints a callback pushed by the server, acknowledging monitor updates so that the server stops resending
+    // them. Repeated updates are only acknowledged.
+    fn handle_callback(&mut self, buf: &[u8]) {
+        let (handler_byte, i) = unmarshal_u8(buf, 0);
+        match handler_byte {
+            // 4 Byte: Subscription ID
+            // 4 Byte: Sequence number
+            // Then the update
+            4 => {
+                let (subscription_id, i) = unmarshal_u32(buf, i);
+                let (sequence, i) = unmarshal_u32(buf, i);
+                self.acknowledge(subscription_id, sequence);
+                if self.monitor_updates.record(subscription_id, sequence) {
+                    print_monitor_update("flight_update", subscription_id, sequence, buf, i);
                 }
-                Err(_) => {
-                    // dbg!("Timed out waiting for response from the server");
+            }
+            // 4 Byte: Subscription ID
+            // 4 Byte: Sequence number
+            // Then the route and the flight on it
+            26 => {
+                let (subscription_id, i) = unmarshal_u32(buf, i);
+                let (sequence, i) = unmarshal_u32(buf, i);
+                self.acknowledge(subscription_id, sequence);
+                if self.monitor_updates.record(subscription_id, sequence) {
+                    print_route_update(subscription_id, sequence, buf, i);
                 }
-            };
+            }
+            10 => parse_flight_changed_callback(&buf[i..]),
+            11 => parse_status_changed_callback(&buf[i..]),
+            14 => parse_waitlist_booked_callback(&buf[i..]),
+            // 4 Byte: Subscription ID
+            // Then the flight or route it was for
+            27 => {
+                let (subscription_id, i) = unmarshal_u32(buf, i);
+                let (target, _) = unmarshal_target(buf, i);
+                if json::enabled() {
+                    json::Object::new("subscription_expired")
+                        .number("subscription_id", subscription_id)
+                        .object("target", target.to_json())
+                        .print();
+                } else {
+                    println!("EVENT: Subscription {subscription_id} to {target} has expired.");
+                }
+                self.forget(subscription_id);
+            }
+            _ => print_error("Invalid handler byte"),
         }
-        println!("Monitor interval ended");
-    } else {
-        // This should not be reachable because any error will be already caught by the handler byte being 0.
-        println!("Subscription failed");
     }
-}
 
-fn parse_get_earliest_flight_ids_response(buf: &[u8]) {
-    let (flight_ids, _) = unmarshal_u32_array(buf, 0);
+    // Tells the server that a monitor update arrived. Acknowledgements carry the callback request ID, and the
+    // server does not respond to them.
+    fn acknowledge(&self, subscription_id: u32, sequence: u32) {
+        // Create a buffer to store the data to send with capacity 2048 bytes
+        let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+        // Add subscription ID and sequence number
+        marshal_u32(subscription_id, &mut buffer_to_send);
+        marshal_u32(sequence, &mut buffer_to_send);
+
+        networking::send_request(
+            networking::CALLBACK_REQUEST_ID,
+            buffer_to_send,
+            &self.socket,
+            &self.server_addr,
+        );
+    }
 
-    if flight_ids.len() == 0 {
-        println!("No flights found for the given source.");
-    } else {
-        println!("Flight IDs: {:#?}", flight_ids);
+    // Asks the server for the current values of each subscription that missed updates, with the session it
+    // was made with.
+    fn resync_missed(&mut self) {
+        while let Some(subscription_id) = self.monitor_updates.missed.pop_first() {
+            let session = match self.monitors.lock().unwrap().get(&subscription_id) {
+                Some(session) => session.clone(),
+                // Subscriptions made from another client are not resynced here.
+                None => continue,
+            };
+            if !json::enabled() {
+                println!("[client] Missed updates of subscription {subscription_id}, asking for the current values");
+            }
+
+            let request_id = self.request_id.fetch_add(1, Ordering::SeqCst) + 1;
+            let request = resync_request(&session, subscription_id);
+            networking::send_request(request_id, request.clone(), &self.socket, &self.server_addr);
+            self.pending_resyncs.insert(
+                request_id,
+                PendingResync {
+                    subscription_id,
+                    request,
+                    resend_at: Instant::now() + Duration::from_secs(DEFAULT_TIMEOUT.into()),
+                },
+            );
+        }
     }
 
-}
+    // Sends the resyncs again that have not been answered in time.
+    fn resend_due_resyncs(&mut self) {
+        let now = Instant::now();
+        for (request_id, pending) in self.pending_resyncs.iter_mut() {
+            if pending.resend_at <= now {
+                networking::send_request(
+                    *request_id,
+                    pending.request.clone(),
+                    &self.socket,
+                    &self.server_addr,
+                );
+                pending.resend_at = now + Duration::from_secs(DEFAULT_TIMEOUT.into());
+            }
+        }
+    }
 
-fn parse_reserve_baggage_response(buf: &[u8]) {
-    let (has_succeeded, _) = unmarshal_u8(buf, 0);
-    if has_succeeded == 1 {
-        println!("Reservation of baggage succeeded");
-    } else {
-        // This should not be reachable because any error will be already caught by the handler byte being 0.
-        println!("Reservation of baggage failed");
+    // Prints the current values from a resync, which replace every update up to its sequence number.
+    fn handle_resync_response(&mut self, subscription_id: u32, buf: &[u8]) {
+        match unmarshal_u8(buf, 4) {
+            (25, i) => {
+                let (subscription_id, i) = unmarshal_u32(buf, i);
+                let (sequence, i) = unmarshal_u32(buf, i);
+                self.monitor_updates.resynced(subscription_id, sequence);
+                let (target, i) = unmarshal_target(buf, i);
+                match target {
+                    MonitorTarget::Flight(_) => {
+                        print_monitor_update("flight_resync", subscription_id, sequence, buf, i)
+                    }
+                    MonitorTarget::Route(..) => {
+                        print_route_flights(subscription_id, sequence, &target, buf, i)
+                    }
+                }
+            }
+            (_, i) => {
+                print_error(&unmarshal_string(buf, i).0);
+                self.forget(subscription_id);
+            }
+        }
+    }
+
+    // Stops tracking a subscription that no longer exists.
+    fn forget(&mut self, subscription_id: u32) {
+        self.monitor_updates.forget(subscription_id);
+        self.monitors.lock().unwrap().remove(&subscription_id);
     }
 }
 
-// Might return errors from IO, or from bad user input.
-fn prepare_get_flight_identifiers(
-    std_in_reader: &mut Lines<StdinLock>,
-) -> Result<Vec<u8>, Box<dyn Error>> {
-    const GET_FLIGHT_IDENTIFIERS_SERVICE_ID: u8 = 1;
-    // Gets input from user for source and destination.
-    println!("Enter source:");
-    let source = std_in_reader.next().unwrap()?;
+// The sequence number of the last update shown for each subscription, to notice missed updates.
+struct MonitorUpdates {
+    last_sequences: HashMap<u32, u32>,
+    // Subscriptions that missed updates, whose current values have to be asked for.
+    missed: BTreeSet<u32>,
+}
 
-    // Validate source is a made up of only letters.
-    if !source.chars().all(|c| c.is_alphabetic()) {
-        return Err("Source must be made up of only letters".into());
+impl MonitorUpdates {
+    fn new() -> MonitorUpdates {
+        MonitorUpdates {
+            last_sequences: HashMap::new(),
+            missed: BTreeSet::new(),
+        }
     }
 
-    println!("Enter destination:");
-    let destination = std_in_reader.next().unwrap()?;
+    // Records an update and returns whether it is new. Sequence numbers start at 1, and a gap before the
+    // update means that updates were missed.
+    fn record(&mut self, subscription_id: u32, sequence: u32) -> bool {
+        let last_sequence = self
+            .last_sequences
+            .get(&subscription_id)
+            .copied()
+            .unwrap_or(0);
+        if sequence <= last_sequence {
+            return false;
+        }
+        if sequence > last_sequence + 1 {
+            self.missed.insert(subscription_id);
+        }
+        self.last_sequences.insert(subscription_id, sequence);
+        true
+    }
 
-    // Validate destination is a made up of only letters.
-    if !destination.chars().all(|c| c.is_alphabetic()) {
-        return Err("Destination must be made up of only letters".into());
+    // Records the current values from a resync, which replace every update up to its sequence number.
+    fn resynced(&mut self, subscription_id: u32, sequence: u32) {
+        let last_sequence = self.last_sequences.entry(subscription_id).or_insert(0);
+        *last_sequence = (*last_sequence).max(sequence);
     }
 
-    // Create a buffer to store the data to send with capacity 2048 bytes
-    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+    // Stops tracking a subscription that no longer exists.
+    fn forget(&mut self, subscription_id: u32) {
+        self.last_sequences.remove(&subscription_id);
+    }
+}
 
-    // Add service ID
+// Prints the fields of an update that changed, or of a resync that are watched.
+fn print_monitor_update(kind: &str, subscription_id: u32, sequence: u32, buf: &[u8], i: usize) {
+    // 1 Byte: Fields, as bits
+    // 4 Byte: Flight ID
+    // 4 Byte: Num_seats across every class
+    // 4 Byte: Baggage capacity left in kg
+    // 1 Byte: Status
+    // 4 Byte: Departure time
+    // Then the seats and airfare of each class
+    let (fields, i) = unmarshal_u8(buf, i);
+    let (flight_id, i) = unmarshal_u32(buf, i);
+    let (num_seats, i) = unmarshal_u32(buf, i);
+    let (baggage_capacity_kg, i) = unmarshal_u32(buf, i);
+    let (status, i) = unmarshal_u8(buf, i);
+    let (departure_time, i) = unmarshal_u32(buf, i);
+    let (cabins, _) = unmarshal_cabins(buf, i);
+
+    if json::enabled() {
+        // Only the watched fields are included.
+        let mut update = json::Object::new(kind)
+            .number("subscription_id", subscription_id)
+            .number("sequence", sequence)
+            .number("flight_id", flight_id);
+        if fields & WATCH_SEATS != 0 {
+            update = update.number("seats", num_seats);
+        }
+        if fields & WATCH_BAGGAGE != 0 {
+            update = update.number("baggage_capacity_kg", baggage_capacity_kg);
+        }
+        if fields & WATCH_STATUS != 0 {
+            update = update
+                .string("status", flight_status_name(status))
+                .number("departure_time", departure_time);
+        }
+        if fields & (WATCH_SEATS | WATCH_AIRFARE) != 0 {
+            update = update.objects("cabins", cabins.iter().map(Cabin::to_json).collect());
+        }
+        update.print();
+        return;
+    }
+
+    println!("EVENT: Flight {} changed", flight_id);
+    if fields & WATCH_SEATS != 0 {
+        println!("{:>10}: {} seats left", "Seats", num_seats);
+    }
+    if fields & WATCH_BAGGAGE != 0 {
+        println!(
+            "{:>10}: {} kg of capacity left",
+            "Baggage", baggage_capacity_kg
+        );
+    }
+    if fields & WATCH_STATUS != 0 {
+        println!(
+            "{:>10}: {} (departure time: {})",
+            "Status",
+            flight_status_name(status),
+            convert_unix_time_to_datetime(departure_time)
+        );
+    }
+    if fields & (WATCH_SEATS | WATCH_AIRFARE) != 0 {
+        print_cabins(&cabins);
+    }
+}
+
+// What a subscription monitors: a flight, or the flights from a source to a destination.
+enum MonitorTarget {
+    Flight(u32),
+    Route(String, String),
+}
+
+impl MonitorTarget {
+    fn to_json(&self) -> json::Object {
+        match self {
+            MonitorTarget::Flight(flight_id) => json::Object::nested()
+                .string("kind", "flight")
+                .number("flight_id", *flight_id),
+            MonitorTarget::Route(source, destination) => json::Object::nested()
+                .string("kind", "route")
+                .string("source", source)
+                .string("destination", destination),
+        }
+    }
+}
+
+impl std::fmt::Display for MonitorTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MonitorTarget::Flight(flight_id) => write!(f, "flight {flight_id}"),
+            MonitorTarget::Route(source, destination) => {
+                write!(f, "the route from {source} to {destination}")
+            }
+        }
+    }
+}
+
+fn unmarshal_target(buf: &[u8], i: usize) -> (MonitorTarget, usize) {
+    // 1 Byte: Kind of target, 0 for a flight and 1 for a route
+    // Then the flight ID, or the source and destination
+    match unmarshal_u8(buf, i) {
+        (0, i) => {
+            let (flight_id, i) = unmarshal_u32(buf, i);
+            (MonitorTarget::Flight(flight_id), i)
+        }
+        (_, i) => {
+            let (source, i) = unmarshal_string(buf, i);
+            let (destination, i) = unmarshal_string(buf, i);
+            (MonitorTarget::Route(source, destination), i)
+        }
+    }
+}
+
+// Prints an update about a flight on a monitored route.
+fn print_route_update(subscription_id: u32, sequence: u32, buf: &[u8], i: usize) {
+    // String: Source
+    // String: Destination
+    // Then the event and the flight
+    let (source, i) = unmarshal_string(buf, i);
+    let (destination, i) = unmarshal_string(buf, i);
+    let (flight, _) = unmarshal_route_flight(buf, i);
+
+    if json::enabled() {
+        json::Object::new("route_update")
+            .number("subscription_id", subscription_id)
+            .number("sequence", sequence)
+            .string("source", &source)
+            .string("destination", &destination)
+            .object("flight", flight.to_json())
+            .print();
+        return;
+    }
+
+    println!("EVENT: From {source} to {destination}, {flight}");
+}
+
+// Prints the flights on a route from a resync.
+fn print_route_flights(
+    subscription_id: u32,
+    sequence: u32,
+    target: &MonitorTarget,
+    buf: &[u8],
+    i: usize,
+) {
+    // 4 Byte: Total flights on the route
+    // 1 Byte: Number of flights listed
+    // Then the flights
+    let (total_flights, i) = unmarshal_u32(buf, i);
+    let (num_flights, mut i) = unmarshal_u8(buf, i);
+    let mut flights = Vec::with_capacity(num_flights as usize);
+    for _ in 0..num_flights {
+        let (flight, j) = unmarshal_route_flight(buf, i);
+        flights.push(flight);
+        i = j;
+    }
+
+    if json::enabled() {
+        json::Object::new("route_resync")
+            .number("subscription_id", subscription_id)
+            .number("sequence", sequence)
+            .object("target", target.to_json())
+            .number("total_flights", total_flights)
+            .objects(
+                "flights",
+                flights.iter().map(RouteFlight::to_json).collect(),
+            )
+            .print();
+        return;
+    }
+
+    println!("Showing {num_flights} of the {total_flights} flights on {target}:");
+    for flight in flights {
+        println!("{flight}");
+    }
+}
+
+// A flight on a monitored route and what happened to it.
+struct RouteFlight {
+    // 0 when listing the flights on the route
+    event: u8,
+    flight_id: u32,
+    departure_time: u32,
+    num_seats: u32,
+    airfare: Money,
+    status: u8,
+}
+
+impl RouteFlight {
+    fn to_json(&self) -> json::Object {
+        let event = match self.event {
+            1 => "added",
+            2 => "cancelled",
+            3 => "more_seats_available",
+            _ => "listed",
+        };
+        json::Object::nested()
+            .string("event", event)
+            .number("flight_id", self.flight_id)
+            .number("departure_time", self.departure_time)
+            .number("seats", self.num_seats)
+            .money("airfare", self.airfare)
+            .string("status", flight_status_name(self.status))
+    }
+}
+
+impl std::fmt::Display for RouteFlight {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let what_happened = match self.event {
+            1 => "was added",
+            2 => "was cancelled",
+            3 => "has more seats available",
+            _ => "is on the route",
+        };
+        write!(
+            f,
+            "flight {} {}: {}, departs {}, {} seats left from {}",
+            self.flight_id,
+            what_happened,
+            flight_status_name(self.status),
+            convert_unix_time_to_datetime(self.departure_time),
+            self.num_seats,
+            self.airfare
+        )
+    }
+}
+
+// Reads a flight on a monitored route, returning the index after it.
+fn unmarshal_route_flight(buf: &[u8], i: usize) -> (RouteFlight, usize) {
+    // 1 Byte: Event, 0 when listing the flights on the route
+    // 4 Byte: Flight ID
+    // 4 Byte: Departure time
+    // 4 Byte: Num_seats across every class
+    // Money: Cheapest airfare
+    // 1 Byte: Status
+    let (event, i) = unmarshal_u8(buf, i);
+    let (flight_id, i) = unmarshal_u32(buf, i);
+    let (departure_time, i) = unmarshal_u32(buf, i);
+    let (num_seats, i) = unmarshal_u32(buf, i);
+    let (airfare, i) = unmarshal_money(buf, i);
+    let (status, i) = unmarshal_u8(buf, i);
+    let flight = RouteFlight {
+        event,
+        flight_id,
+        departure_time,
+        num_seats,
+        airfare,
+        status,
+    };
+    (flight, i)
+}
+
+fn parse_get_flight_identifiers_response(buf: &[u8]) {
+    let (flight_ids, _) = unmarshal_u32_array(buf, 0);
+    if json::enabled() {
+        json::Object::new("flight_ids")
+            .numbers("flight_ids", &flight_ids)
+            .print();
+        return;
+    }
+    println!("Flight IDs: {:#?}", flight_ids);
+}
+
+fn parse_get_flight_summary_response(buf: &[u8]) {
+    let (departure_time, i) = unmarshal_u32(buf, 0);
+    let (cabins, i) = unmarshal_cabins(buf, i);
+    let (remaining_baggage_capacity_kg, i) = unmarshal_u32(buf, i);
+    let (status, i) = unmarshal_u8(buf, i);
+    // A quote is only given to logged in users.
+    let (has_quote, i) = unmarshal_u8(buf, i);
+    let quote = if has_quote == 1 {
+        let (quote_id, i) = unmarshal_u32(buf, i);
+        let (quote_expires_at, _) = unmarshal_u32(buf, i);
+        Some((quote_id, quote_expires_at))
+    } else {
+        None
+    };
+    if json::enabled() {
+        json::Object::new("flight_summary")
+            .number("departure_time", departure_time)
+            .objects("cabins", cabins.iter().map(Cabin::to_json).collect())
+            .number("baggage_capacity_kg", remaining_baggage_capacity_kg)
+            .string("status", flight_status_name(status))
+            .optional_number("quote_id", quote.map(|(quote_id, _)| quote_id))
+            .optional_number("quote_expires_at", quote.map(|(_, expires_at)| expires_at))
+            .print();
+        return;
+    }
+    print_cabins(&cabins);
+    println!("Status: {}", flight_status_name(status));
+    println!(
+        "Departure time: {}",
+        convert_unix_time_to_datetime(departure_time)
+    );
+    println!(
+        "Remaining baggage capacity: {} kg",
+        remaining_baggage_capacity_kg
+    );
+    match quote {
+        Some((quote_id, quote_expires_at)) => println!(
+            "Quote ID: {} (reserve before {} to book at these fares once)",
+            quote_id,
+            convert_unix_time_to_datetime(quote_expires_at)
+        ),
+        None => println!("Log in to get a quote to book at these fares."),
+    }
+}
+
+// The seats and airfare of a seat class.
+struct Cabin {
+    seat_class: u8,
+    seats: u32,
+    airfare: Money,
+    sold: u32,
+    physical_seats: u32,
+}
+
+impl Cabin {
+    fn to_json(&self) -> json::Object {
+        json::Object::nested()
+            .string("seat_class", seat_class_name(self.seat_class))
+            .number("seats", self.seats)
+            .money("airfare", self.airfare)
+            .number("sold", self.sold)
+            .number("physical_seats", self.physical_seats)
+    }
+}
+
+// Reads the seats and airfare of each seat class starting at index i, returning the index after them.
+fn unmarshal_cabins(buf: &[u8], i: usize) -> (Vec<Cabin>, usize) {
+    let (num_classes, mut i) = unmarshal_u8(buf, i);
+    let mut cabins = Vec::with_capacity(num_classes as usize);
+    for _ in 0..num_classes {
+        let (seat_class, j) = unmarshal_u8(buf, i);
+        let (seats, j) = unmarshal_u32(buf, j);
+        let (airfare, j) = unmarshal_money(buf, j);
+        let (sold, j) = unmarshal_u32(buf, j);
+        let (physical_seats, j) = unmarshal_u32(buf, j);
+        i = j;
+        cabins.push(Cabin {
+            seat_class,
+            seats,
+            airfare,
+            sold,
+            physical_seats,
+        });
+    }
+    (cabins, i)
+}
+
+fn print_cabins(cabins: &[Cabin]) {
+    for cabin in cabins {
+        println!(
+            "{:>10}: {} seats at {} ({} sold of {} physical seats)",
+            seat_class_name(cabin.seat_class),
+            cabin.seats,
+            cabin.airfare,
+            cabin.sold,
+            cabin.physical_seats
+        );
+    }
+}
+
+fn parse_reserve_seats_response(buf: &[u8]) {
+    let (has_succeeded, i) = unmarshal_u8(buf, 0);
+    if has_succeeded == 1 {
+        let (booking_id, i) = unmarshal_u32(buf, i);
+        let (airfare, i) = unmarshal_money(buf, i);
+        let (seats, _) = unmarshal_seats(buf, i);
+        if json::enabled() {
+            json::Object::new("reservation")
+                .number("booking_id", booking_id)
+                .money("airfare", airfare)
+                .strings("seats", &seats)
+                .print();
+            return;
+        }
+        println!("Reservation succeeded. Booking reference: {}", booking_id);
+        println!("Fare per seat: {}", airfare);
+
+        // Flights with a seat map also assign seats.
+        if !seats.is_empty() {
+            println!("Seats: {}", seats.join(", "));
+        }
+    } else {
+        // This should not be reachable because any error will be already caught by the handler byte being 0.
+        print_error("Reservation failed");
+    }
+}
+
+// Reads a list of seat names, prefixed by the number of seats, starting at index i.
+fn unmarshal_seats(buf: &[u8], i: usize) -> (Vec<String>, usize) {
+    let (num_seats, mut i) = unmarshal_u8(buf, i);
+    let mut seats = Vec::with_capacity(num_seats as usize);
+    for _ in 0..num_seats {
+        let (seat, j) = unmarshal_string(buf, i);
+        seats.push(seat);
+        i = j;
+    }
+    (seats, i)
+}
+
+fn parse_join_waitlist_response(buf: &[u8]) {
+    let (waitlist_id, i) = unmarshal_u32(buf, 0);
+    let (position, _) = unmarshal_u32(buf, i);
+    if json::enabled() {
+        json::Object::new("waitlist")
+            .number("waitlist_id", waitlist_id)
+            .number("position", position)
+            .print();
+        return;
+    }
+    println!(
+        "Joined the waitlist. Waitlist reference: {}, position: {}",
+        waitlist_id, position
+    );
+    println!("You will be told here when your seats are booked, as long as the client is running.");
+}
+
+fn parse_cancel_booking_response(buf: &[u8]) {
+    let (booking_id, _) = unmarshal_u32(buf, 0);
+    if json::enabled() {
+        json::Object::new("cancellation")
+            .number("booking_id", booking_id)
+            .print();
+        return;
+    }
+    println!("Booking {} has been cancelled.", booking_id);
+}
+
+// Prints the boarding pass issued at check-in.
+fn parse_check_in_response(buf: &[u8]) {
+    let (booking_id, i) = unmarshal_u32(buf, 0);
+    let (passenger, i) = unmarshal_u8(buf, i);
+    let (name, i) = unmarshal_string(buf, i);
+    let (flight_id, i) = unmarshal_u32(buf, i);
+    let (source, i) = unmarshal_string(buf, i);
+    let (destination, i) = unmarshal_string(buf, i);
+    let (departure_time, i) = unmarshal_u32(buf, i);
+    let (seat_class, i) = unmarshal_u8(buf, i);
+    let (seat, i) = unmarshal_string(buf, i);
+    let (gate, i) = unmarshal_string(buf, i);
+    let (boarding_group, i) = unmarshal_u8(buf, i);
+    let (sequence_number, i) = unmarshal_u32(buf, i);
+    let (num_bags, i) = unmarshal_u8(buf, i);
+    let (baggage_kg, i) = unmarshal_u32(buf, i);
+    let (baggage_fee, i) = unmarshal_money(buf, i);
+    let (barcode, _) = unmarshal_string(buf, i);
+
+    if json::enabled() {
+        json::Object::new("boarding_pass")
+            .number("booking_id", booking_id)
+            .number("passenger", passenger)
+            .string("name", &name)
+            .number("flight_id", flight_id)
+            .string("source", &source)
+            .string("destination", &destination)
+            .number("departure_time", departure_time)
+            .string("seat_class", seat_class_name(seat_class))
+            .string("seat", &seat)
+            .string("gate", &gate)
+            .number("boarding_group", boarding_group)
+            .number("sequence_number", sequence_number)
+            .number("bags", num_bags)
+            .number("baggage_kg", baggage_kg)
+            .money("baggage_fee", baggage_fee)
+            .string("barcode", &barcode)
+            .print();
+        return;
+    }
+
+    print_padded_string("Boarding Pass");
+    println!(
+        "Passenger: {} (passenger {} of booking {})",
+        name, passenger, booking_id
+    );
+    println!("Flight {}: {} -> {}", flight_id, source, destination);
+    println!("Departs: {}", convert_unix_time_to_datetime(departure_time));
+    println!(
+        "Seat: {} ({}) | Gate: {} | Boarding group: {} | Sequence: {}",
+        seat,
+        seat_class_name(seat_class),
+        gate,
+        boarding_group,
+        sequence_number
+    );
+    println!(
+        "Checked baggage: {} piece(s), {} kg, excess fee {}",
+        num_bags, baggage_kg, baggage_fee
+    );
+    println!("Barcode: {}", barcode);
+}
+
+fn parse_set_passenger_response(buf: &[u8]) {
+    let (booking_id, i) = unmarshal_u32(buf, 0);
+    let (passenger, _) = unmarshal_u8(buf, i);
+    if json::enabled() {
+        json::Object::new("passenger")
+            .number("booking_id", booking_id)
+            .number("passenger", passenger)
+            .print();
+        return;
+    }
+    println!(
+        "Saved the details of passenger {} of booking {}.",
+        passenger, booking_id
+    );
+}
+
+fn parse_register_response(buf: &[u8]) {
+    let (user_id, _) = unmarshal_u32(buf, 0);
+    if json::enabled() {
+        json::Object::new("registration")
+            .number("user_id", user_id)
+            .print();
+        return;
+    }
+    println!("Registered as user {}. Log in to make bookings.", user_id);
+}
+
+// Prints who is logged in, and returns the session to send with the services that act for a user.
+fn parse_login_response(buf: &[u8]) -> Session {
+    let (user_id, i) = unmarshal_u32(buf, 0);
+    let (role, i) = unmarshal_u8(buf, i);
+    let (token, i) = unmarshal_string(buf, i);
+    let (expires_at, _) = unmarshal_u32(buf, i);
+    if json::enabled() {
+        // The token is kept by the client, so it is not printed.
+        json::Object::new("login")
+            .number("user_id", user_id)
+            .string("role", role_name(role))
+            .number("expires_at", expires_at)
+            .print();
+    } else {
+        println!(
+            "Logged in as user {} ({}). The session expires at {}.",
+            user_id,
+            role_name(role),
+            convert_unix_time_to_datetime(expires_at)
+        );
+    }
+    Session {
+        token,
+        acting_for: None,
+    }
+}
+
+fn parse_set_role_response(buf: &[u8]) {
+    let (user_id, i) = unmarshal_u32(buf, 0);
+    let (role, _) = unmarshal_u8(buf, i);
+    if json::enabled() {
+        json::Object::new("role")
+            .number("user_id", user_id)
+            .string("role", role_name(role))
+            .print();
+        return;
+    }
+    println!("User {} is now {}.", user_id, role_name(role));
+}
+
+fn parse_list_bookings_response(buf: &[u8]) {
+    let (total_bookings, i) = unmarshal_u32(buf, 0);
+    let (num_bookings, mut i) = unmarshal_u8(buf, i);
+
+    if total_bookings == 0 && !json::enabled() {
+        println!("You have no bookings.");
+        return;
+    }
+
+    let mut bookings = Vec::with_capacity(num_bookings as usize);
+    if !json::enabled() {
+        println!("Showing {num_bookings} of your {total_bookings} bookings:");
+    }
+    for _ in 0..num_bookings {
+        let (booking_id, j) = unmarshal_u32(buf, i);
+        let (flight_id, j) = unmarshal_u32(buf, j);
+        let (source, j) = unmarshal_string(buf, j);
+        let (destination, j) = unmarshal_string(buf, j);
+        let (departure_time, j) = unmarshal_u32(buf, j);
+        let (status, j) = unmarshal_u8(buf, j);
+        let (seat_class, j) = unmarshal_u8(buf, j);
+        let (num_seats, j) = unmarshal_u32(buf, j);
+        let (airfare, j) = unmarshal_money(buf, j);
+        i = j;
+        if json::enabled() {
+            bookings.push(
+                json::Object::nested()
+                    .number("booking_id", booking_id)
+                    .number("flight_id", flight_id)
+                    .string("source", &source)
+                    .string("destination", &destination)
+                    .number("departure_time", departure_time)
+                    .string("status", flight_status_name(status))
+                    .string("seat_class", seat_class_name(seat_class))
+                    .number("seats", num_seats)
+                    .money("airfare", airfare),
+            );
+            continue;
+        }
+        println!(
+            "Booking {}: flight {} from {} to {} departing {} ({}), {} {} seat(s) at {} each",
+            booking_id,
+            flight_id,
+            source,
+            destination,
+            convert_unix_time_to_datetime(departure_time),
+            flight_status_name(status),
+            num_seats,
+            seat_class_name(seat_class),
+            airfare
+        );
+    }
+
+    if json::enabled() {
+        json::Object::new("bookings")
+            .number("total_bookings", total_bookings)
+            .objects("bookings", bookings)
+            .print();
+    }
+}
+
+// Waitlist booked callbacks are sent when seats free up for a client on a flight's waitlist.
+fn parse_waitlist_booked_callback(buf: &[u8]) {
+    // 4 Byte: Waitlist reference
+    // 4 Byte: Flight ID
+    // 4 Byte: Booking reference
+    // 1 Byte: Seat class
+    // 4 Byte: Number of seats
+    // Then the assigned seats, if the flight has a seat map
+    let (waitlist_id, i) = unmarshal_u32(buf, 0);
+    let (flight_id, i) = unmarshal_u32(buf, i);
+    let (booking_id, i) = unmarshal_u32(buf, i);
+    let (seat_class, i) = unmarshal_u8(buf, i);
+    let (num_seats, i) = unmarshal_u32(buf, i);
+    let (seats, _) = unmarshal_seats(buf, i);
+    if json::enabled() {
+        json::Object::new("waitlist_booked")
+            .number("waitlist_id", waitlist_id)
+            .number("flight_id", flight_id)
+            .number("booking_id", booking_id)
+            .string("seat_class", seat_class_name(seat_class))
+            .number("num_seats", num_seats)
+            .strings("seats", &seats)
+            .print();
+        return;
+    }
+    println!(
+        "EVENT: Waitlist {} booked {} {} seats on flight {}. Booking reference: {}",
+        waitlist_id,
+        num_seats,
+        seat_class_name(seat_class),
+        flight_id,
+        booking_id
+    );
+    if !seats.is_empty() {
+        println!("Seats: {}", seats.join(", "));
+    }
+}
+
+// Prints the seat map one row per line, e.g. "  3 economy  [A][B] [C][x] exit" where "x" is an occupied
+// seat and "#" a seat that cannot be reserved.
+fn parse_get_seat_map_response(buf: &[u8]) {
+    let (letters, i) = unmarshal_string(buf, 0);
+    let (num_rows, mut i) = unmarshal_u32(buf, i);
+    let mut rows = Vec::with_capacity(num_rows as usize);
+    for row in 1..=num_rows {
+        let (seat_class, j) = unmarshal_u8(buf, i);
+        let (is_exit_row, mut j) = unmarshal_u8(buf, j);
+        let mut line = format!("{:>3} {:<9}", row, seat_class_name(seat_class));
+        let mut seats = Vec::new();
+        for letter in letters.chars() {
+            if letter == ' ' {
+                line.push(' ');
+                continue;
+            }
+            let (state, k) = unmarshal_u8(buf, j);
+            j = k;
+            let (mark, state) = match state {
+                0 => (letter, "free"),
+                1 => ('x', "occupied"),
+                _ => ('#', "not_available"),
+            };
+            line.push_str(&format!("[{}]", mark));
+            seats.push(
+                json::Object::nested()
+                    .string("seat", &format!("{}{}", row, letter))
+                    .string("state", state),
+            );
+        }
+        if is_exit_row == 1 {
+            line.push_str(" exit");
+        }
+        if json::enabled() {
+            rows.push(
+                json::Object::nested()
+                    .number("row", row)
+                    .string("seat_class", seat_class_name(seat_class))
+                    .bool("exit_row", is_exit_row == 1)
+                    .objects("seats", seats),
+            );
+        } else {
+            println!("{}", line);
+        }
+        i = j;
+    }
+    if json::enabled() {
+        json::Object::new("seat_map").objects("rows", rows).print();
+        return;
+    }
+    println!("Free seats show their letter, x = occupied, # = not available.");
+}
+
+// Returns the subscription ID, unless the subscription failed.
+fn parse_monitor_seat_availability_response(buf: &[u8]) -> Option<u32> {
+    let (has_succeeded, i) = unmarshal_u8(buf, 0);
+    if has_succeeded == 1 {
+        let (subscription_id, i) = unmarshal_u32(buf, i);
+        let (expires_at, _) = unmarshal_u32(buf, i);
+
+        if json::enabled() {
+            json::Object::new("subscription")
+                .number("subscription_id", subscription_id)
+                .string("target", "flight")
+                .number("expires_at", expires_at)
+                .print();
+            return Some(subscription_id);
+        }
+
+        // Updates are printed in the background as they arrive, so the menu stays usable.
+        println!(
+            "Subscription {} succeeded until {}. Updates are shown as they arrive.",
+            subscription_id,
+            convert_unix_time_to_datetime(expires_at)
+        );
+        Some(subscription_id)
+    } else {
+        // This should not be reachable because any error will be already caught by the handler byte being 0.
+        print_error("Subscription failed");
+        None
+    }
+}
+
+fn parse_monitor_route_response(buf: &[u8]) -> Option<u32> {
+    let (subscription_id, i) = unmarshal_u32(buf, 0);
+    let (expires_at, i) = unmarshal_u32(buf, i);
+    let (num_flights, _) = unmarshal_u32(buf, i);
+
+    if json::enabled() {
+        json::Object::new("subscription")
+            .number("subscription_id", subscription_id)
+            .string("target", "route")
+            .number("expires_at", expires_at)
+            .number("flights", num_flights)
+            .print();
+        return Some(subscription_id);
+    }
+
+    println!(
+        "Subscription {} to a route with {} flights so far succeeded until {}. Updates are shown as they arrive.",
+        subscription_id,
+        num_flights,
+        convert_unix_time_to_datetime(expires_at)
+    );
+    Some(subscription_id)
+}
+
+// Returns the ID of the cancelled subscription.
+fn parse_unsubscribe_response(buf: &[u8]) -> u32 {
+    let (subscription_id, i) = unmarshal_u32(buf, 0);
+    let (target, _) = unmarshal_target(buf, i);
+    if json::enabled() {
+        json::Object::new("unsubscribed")
+            .number("subscription_id", subscription_id)
+            .object("target", target.to_json())
+            .print();
+    } else {
+        println!("Stopped monitoring {target} (subscription {subscription_id}).");
+    }
+    subscription_id
+}
+
+fn parse_list_subscriptions_response(buf: &[u8]) {
+    let (total_subscriptions, i) = unmarshal_u32(buf, 0);
+    let (num_subscriptions, mut i) = unmarshal_u8(buf, i);
+
+    if total_subscriptions == 0 && !json::enabled() {
+        println!("You are not monitoring any flights or routes.");
+        return;
+    }
+
+    let mut subscriptions = Vec::with_capacity(num_subscriptions as usize);
+    if !json::enabled() {
+        println!("Showing {num_subscriptions} of your {total_subscriptions} subscriptions:");
+    }
+    for _ in 0..num_subscriptions {
+        let (subscription_id, j) = unmarshal_u32(buf, i);
+        let (target, j) = unmarshal_target(buf, j);
+        let (expires_at, j) = unmarshal_u32(buf, j);
+        i = j;
+        if json::enabled() {
+            subscriptions.push(
+                json::Object::nested()
+                    .number("subscription_id", subscription_id)
+                    .object("target", target.to_json())
+                    .number("expires_at", expires_at),
+            );
+            continue;
+        }
+        println!(
+            "Subscription {}: {} until {}",
+            subscription_id,
+            target,
+            convert_unix_time_to_datetime(expires_at)
+        );
+    }
+
+    if json::enabled() {
+        json::Object::new("subscriptions")
+            .number("total_subscriptions", total_subscriptions)
+            .objects("subscriptions", subscriptions)
+            .print();
+    }
+}
+
+// Flight changed callbacks are sent when an admin updates or cancels a flight the client booked or monitors.
+fn parse_flight_changed_callback(buf: &[u8]) {
+    // 4 Byte: Flight ID
+    // Long string: Description of the change
+    let (flight_id, i) = unmarshal_u32(buf, 0);
+    let (message, _) = unmarshal_long_string(buf, i);
+    if json::enabled() {
+        json::Object::new("flight_changed")
+            .number("flight_id", flight_id)
+            .string("message", &message)
+            .print();
+        return;
+    }
+    println!("EVENT: Flight {} was updated. {}", flight_id, message);
+}
+
+// Status changed callbacks are sent when a flight the client booked or monitors is delayed, cancelled, etc.
+fn parse_status_changed_callback(buf: &[u8]) {
+    // 4 Byte: Flight ID
+    // 1 Byte: New status
+    // 4 Byte: Departure time
+    let (flight_id, i) = unmarshal_u32(buf, 0);
+    let (status, i) = unmarshal_u8(buf, i);
+    let (departure_time, _) = unmarshal_u32(buf, i);
+    if json::enabled() {
+        json::Object::new("status_changed")
+            .number("flight_id", flight_id)
+            .string("status", flight_status_name(status))
+            .number("departure_time", departure_time)
+            .print();
+        return;
+    }
+    println!(
+        "EVENT: Flight {} is now {} (departure time: {})",
+        flight_id,
+        flight_status_name(status),
+        convert_unix_time_to_datetime(departure_time)
+    );
+}
+
+fn parse_admin_response(buf: &[u8]) {
+    let (flight_id, i) = unmarshal_u32(buf, 0);
+    let (num_notified, i) = unmarshal_u32(buf, i);
+    let (bumped_booking_ids, i) = unmarshal_u32_array(buf, i);
+    let result = json::Object::new("admin")
+        .number("flight_id", flight_id)
+        .number("notified", num_notified)
+        .numbers("bumped_booking_ids", &bumped_booking_ids);
+    if !json::enabled() {
+        println!(
+            "Admin operation succeeded for flight {}. {} client(s) notified.",
+            flight_id, num_notified
+        );
+        if !bumped_booking_ids.is_empty() {
+            println!("Bumped bookings: {:?}", bumped_booking_ids);
+        }
+    }
+
+    // A manifest page, if one was exported.
+    let (has_manifest, i) = unmarshal_u8(buf, i);
+    if has_manifest == 1 {
+        let (total_passengers, i) = unmarshal_u32(buf, i);
+        let (offset, i) = unmarshal_u32(buf, i);
+        let (num_passengers, i) = unmarshal_u32(buf, i);
+        let (manifest, _) = unmarshal_long_string(buf, i);
+        if json::enabled() {
+            let manifest = json::Object::nested()
+                .number("total_passengers", total_passengers)
+                .number("offset", offset)
+                .number("passengers", num_passengers)
+                .string("text", &manifest);
+            result.object("manifest", manifest).print();
+            return;
+        }
+        println!("{}", manifest.trim_end());
+        if num_passengers == 0 {
+            println!("No passengers to show ({} in total).", total_passengers);
+        } else {
+            println!(
+                "Passengers {} to {} of {}.",
+                offset + 1,
+                offset + num_passengers,
+                total_passengers
+            );
+        }
+        if offset + num_passengers < total_passengers {
+            println!(
+                "Export again starting from passenger {} for the rest.",
+                offset + num_passengers + 1
+            );
+        }
+    } else if json::enabled() {
+        result.optional_string("manifest", None).print();
+    }
+}
+
+fn parse_get_earliest_flight_ids_response(buf: &[u8]) {
+    let (flight_ids, _) = unmarshal_u32_array(buf, 0);
+
+    if json::enabled() {
+        json::Object::new("earliest_flight_ids")
+            .numbers("flight_ids", &flight_ids)
+            .print();
+    } else if flight_ids.is_empty() {
+        println!("No flights found for the given source.");
+    } else {
+        println!("Flight IDs: {:#?}", flight_ids);
+    }
+}
+
+fn parse_reserve_baggage_response(buf: &[u8]) {
+    let (has_succeeded, i) = unmarshal_u8(buf, 0);
+    if has_succeeded == 1 {
+        let (total_kg, i) = unmarshal_u32(buf, i);
+        let (free_allowance_kg, i) = unmarshal_u32(buf, i);
+        let (fee, i) = unmarshal_money(buf, i);
+        let (total_fee, _) = unmarshal_money(buf, i);
+        if json::enabled() {
+            json::Object::new("baggage_reservation")
+                .number("total_kg", total_kg)
+                .number("free_allowance_kg", free_allowance_kg)
+                .money("fee", fee)
+                .money("total_fee", total_fee)
+                .print();
+            return;
+        }
+        println!("Reservation of baggage succeeded");
+        println!(
+            "The passenger has {} kg of baggage, of which {} kg is free",
+            total_kg, free_allowance_kg
+        );
+        println!(
+            "Excess baggage fee: {} (total for the passenger: {})",
+            fee, total_fee
+        );
+    } else {
+        // This should not be reachable because any error will be already caught by the handler byte being 0.
+        print_error("Reservation of baggage failed");
+    }
+}
+
+fn parse_search_flights_response(buf: &[u8]) {
+    let (total_matches, i) = unmarshal_u32(buf, 0);
+    let (num_flights, i) = unmarshal_u8(buf, i);
+    let flights = unmarshal_flight_listings(buf, i, num_flights);
+
+    if json::enabled() {
+        json::Object::new("search_results")
+            .number("total_matches", total_matches)
+            .objects(
+                "flights",
+                flights.iter().map(FlightListing::to_json).collect(),
+            )
+            .print();
+        return;
+    }
+
+    if total_matches == 0 {
+        println!("No flights match the given filters.");
+        return;
+    }
+
+    println!("Showing {num_flights} of {total_matches} matching flights:");
+    for flight in flights {
+        println!("{flight}");
+    }
+}
+
+fn parse_get_next_departures_response(buf: &[u8]) {
+    let (num_flights, i) = unmarshal_u8(buf, 0);
+    let flights = unmarshal_flight_listings(buf, i, num_flights);
+
+    if json::enabled() {
+        json::Object::new("next_departures")
+            .objects(
+                "flights",
+                flights.iter().map(FlightListing::to_json).collect(),
+            )
+            .print();
+        return;
+    }
+
+    if num_flights == 0 {
+        println!("No upcoming departures found.");
+        return;
+    }
+
+    println!("Next {num_flights} departures:");
+    for flight in flights {
+        println!("{flight}");
+    }
+}
+
+// A flight in search results or the next departures.
+struct FlightListing {
+    flight_id: u32,
+    source: String,
+    destination: String,
+    departure_time: u32,
+    airfare: Money,
+    seats: u32,
+    remaining_baggage_capacity_kg: u32,
+    status: u8,
+}
+
+impl FlightListing {
+    fn to_json(&self) -> json::Object {
+        json::Object::nested()
+            .number("flight_id", self.flight_id)
+            .string("source", &self.source)
+            .string("destination", &self.destination)
+            .number("departure_time", self.departure_time)
+            .money("airfare", self.airfare)
+            .number("seats", self.seats)
+            .number("baggage_capacity_kg", self.remaining_baggage_capacity_kg)
+            .string("status", flight_status_name(self.status))
+    }
+}
+
+impl std::fmt::Display for FlightListing {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Flight {}: {} -> {} | {} | Departs {} | Airfare {} | Seats {} | Baggage {} kg",
+            self.flight_id,
+            self.source,
+            self.destination,
+            flight_status_name(self.status),
+            convert_unix_time_to_datetime(self.departure_time),
+            self.airfare,
+            self.seats,
+            self.remaining_baggage_capacity_kg
+        )
+    }
+}
+
+// Reads the flight listings starting at index i.
+fn unmarshal_flight_listings(buf: &[u8], mut i: usize, num_flights: u8) -> Vec<FlightListing> {
+    let mut flights = Vec::with_capacity(num_flights as usize);
+    for _ in 0..num_flights {
+        let (flight_id, j) = unmarshal_u32(buf, i);
+        let (source, j) = unmarshal_string(buf, j);
+        let (destination, j) = unmarshal_string(buf, j);
+        let (departure_time, j) = unmarshal_u32(buf, j);
+        let (airfare, j) = unmarshal_money(buf, j);
+        let (seats, j) = unmarshal_u32(buf, j);
+        let (remaining_baggage_capacity_kg, j) = unmarshal_u32(buf, j);
+        let (status, j) = unmarshal_u8(buf, j);
+        i = j;
+        flights.push(FlightListing {
+            flight_id,
+            source,
+            destination,
+            departure_time,
+            airfare,
+            seats,
+            remaining_baggage_capacity_kg,
+            status,
+        });
+    }
+    flights
+}
+
+// Prints an error as text, or as an object with its message.
+fn print_error(message: &str) {
+    if json::enabled() {
+        json::Object::new("error")
+            .string("message", message)
+            .print();
+    } else {
+        println!("Error: {}", message);
+    }
+}
+
+// Might return errors from IO, or from bad user input.
+fn prepare_get_flight_identifiers(
+    std_in_reader: &mut Lines<StdinLock>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    // Gets input from user for source and destination.
+    println!("Enter source:");
+    let source = std_in_reader.next().unwrap()?;
+
+    // Validate source is a made up of only letters.
+    if !source.chars().all(|c| c.is_alphabetic()) {
+        return Err("Source must be made up of only letters".into());
+    }
+
+    println!("Enter destination:");
+    let destination = std_in_reader.next().unwrap()?;
+
+    // Validate destination is a made up of only letters.
+    if !destination.chars().all(|c| c.is_alphabetic()) {
+        return Err("Destination must be made up of only letters".into());
+    }
+
+    Ok(flight_identifiers_request(&source, &destination))
+}
+
+// The request for the IDs of the flights from the source to the destination.
+fn flight_identifiers_request(source: &str, destination: &str) -> Vec<u8> {
+    const GET_FLIGHT_IDENTIFIERS_SERVICE_ID: u8 = 1;
+
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add service ID
     marshal_u8(GET_FLIGHT_IDENTIFIERS_SERVICE_ID, &mut buffer_to_send);
 
-    // Add source
-    marshal_string(&source, &mut buffer_to_send);
+    // Add source
+    marshal_string(source, &mut buffer_to_send);
+
+    // Add destination
+    marshal_string(destination, &mut buffer_to_send);
+
+    // Return the buffer
+    buffer_to_send
+}
+
+fn prepare_get_flight_summary(
+    std_in_reader: &mut Lines<StdinLock>,
+    session: Option<&Session>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    // Gets input from user for flight ID.
+    println!("Enter flight identifier:");
+    let flight_id = std_in_reader.next().unwrap()?;
+
+    // Convert the flight ID to a u32
+    let flight_id = match flight_id.parse::<u32>() {
+        Ok(flight_id) => flight_id,
+        Err(_) => {
+            return Err(Box::new(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Invalid flight identifier",
+            )));
+        }
+    };
+
+    let display_currency = read_display_currency(std_in_reader)?;
+
+    Ok(flight_summary_request(
+        session,
+        flight_id,
+        display_currency.as_deref(),
+    ))
+}
+
+// The request for a flight's summary, with the fares in the currency given or else the server's. Only a
+// logged in user is given a quote to book at the fares shown.
+fn flight_summary_request(
+    session: Option<&Session>,
+    flight_id: u32,
+    display_currency: Option<&str>,
+) -> Vec<u8> {
+    const GET_FLIGHT_SUMMARY_SERVICE_ID: u8 = 2;
+
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add service ID as first byte
+    marshal_u8(GET_FLIGHT_SUMMARY_SERVICE_ID, &mut buffer_to_send);
+
+    // Add whether the user is logged in, then the session if so
+    match session {
+        Some(session) => {
+            marshal_u8(1, &mut buffer_to_send);
+            marshal_session(session, &mut buffer_to_send);
+        }
+        None => marshal_u8(0, &mut buffer_to_send),
+    }
+
+    // Add flight ID and the optional currency to show the fares in
+    marshal_u32(flight_id, &mut buffer_to_send);
+    marshal_optional_string(display_currency, &mut buffer_to_send);
+
+    // Return the buffer
+    buffer_to_send
+}
+
+fn prepare_reserve_seats(
+    std_in_reader: &mut Lines<StdinLock>,
+    session: Option<&Session>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let session = require_login(session)?;
+
+    // Gets input from user for flight ID.
+    println!("Enter flight identifier:");
+    let flight_id = std_in_reader.next().unwrap()?;
+
+    // Gets input from user for number of seats.
+    println!("Enter number of seats to reserve:");
+    let seats = std_in_reader.next().unwrap()?;
+
+    // Gets input from user for seat class.
+    println!("{}", SEAT_CLASS_PROMPT);
+    let seat_class = read_seat_class(std_in_reader)?;
+
+    // Gets input from user for the quote to book at.
+    let quote_id = read_quote_id(std_in_reader)?;
+
+    // Convert the flight ID to a u32
+    let flight_id = match flight_id.parse::<u32>() {
+        Ok(flight_id) => flight_id,
+        Err(_) => {
+            println!("Invalid flight identifier");
+            return Err(Box::new(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Invalid flight identifier",
+            )));
+        }
+    };
+
+    // Convert the number of seats to a u32
+    let seats = match seats.parse::<u32>() {
+        Ok(seats) => seats,
+        Err(_) => {
+            println!("Invalid number of seats");
+            return Err(Box::new(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Invalid number of seats",
+            )));
+        }
+    };
+
+    Ok(reserve_seats_request(
+        session, flight_id, seats, seat_class, quote_id,
+    ))
+}
+
+// The request to reserve seats of a class, at a quoted fare or else the current one.
+fn reserve_seats_request(
+    session: &Session,
+    flight_id: u32,
+    seats: u32,
+    seat_class: u8,
+    quote_id: Option<u32>,
+) -> Vec<u8> {
+    const RESERVE_SEATS_SERVICE_ID: u8 = 3;
+
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add service ID as first byte
+    marshal_u8(RESERVE_SEATS_SERVICE_ID, &mut buffer_to_send);
+
+    // Add the session from logging in
+    marshal_session(session, &mut buffer_to_send);
+
+    // Add flight ID
+    marshal_u32(flight_id, &mut buffer_to_send);
+
+    // Add number of seats
+    marshal_u32(seats, &mut buffer_to_send);
+
+    // Add seat class
+    marshal_u8(seat_class, &mut buffer_to_send);
+
+    // Add quote ID
+    marshal_optional_u32(quote_id, &mut buffer_to_send);
+
+    // Return the buffer
+    buffer_to_send
+}
+
+// Prepares a request to register (service 18) or log in (service 19), which both take a username and password.
+fn prepare_account(
+    std_in_reader: &mut Lines<StdinLock>,
+    service_id: u8,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    println!("Enter username:");
+    let username = std_in_reader.next().unwrap()?.trim().to_string();
+
+    println!("Enter password:");
+    let password = std_in_reader.next().unwrap()?;
+    if username.len() > u8::MAX as usize || password.len() > u8::MAX as usize {
+        return Err("Username and password must be at most 255 bytes long".into());
+    }
+
+    // Only registration takes the admin key, which makes the new user an admin.
+    let admin_key = if service_id == 18 {
+        println!(
+            "Enter the server's admin key to register an admin (leave empty for a traveller):"
+        );
+        read_optional_input(std_in_reader)?
+    } else {
+        None
+    };
+    if admin_key
+        .as_ref()
+        .is_some_and(|key| key.len() > u8::MAX as usize)
+    {
+        return Err("Admin key is too long".into());
+    }
+
+    Ok(account_request(
+        service_id,
+        &username,
+        &password,
+        admin_key.as_deref(),
+    ))
+}
+
+// The request to register (service 18), with the admin key if given, or to log in (service 19).
+fn account_request(
+    service_id: u8,
+    username: &str,
+    password: &str,
+    admin_key: Option<&str>,
+) -> Vec<u8> {
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add service ID as first byte
+    marshal_u8(service_id, &mut buffer_to_send);
+
+    // Add username and password, and the admin key when registering
+    marshal_string(username, &mut buffer_to_send);
+    marshal_string(password, &mut buffer_to_send);
+    if service_id == 18 {
+        marshal_optional_string(admin_key, &mut buffer_to_send);
+    }
+
+    // Return the buffer
+    buffer_to_send
+}
+
+fn prepare_set_role(
+    std_in_reader: &mut Lines<StdinLock>,
+    session: Option<&Session>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    const SET_ROLE_SERVICE_ID: u8 = 21;
+
+    let session = require_login(session)?;
+
+    println!("Enter username:");
+    let username = std_in_reader.next().unwrap()?.trim().to_string();
+    if username.len() > u8::MAX as usize {
+        return Err("Username must be at most 255 bytes long".into());
+    }
+
+    println!("Enter role: 0 = traveller, 1 = agent, 2 = admin");
+    let role = match std_in_reader.next().unwrap()?.trim().parse::<u8>() {
+        Ok(role) if role <= 2 => role,
+        _ => return Err("Role must be 0, 1 or 2".into()),
+    };
+
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add service ID as first byte
+    marshal_u8(SET_ROLE_SERVICE_ID, &mut buffer_to_send);
+
+    // Add the session from logging in
+    marshal_session(session, &mut buffer_to_send);
+
+    // Add username and role
+    marshal_string(&username, &mut buffer_to_send);
+    marshal_u8(role, &mut buffer_to_send);
+
+    // Return the buffer
+    Ok(buffer_to_send)
+}
+
+// Sets or clears the traveller an agent acts for in the requests that follow. Nothing is sent to the server;
+// it checks the traveller with each request.
+fn choose_acting_for(
+    std_in_reader: &mut Lines<StdinLock>,
+    session: Option<&mut Session>,
+) -> Result<(), Box<dyn Error>> {
+    let session = session.ok_or("Log in first (service 19) to act for a traveller")?;
+    println!("Enter the username of the traveller to act for (leave empty to act for yourself):");
+    session.acting_for = read_optional_input(std_in_reader)?;
+    if session
+        .acting_for
+        .as_ref()
+        .is_some_and(|username| username.len() > u8::MAX as usize)
+    {
+        session.acting_for = None;
+        return Err("Username must be at most 255 bytes long".into());
+    }
+    match &session.acting_for {
+        Some(username) => println!("Now acting for {}.", username),
+        None => println!("Now acting for yourself."),
+    }
+    Ok(())
+}
+
+fn prepare_list_bookings(
+    std_in_reader: &mut Lines<StdinLock>,
+    session: Option<&Session>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    const LIST_BOOKINGS_SERVICE_ID: u8 = 20;
+
+    let session = require_login(session)?;
+
+    // Long lists are sent a page at a time.
+    println!("Enter the booking to start from (default 1):");
+    let start = match read_optional_input(std_in_reader)? {
+        Some(input) => match input.parse::<u32>() {
+            Ok(start) if start >= 1 => start,
+            _ => return Err("Booking must be at least 1".into()),
+        },
+        None => 1,
+    };
+
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add service ID as first byte
+    marshal_u8(LIST_BOOKINGS_SERVICE_ID, &mut buffer_to_send);
+
+    // Add the session from logging in
+    marshal_session(session, &mut buffer_to_send);
+
+    // Add page offset
+    marshal_u32(start - 1, &mut buffer_to_send);
+
+    // Return the buffer
+    Ok(buffer_to_send)
+}
+
+fn prepare_get_seat_map(std_in_reader: &mut Lines<StdinLock>) -> Result<Vec<u8>, Box<dyn Error>> {
+    const GET_SEAT_MAP_SERVICE_ID: u8 = 12;
+
+    println!("Enter flight identifier:");
+    let flight_id = std_in_reader
+        .next()
+        .unwrap()?
+        .parse::<u32>()
+        .map_err(|_| "Invalid flight identifier")?;
+
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add service ID as first byte
+    marshal_u8(GET_SEAT_MAP_SERVICE_ID, &mut buffer_to_send);
+
+    // Add flight ID
+    marshal_u32(flight_id, &mut buffer_to_send);
+
+    // Return the buffer
+    Ok(buffer_to_send)
+}
+
+fn prepare_reserve_specific_seats(
+    std_in_reader: &mut Lines<StdinLock>,
+    session: Option<&Session>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    const RESERVE_SPECIFIC_SEATS_SERVICE_ID: u8 = 13;
 
-    // Add destination
-    marshal_string(&destination, &mut buffer_to_send);
+    let session = require_login(session)?;
+
+    println!("Enter flight identifier:");
+    let flight_id = std_in_reader
+        .next()
+        .unwrap()?
+        .parse::<u32>()
+        .map_err(|_| "Invalid flight identifier")?;
+
+    println!("{}", SEAT_CLASS_PROMPT);
+    let seat_class = read_seat_class(std_in_reader)?;
+
+    println!("Enter the seats to reserve separated by spaces, e.g. 3A 3B, or the number of seats to sit together:");
+    let input = std_in_reader.next().unwrap()?;
+
+    let quote_id = read_quote_id(std_in_reader)?;
+
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add service ID as first byte
+    marshal_u8(RESERVE_SPECIFIC_SEATS_SERVICE_ID, &mut buffer_to_send);
+
+    // Add the session from logging in
+    marshal_session(session, &mut buffer_to_send);
+
+    // Add flight ID and seat class
+    marshal_u32(flight_id, &mut buffer_to_send);
+    marshal_u8(seat_class, &mut buffer_to_send);
+
+    // Add the seats, either by name or as a number for the server to pick.
+    match input.trim().parse::<u32>() {
+        Ok(num_seats) => {
+            marshal_u8(2, &mut buffer_to_send);
+            marshal_u32(num_seats, &mut buffer_to_send);
+        }
+        Err(_) => {
+            let seats = input
+                .split_whitespace()
+                .map(|seat| seat.to_ascii_uppercase())
+                .collect::<Vec<String>>();
+            if seats.is_empty() || seats.len() > 255 {
+                return Err("Enter between 1 and 255 seats".into());
+            }
+            marshal_u8(1, &mut buffer_to_send);
+            marshal_u8(seats.len() as u8, &mut buffer_to_send);
+            for seat in seats.iter() {
+                marshal_string(seat, &mut buffer_to_send);
+            }
+        }
+    }
+
+    // Add quote ID
+    marshal_optional_u32(quote_id, &mut buffer_to_send);
 
     // Return the buffer
     Ok(buffer_to_send)
 }
 
-fn prepare_get_flight_summary(
+fn prepare_join_waitlist(
     std_in_reader: &mut Lines<StdinLock>,
+    session: Option<&Session>,
 ) -> Result<Vec<u8>, Box<dyn Error>> {
-    const GET_FLIGHT_SUMMARY_SERVICE_ID: u8 = 2;
+    const JOIN_WAITLIST_SERVICE_ID: u8 = 14;
+
+    let session = require_login(session)?;
+
+    println!("Enter flight identifier:");
+    let flight_id = std_in_reader
+        .next()
+        .unwrap()?
+        .parse::<u32>()
+        .map_err(|_| "Invalid flight identifier")?;
+
+    println!("{}", SEAT_CLASS_PROMPT);
+    let seat_class = read_seat_class(std_in_reader)?;
+
+    println!("Enter number of seats to wait for:");
+    let num_seats = std_in_reader
+        .next()
+        .unwrap()?
+        .parse::<u32>()
+        .map_err(|_| "Invalid number of seats")?;
+
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add service ID as first byte
+    marshal_u8(JOIN_WAITLIST_SERVICE_ID, &mut buffer_to_send);
+
+    // Add the session from logging in
+    marshal_session(session, &mut buffer_to_send);
+
+    // Add flight ID, seat class and number of seats
+    marshal_u32(flight_id, &mut buffer_to_send);
+    marshal_u8(seat_class, &mut buffer_to_send);
+    marshal_u32(num_seats, &mut buffer_to_send);
+
+    // Return the buffer
+    Ok(buffer_to_send)
+}
+
+fn prepare_set_passenger(
+    std_in_reader: &mut Lines<StdinLock>,
+    session: Option<&Session>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    const SET_PASSENGER_SERVICE_ID: u8 = 16;
+
+    let session = require_login(session)?;
+
+    println!("Enter booking reference:");
+    let booking_id = std_in_reader
+        .next()
+        .unwrap()?
+        .parse::<u32>()
+        .map_err(|_| "Invalid booking reference")?;
+
+    // Passengers are numbered in the order of the seats of the booking.
+    println!("Enter passenger number (default 1):");
+    let passenger = match read_optional_input(std_in_reader)? {
+        Some(input) => match input.parse::<u8>() {
+            Ok(passenger) if passenger >= 1 => passenger,
+            _ => return Err("Passenger number must be at least 1".into()),
+        },
+        None => 1,
+    };
+
+    // The server checks the details, so only their length is checked here.
+    let mut details = Vec::with_capacity(4);
+    for prompt in [
+        "Enter full name:",
+        "Enter passport or ID document number:",
+        "Enter date of birth (YYYY-MM-DD):",
+        "Enter email address or phone number:",
+    ] {
+        println!("{}", prompt);
+        let detail = std_in_reader.next().unwrap()?.trim().to_string();
+        if detail.len() > 255 {
+            return Err("Passenger details must be at most 255 characters each".into());
+        }
+        details.push(detail);
+    }
+    details[1] = details[1].to_ascii_uppercase();
+
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add service ID as first byte
+    marshal_u8(SET_PASSENGER_SERVICE_ID, &mut buffer_to_send);
+
+    // Add the session from logging in
+    marshal_session(session, &mut buffer_to_send);
+
+    // Add booking reference, passenger and their details
+    marshal_u32(booking_id, &mut buffer_to_send);
+    marshal_u8(passenger, &mut buffer_to_send);
+    for detail in details.iter() {
+        marshal_string(detail, &mut buffer_to_send);
+    }
+
+    // Return the buffer
+    Ok(buffer_to_send)
+}
+
+fn prepare_check_in(
+    std_in_reader: &mut Lines<StdinLock>,
+    session: Option<&Session>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    const CHECK_IN_SERVICE_ID: u8 = 17;
+
+    let session = require_login(session)?;
+
+    println!("Enter booking reference:");
+    let booking_id = std_in_reader
+        .next()
+        .unwrap()?
+        .parse::<u32>()
+        .map_err(|_| "Invalid booking reference")?;
+
+    // Passengers are numbered in the order of the seats of the booking.
+    println!("Enter passenger number (default 1):");
+    let passenger = match read_optional_input(std_in_reader)? {
+        Some(input) => match input.parse::<u8>() {
+            Ok(passenger) if passenger >= 1 => passenger,
+            _ => return Err("Passenger number must be at least 1".into()),
+        },
+        None => 1,
+    };
+
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add service ID as first byte
+    marshal_u8(CHECK_IN_SERVICE_ID, &mut buffer_to_send);
+
+    // Add the session from logging in
+    marshal_session(session, &mut buffer_to_send);
+
+    // Add booking reference and passenger
+    marshal_u32(booking_id, &mut buffer_to_send);
+    marshal_u8(passenger, &mut buffer_to_send);
+
+    // Return the buffer
+    Ok(buffer_to_send)
+}
+
+fn prepare_cancel_booking(
+    std_in_reader: &mut Lines<StdinLock>,
+    session: Option<&Session>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    const CANCEL_BOOKING_SERVICE_ID: u8 = 15;
+
+    let session = require_login(session)?;
+
+    println!("Enter booking reference:");
+    let booking_id = std_in_reader
+        .next()
+        .unwrap()?
+        .parse::<u32>()
+        .map_err(|_| "Invalid booking reference")?;
+
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add service ID as first byte
+    marshal_u8(CANCEL_BOOKING_SERVICE_ID, &mut buffer_to_send);
+
+    // Add the session from logging in
+    marshal_session(session, &mut buffer_to_send);
+
+    // Add booking reference
+    marshal_u32(booking_id, &mut buffer_to_send);
+
+    // Return the buffer
+    Ok(buffer_to_send)
+}
+
+fn prepare_monitor_seat_availability(
+    std_in_reader: &mut Lines<StdinLock>,
+    session: Option<&Session>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let session = require_login(session)?;
 
     // Gets input from user for flight ID.
     println!("Enter flight identifier:");
@@ -342,158 +2601,529 @@ fn prepare_get_flight_summary(
     let flight_id = match flight_id.parse::<u32>() {
         Ok(flight_id) => flight_id,
         Err(_) => {
+            println!("Invalid flight ID");
             return Err(Box::new(std::io::Error::new(
                 std::io::ErrorKind::InvalidInput,
-                "Invalid flight identifier",
+                "Invalid flight ID",
+            )));
+        }
+    };
+
+    let monitor_interval = read_monitor_interval(std_in_reader)?;
+
+    println!("Enter the fields to watch, comma separated: seats, baggage, airfare, status (default: seats):");
+    let fields = match read_optional_input(std_in_reader)? {
+        Some(input) => parse_watched_fields(&input)?,
+        None => WATCH_SEATS,
+    };
+
+    // Each watched field other than the status can be limited to values below a threshold.
+    let mut seats_below = None;
+    if fields & WATCH_SEATS != 0 {
+        println!("Notify only when fewer seats than this are left:");
+        if let Some(input) = read_optional_input(std_in_reader)? {
+            seats_below = Some(
+                input
+                    .parse::<u32>()
+                    .map_err(|_| "Invalid number of seats")?,
+            );
+        }
+    }
+    let mut baggage_kg_below = None;
+    if fields & WATCH_BAGGAGE != 0 {
+        println!("Notify only when less baggage capacity than this is left, in kg:");
+        if let Some(input) = read_optional_input(std_in_reader)? {
+            baggage_kg_below = Some(
+                input
+                    .parse::<u32>()
+                    .map_err(|_| "Invalid baggage capacity")?,
+            );
+        }
+    }
+    let mut airfare_below = None;
+    if fields & WATCH_AIRFARE != 0 {
+        println!("Notify only when the cheapest airfare drops below this, with its currency, e.g. 25.00 SGD:");
+        if let Some(input) = read_optional_input(std_in_reader)? {
+            airfare_below = Some(parse_money(&input)?);
+        }
+    }
+
+    let watch = Watch {
+        fields,
+        seats_below,
+        baggage_kg_below,
+        airfare_below,
+    };
+    Ok(monitor_seat_availability_request(
+        session,
+        flight_id,
+        monitor_interval,
+        &watch,
+    ))
+}
+
+// The fields of a flight to watch, as bits, and the optional thresholds their values must drop below.
+struct Watch {
+    fields: u8,
+    seats_below: Option<u32>,
+    baggage_kg_below: Option<u32>,
+    airfare_below: Option<Money>,
+}
+
+// The request to monitor a flight for the interval, in seconds.
+fn monitor_seat_availability_request(
+    session: &Session,
+    flight_id: u32,
+    monitor_interval: u32,
+    watch: &Watch,
+) -> Vec<u8> {
+    const MONITOR_SEAT_AVAILABILITY_SERVICE_ID: u8 = 4;
+
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add service ID as first byte
+    marshal_u8(MONITOR_SEAT_AVAILABILITY_SERVICE_ID, &mut buffer_to_send);
+
+    // Add the session from logging in
+    marshal_session(session, &mut buffer_to_send);
+
+    // Add flight ID
+    marshal_u32(flight_id, &mut buffer_to_send);
+
+    // Add monitor interval
+    marshal_u32(monitor_interval, &mut buffer_to_send);
+
+    // Add the watched fields and their optional thresholds
+    marshal_u8(watch.fields, &mut buffer_to_send);
+    marshal_optional_u32(watch.seats_below, &mut buffer_to_send);
+    marshal_optional_u32(watch.baggage_kg_below, &mut buffer_to_send);
+    marshal_optional_money(watch.airfare_below, &mut buffer_to_send);
+
+    // Return the buffer
+    buffer_to_send
+}
+
+// Parses comma separated field names into the bits of the fields to watch.
+fn parse_watched_fields(input: &str) -> Result<u8, Box<dyn Error>> {
+    let mut fields = 0;
+    for name in input.split(',').map(str::trim) {
+        match WATCHED_FIELDS
+            .iter()
+            .find(|(_, field_name)| name.eq_ignore_ascii_case(field_name))
+        {
+            Some((field, _)) => fields |= field,
+            None => return Err(format!("Unknown field to watch: {}", name).into()),
+        }
+    }
+    Ok(fields)
+}
+
+// Reads the monitor interval, in seconds.
+fn read_monitor_interval(std_in_reader: &mut Lines<StdinLock>) -> Result<u32, Box<dyn Error>> {
+    // Gets input from user for monitor_interval.
+    const SECONDS_IN_YEAR: u32 = 31536000;
+    println!(
+        "Enter monitor interval, up to {} seconds (1 year):",
+        SECONDS_IN_YEAR
+    );
+    let monitor_interval = std_in_reader.next().unwrap()?;
+
+    // Check if monitor interval fits in a u32
+    if monitor_interval.len() > 10 {
+        return Err(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Monitor interval is too big.",
+        )));
+    }
+    // Convert the monitor interval to a u32
+    let monitor_interval = match monitor_interval.parse::<u32>() {
+        Ok(monitor_interval) => monitor_interval,
+        Err(_) => {
+            println!("Invalid monitor interval");
+            return Err(Box::new(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Invalid monitor interval",
             )));
         }
     };
 
+    if monitor_interval > SECONDS_IN_YEAR {
+        return Err(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Monitor interval is too big.",
+        )));
+    }
+
+    Ok(monitor_interval)
+}
+
+fn prepare_monitor_route(
+    std_in_reader: &mut Lines<StdinLock>,
+    session: Option<&Session>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    const MONITOR_ROUTE_SERVICE_ID: u8 = 26;
+
+    let session = require_login(session)?;
+
+    // Gets input from user for source and destination.
+    println!("Enter source:");
+    let source = std_in_reader.next().unwrap()?;
+
+    // Validate source is a made up of only letters.
+    if !source.chars().all(|c| c.is_alphabetic()) {
+        return Err("Source must be made up of only letters".into());
+    }
+
+    println!("Enter destination:");
+    let destination = std_in_reader.next().unwrap()?;
+
+    // Validate destination is a made up of only letters.
+    if !destination.chars().all(|c| c.is_alphabetic()) {
+        return Err("Destination must be made up of only letters".into());
+    }
+
+    let monitor_interval = read_monitor_interval(std_in_reader)?;
+
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add service ID as first byte
+    marshal_u8(MONITOR_ROUTE_SERVICE_ID, &mut buffer_to_send);
+
+    // Add the session from logging in
+    marshal_session(session, &mut buffer_to_send);
+
+    // Add source, destination and monitor interval
+    marshal_string(&source, &mut buffer_to_send);
+    marshal_string(&destination, &mut buffer_to_send);
+    marshal_u32(monitor_interval, &mut buffer_to_send);
+
+    // Return the buffer
+    Ok(buffer_to_send)
+}
+
+fn prepare_unsubscribe(
+    std_in_reader: &mut Lines<StdinLock>,
+    session: Option<&Session>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let session = require_login(session)?;
+
+    println!("Enter subscription ID:");
+    let subscription_id = std_in_reader
+        .next()
+        .unwrap()?
+        .parse::<u32>()
+        .map_err(|_| "Invalid subscription ID")?;
+
+    Ok(unsubscribe_request(session, subscription_id))
+}
+
+// The request to stop monitoring.
+fn unsubscribe_request(session: &Session, subscription_id: u32) -> Vec<u8> {
+    const UNSUBSCRIBE_SERVICE_ID: u8 = 23;
+
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add service ID as first byte
+    marshal_u8(UNSUBSCRIBE_SERVICE_ID, &mut buffer_to_send);
+
+    // Add the session from logging in
+    marshal_session(session, &mut buffer_to_send);
+
+    // Add subscription ID
+    marshal_u32(subscription_id, &mut buffer_to_send);
+
+    buffer_to_send
+}
+
+// The request for the current seats of a subscription that missed seat updates.
+fn resync_request(session: &Session, subscription_id: u32) -> Vec<u8> {
+    const RESYNC_SUBSCRIPTION_SERVICE_ID: u8 = 25;
+
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add service ID as first byte
+    marshal_u8(RESYNC_SUBSCRIPTION_SERVICE_ID, &mut buffer_to_send);
+
+    // Add the session from logging in
+    marshal_session(session, &mut buffer_to_send);
+
+    // Add subscription ID
+    marshal_u32(subscription_id, &mut buffer_to_send);
+
+    buffer_to_send
+}
+
+fn prepare_list_subscriptions(session: Option<&Session>) -> Result<Vec<u8>, Box<dyn Error>> {
+    const LIST_SUBSCRIPTIONS_SERVICE_ID: u8 = 24;
+
+    let session = require_login(session)?;
+
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add service ID as first byte
+    marshal_u8(LIST_SUBSCRIPTIONS_SERVICE_ID, &mut buffer_to_send);
+
+    // Add the session from logging in
+    marshal_session(session, &mut buffer_to_send);
+
+    // Return the buffer
+    Ok(buffer_to_send)
+}
+
+fn prepare_earliest_flight_ids(
+    std_in_reader: &mut Lines<StdinLock>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    const GET_EARLIEST_FLIGHT_IDS: u8 = 5;
+
+    // Gets input from user for source and destination.
+    println!("Enter source:");
+    let source = std_in_reader.next().unwrap()?;
+
+    // Validate source is a made up of only letters.
+    if !source.chars().all(|c| c.is_alphabetic()) {
+        return Err("Source must be made up of only letters".into());
+    }
+
     // Create a buffer to store the data to send with capacity 2048 bytes
     let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
 
     // Add service ID as first byte
-    marshal_u8(GET_FLIGHT_SUMMARY_SERVICE_ID, &mut buffer_to_send);
+    marshal_u8(GET_EARLIEST_FLIGHT_IDS, &mut buffer_to_send);
 
-    // Add flight ID
-    marshal_u32(flight_id, &mut buffer_to_send);
+    // Add flight source
+    marshal_string(&source, &mut buffer_to_send);
 
     // Return the buffer
     Ok(buffer_to_send)
 }
 
-fn prepare_reserve_seats(std_in_reader: &mut Lines<StdinLock>) -> Result<Vec<u8>, Box<dyn Error>> {
-    const RESERVE_SEATS_SERVICE_ID: u8 = 3;
-
-    // Gets input from user for flight ID.
-    println!("Enter flight identifier:");
-    let flight_id = std_in_reader.next().unwrap()?;
-
-    // Gets input from user for number of seats.
-    println!("Enter number of seats to reserve:");
-    let seats = std_in_reader.next().unwrap()?;
+fn prepare_reserve_baggage(
+    std_in_reader: &mut Lines<StdinLock>,
+    session: Option<&Session>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    const RESERVE_BAGGAGE_SERVICE_ID: u8 = 6;
 
-    // Convert the flight ID to a u32
-    let flight_id = match flight_id.parse::<u32>() {
-        Ok(flight_id) => flight_id,
-        Err(_) => {
-            println!("Invalid flight identifier");
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "Invalid flight identifier",
-            )));
-        }
+    let session = require_login(session)?;
+
+    // Gets input from user for the booking reference.
+    println!("Enter booking reference:");
+    let booking_id = std_in_reader
+        .next()
+        .unwrap()?
+        .parse::<u32>()
+        .map_err(|_| "Invalid booking reference")?;
+
+    // Passengers are numbered in the order of the seats of the booking.
+    println!("Enter passenger number (default 1):");
+    let passenger = match read_optional_input(std_in_reader)? {
+        Some(input) => match input.parse::<u8>() {
+            Ok(passenger) if passenger >= 1 => passenger,
+            _ => return Err("Passenger number must be at least 1".into()),
+        },
+        None => 1,
     };
 
-    // Convert the number of seats to a u32
-    let seats = match seats.parse::<u32>() {
-        Ok(seats) => seats,
-        Err(_) => {
-            println!("Invalid number of seats");
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "Invalid number of seats",
-            )));
-        }
-    };
+    // The server checks the weights against its baggage limits.
+    println!("Enter the weight in kg of each piece of baggage, separated by commas (e.g. 23, 8):");
+    let pieces_kg = std_in_reader
+        .next()
+        .unwrap()?
+        .split(',')
+        .map(|weight| weight.trim().parse::<u32>())
+        .collect::<Result<Vec<u32>, _>>()
+        .map_err(|_| "Invalid baggage weight")?;
+    if pieces_kg.is_empty() || pieces_kg.len() > u8::MAX as usize {
+        return Err("Enter between 1 and 255 pieces of baggage".into());
+    }
 
     // Create a buffer to store the data to send with capacity 2048 bytes
     let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
 
     // Add service ID as first byte
-    marshal_u8(RESERVE_SEATS_SERVICE_ID, &mut buffer_to_send);
+    marshal_u8(RESERVE_BAGGAGE_SERVICE_ID, &mut buffer_to_send);
 
-    // Add flight ID
-    marshal_u32(flight_id, &mut buffer_to_send);
+    // Add the session from logging in
+    marshal_session(session, &mut buffer_to_send);
 
-    // Add number of seats
-    marshal_u32(seats, &mut buffer_to_send);
+    // Add booking reference and passenger
+    marshal_u32(booking_id, &mut buffer_to_send);
+    marshal_u8(passenger, &mut buffer_to_send);
+
+    // Add the weight of each piece
+    marshal_u32_array(&pieces_kg, &mut buffer_to_send);
 
     // Return the buffer
     Ok(buffer_to_send)
 }
 
-fn prepare_monitor_seat_availability(
-    std_in_reader: &mut Lines<StdinLock>,
-    time_out_duration: &mut u32,
-) -> Result<Vec<u8>, Box<dyn Error>> {
-    const MONITOR_SEAT_AVAILABILITY_SERVICE_ID: u8 = 4;
+fn prepare_search_flights(std_in_reader: &mut Lines<StdinLock>) -> Result<Vec<u8>, Box<dyn Error>> {
+    println!("Leave any filter empty to skip it.");
 
-    // Gets input from user for flight ID.
-    println!("Enter flight identifier:");
-    let flight_id = std_in_reader.next().unwrap()?;
+    println!("Enter source:");
+    let source = read_optional_input(std_in_reader)?;
+    if let Some(source) = &source {
+        if !source.chars().all(|c| c.is_alphabetic()) {
+            return Err("Source must be made up of only letters".into());
+        }
+    }
+
+    println!("Enter destination:");
+    let destination = read_optional_input(std_in_reader)?;
+    if let Some(destination) = &destination {
+        if !destination.chars().all(|c| c.is_alphabetic()) {
+            return Err("Destination must be made up of only letters".into());
+        }
+    }
+
+    println!("Enter earliest departure time (YYYY-MM-DD HH:MM):");
+    let departure_after = match read_optional_input(std_in_reader)? {
+        Some(input) => Some(convert_datetime_input_to_unix_time(&input)?),
+        None => None,
+    };
+
+    println!("Enter latest departure time (YYYY-MM-DD HH:MM):");
+    let departure_before = match read_optional_input(std_in_reader)? {
+        Some(input) => Some(convert_datetime_input_to_unix_time(&input)?),
+        None => None,
+    };
 
-    // Gets input from user for monitor_interval.
-    const SECONDS_IN_YEAR: u32 = 31536000;
     println!(
-        "Enter monitor interval, up to {} seconds (1 year):",
-        SECONDS_IN_YEAR
+        "Enter seat class for the seat and airfare filters: 0 = economy, 1 = premium, 2 = business"
     );
-    let monitor_interval = std_in_reader.next().unwrap()?;
+    let seat_class = match read_optional_input(std_in_reader)? {
+        Some(input) => Some(parse_seat_class(&input)?),
+        None => None,
+    };
 
-    // Convert the flight ID to a u32
-    let flight_id = match flight_id.parse::<u32>() {
-        Ok(flight_id) => flight_id,
-        Err(_) => {
-            println!("Invalid flight ID");
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "Invalid flight ID",
-            )));
-        }
+    println!("Enter minimum number of seats available:");
+    let min_seats = match read_optional_input(std_in_reader)? {
+        Some(input) => Some(
+            input
+                .parse::<u32>()
+                .map_err(|_| "Invalid number of seats")?,
+        ),
+        None => None,
     };
 
-    // Check if monitor interval fits in a u32
-    if monitor_interval.len() > 10 {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::InvalidInput,
-            "Monitor interval is too big.",
-        )));
-    }
-    // Convert the monitor interval to a u32
-    let monitor_interval = match monitor_interval.parse::<u32>() {
-        Ok(monitor_interval) => monitor_interval,
-        Err(_) => {
-            println!("Invalid monitor interval");
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "Invalid monitor interval",
-            )));
-        }
+    println!("Enter maximum airfare with its currency, e.g. 25.00 SGD:");
+    let max_airfare = match read_optional_input(std_in_reader)? {
+        Some(input) => Some(parse_money(&input)?),
+        None => None,
     };
 
-    if monitor_interval > SECONDS_IN_YEAR {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::InvalidInput,
-            "Monitor interval is too big.",
-        )));
-    }
+    println!("Enter minimum remaining baggage capacity in kg:");
+    let min_baggage_capacity_kg = match read_optional_input(std_in_reader)? {
+        Some(input) => Some(
+            input
+                .parse::<u32>()
+                .map_err(|_| "Invalid baggage capacity")?,
+        ),
+        None => None,
+    };
 
-    // Modify the time out duration to the monitor interval
-    *time_out_duration = monitor_interval;
+    println!("Sort by: 0 = departure time (default), 1 = airfare, 2 = seats available");
+    let sort_by = match read_optional_input(std_in_reader)? {
+        Some(input) => match input.parse::<u8>() {
+            Ok(sort_by) if sort_by <= 2 => sort_by,
+            _ => return Err("Sort key must be 0, 1 or 2".into()),
+        },
+        None => 0,
+    };
+
+    println!("Enter page number (default 1):");
+    let page_number = match read_optional_input(std_in_reader)? {
+        Some(input) => match input.parse::<u32>() {
+            Ok(page_number) if page_number >= 1 => page_number,
+            _ => return Err("Page number must be at least 1".into()),
+        },
+        None => 1,
+    };
+
+    let display_currency = read_display_currency(std_in_reader)?;
+
+    Ok(search_flights_request(&SearchFilters {
+        source,
+        destination,
+        departure_after,
+        departure_before,
+        seat_class,
+        min_seats,
+        max_airfare,
+        min_baggage_capacity_kg,
+        sort_by,
+        page_number,
+        display_currency,
+    }))
+}
+
+// What to search flights by. Every filter is optional.
+#[derive(Default)]
+struct SearchFilters {
+    source: Option<String>,
+    destination: Option<String>,
+    departure_after: Option<u32>,
+    departure_before: Option<u32>,
+    seat_class: Option<u8>,
+    min_seats: Option<u32>,
+    max_airfare: Option<Money>,
+    min_baggage_capacity_kg: Option<u32>,
+    // 0 = departure time, 1 = airfare, 2 = seats available
+    sort_by: u8,
+    // Starting at 1, or 0 for the first page.
+    page_number: u32,
+    display_currency: Option<String>,
+}
+
+// The request for one page of the flights that match the filters.
+fn search_flights_request(filters: &SearchFilters) -> Vec<u8> {
+    const SEARCH_FLIGHTS_SERVICE_ID: u8 = 7;
+    const DEFAULT_PAGE_SIZE: u32 = 10;
 
     // Create a buffer to store the data to send with capacity 2048 bytes
     let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
 
     // Add service ID as first byte
-    marshal_u8(MONITOR_SEAT_AVAILABILITY_SERVICE_ID, &mut buffer_to_send);
-
-    // Add flight ID
-    marshal_u32(flight_id, &mut buffer_to_send);
+    marshal_u8(SEARCH_FLIGHTS_SERVICE_ID, &mut buffer_to_send);
+
+    // Add the optional filters
+    marshal_optional_string(filters.source.as_deref(), &mut buffer_to_send);
+    marshal_optional_string(filters.destination.as_deref(), &mut buffer_to_send);
+    marshal_optional_u32(filters.departure_after, &mut buffer_to_send);
+    marshal_optional_u32(filters.departure_before, &mut buffer_to_send);
+    marshal_optional_u8(filters.seat_class, &mut buffer_to_send);
+    marshal_optional_u32(filters.min_seats, &mut buffer_to_send);
+    marshal_optional_money(filters.max_airfare, &mut buffer_to_send);
+    marshal_optional_u32(filters.min_baggage_capacity_kg, &mut buffer_to_send);
+
+    // Add sort key
+    marshal_u8(filters.sort_by, &mut buffer_to_send);
+
+    // Add page offset and page size
+    marshal_u32(
+        filters.page_number.saturating_sub(1) * DEFAULT_PAGE_SIZE,
+        &mut buffer_to_send,
+    );
+    marshal_u32(DEFAULT_PAGE_SIZE, &mut buffer_to_send);
 
-    // Add monitor interval
-    marshal_u32(monitor_interval, &mut buffer_to_send);
+    // Add the optional currency to show the fares in
+    marshal_optional_string(filters.display_currency.as_deref(), &mut buffer_to_send);
 
     // Return the buffer
-    Ok(buffer_to_send)
+    buffer_to_send
 }
 
-fn prepare_earliest_flight_ids(
+fn prepare_next_departures(
     std_in_reader: &mut Lines<StdinLock>,
 ) -> Result<Vec<u8>, Box<dyn Error>> {
-    const GET_EARLIEST_FLIGHT_IDS: u8 = 5;
+    const GET_NEXT_DEPARTURES_SERVICE_ID: u8 = 8;
+    const DEFAULT_NUM_FLIGHTS: u32 = 5;
 
-    // Gets input from user for source and destination.
+    // Gets input from user for source.
     println!("Enter source:");
     let source = std_in_reader.next().unwrap()?;
 
@@ -502,78 +3132,483 @@ fn prepare_earliest_flight_ids(
         return Err("Source must be made up of only letters".into());
     }
 
+    println!("Enter destination (leave empty for any):");
+    let destination = read_optional_input(std_in_reader)?;
+    if let Some(destination) = &destination {
+        if !destination.chars().all(|c| c.is_alphabetic()) {
+            return Err("Destination must be made up of only letters".into());
+        }
+    }
+
+    println!("Enter departures after (YYYY-MM-DD HH:MM, leave empty for now):");
+    let departure_after = match read_optional_input(std_in_reader)? {
+        Some(input) => Some(convert_datetime_input_to_unix_time(&input)?),
+        None => None,
+    };
+
+    println!("Enter number of flights (default {DEFAULT_NUM_FLIGHTS}):");
+    let num_flights = match read_optional_input(std_in_reader)? {
+        Some(input) => match input.parse::<u32>() {
+            Ok(num_flights) if num_flights >= 1 => num_flights,
+            _ => return Err("Number of flights must be at least 1".into()),
+        },
+        None => DEFAULT_NUM_FLIGHTS,
+    };
+
+    let display_currency = read_display_currency(std_in_reader)?;
+
     // Create a buffer to store the data to send with capacity 2048 bytes
     let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
 
     // Add service ID as first byte
-    marshal_u8(GET_EARLIEST_FLIGHT_IDS, &mut buffer_to_send);
+    marshal_u8(GET_NEXT_DEPARTURES_SERVICE_ID, &mut buffer_to_send);
 
-    // Add flight source
+    // Add source, optional destination and optional start time
     marshal_string(&source, &mut buffer_to_send);
+    marshal_optional_string(destination.as_deref(), &mut buffer_to_send);
+    marshal_optional_u32(departure_after, &mut buffer_to_send);
+
+    // Add number of flights and the optional currency to show the fares in
+    marshal_u32(num_flights, &mut buffer_to_send);
+    marshal_optional_string(display_currency.as_deref(), &mut buffer_to_send);
 
     // Return the buffer
     Ok(buffer_to_send)
 }
 
-fn prepare_reserve_baggage(std_in_reader: &mut Lines<StdinLock>) -> Result<Vec<u8>, Box<dyn Error>> {
-    const RESERVE_BAGGAGE_SERVICE_ID: u8 = 6;
+fn prepare_admin(
+    std_in_reader: &mut Lines<StdinLock>,
+    session: Option<&Session>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    const ADMIN_SERVICE_ID: u8 = 9;
 
-    // Gets input from user for flight ID.
-    println!("Enter flight identifier:");
-    let flight_id = std_in_reader.next().unwrap()?;
+    let session = require_login(session)?;
 
-    // Gets input from user for baggage weight in kg.
-    println!("Enter baggage weight in kg to reserve:");
-    let baggage_weight = std_in_reader.next().unwrap()?;
+    println!("Choose an operation: 1 = add flight, 2 = update flight, 3 = cancel flight, 4 = set flight status, 5 = set seat map, 6 = check in a boarding flight, 7 = export passenger manifest");
+    let operation = std_in_reader.next().unwrap()?;
+    let operation = match operation.parse::<u8>() {
+        Ok(operation) if (1..=7).contains(&operation) => operation,
+        _ => return Err("Operation must be between 1 and 7".into()),
+    };
 
-    // Convert the flight ID to a u32
-    let flight_id = match flight_id.parse::<u32>() {
-        Ok(flight_id) => flight_id,
-        Err(_) => {
-            println!("Invalid flight identifier");
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "Invalid flight identifier",
-            )));
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add service ID as first byte
+    marshal_u8(ADMIN_SERVICE_ID, &mut buffer_to_send);
+
+    // Add the session from logging in and the operation
+    marshal_session(session, &mut buffer_to_send);
+    marshal_u8(operation, &mut buffer_to_send);
+
+    match operation {
+        1 => {
+            println!("Enter source:");
+            let source = std_in_reader.next().unwrap()?;
+            if source.is_empty() || !source.chars().all(|c| c.is_alphabetic()) {
+                return Err("Source must be made up of only letters".into());
+            }
+
+            println!("Enter destination:");
+            let destination = std_in_reader.next().unwrap()?;
+            if destination.is_empty() || !destination.chars().all(|c| c.is_alphabetic()) {
+                return Err("Destination must be made up of only letters".into());
+            }
+
+            println!("Enter departure time (YYYY-MM-DD HH:MM):");
+            let departure_time =
+                convert_datetime_input_to_unix_time(&std_in_reader.next().unwrap()?)?;
+
+            let mut cabins = Vec::with_capacity(SEAT_CLASSES.len());
+            for (_, seat_class_name) in SEAT_CLASSES {
+                println!("Enter number of {seat_class_name} seats:");
+                let seats = std_in_reader
+                    .next()
+                    .unwrap()?
+                    .parse::<u32>()
+                    .map_err(|_| "Invalid number of seats")?;
+
+                println!("Enter {seat_class_name} airfare with its currency, e.g. 10.10 SGD:");
+                let airfare = parse_money(&std_in_reader.next().unwrap()?)?;
+
+                cabins.push((seats, airfare));
+            }
+
+            println!("Enter baggage capacity in kg:");
+            let baggage_capacity_kg = std_in_reader
+                .next()
+                .unwrap()?
+                .parse::<u32>()
+                .map_err(|_| "Invalid baggage capacity")?;
+
+            marshal_string(&source, &mut buffer_to_send);
+            marshal_string(&destination, &mut buffer_to_send);
+            marshal_u32(departure_time, &mut buffer_to_send);
+            for (seats, airfare) in cabins {
+                marshal_u32(seats, &mut buffer_to_send);
+                marshal_money(airfare, &mut buffer_to_send);
+            }
+            marshal_u32(baggage_capacity_kg, &mut buffer_to_send);
         }
-    };
+        2 => {
+            println!("Enter flight identifier:");
+            let flight_id = std_in_reader
+                .next()
+                .unwrap()?
+                .parse::<u32>()
+                .map_err(|_| "Invalid flight identifier")?;
+
+            println!("Leave any field empty to keep it unchanged.");
+
+            println!("Enter new departure time (YYYY-MM-DD HH:MM):");
+            let departure_time = match read_optional_input(std_in_reader)? {
+                Some(input) => Some(convert_datetime_input_to_unix_time(&input)?),
+                None => None,
+            };
 
-    // Convert the number of baggage weight to a u32
-    let baggage_weight = match baggage_weight.parse::<u32>() {
-        Ok(baggage_weight) => baggage_weight,
-        Err(_) => {
-            println!("Invalid baggage weight");
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "Invalid baggage weight",
-            )));
+            let mut cabin_changes = Vec::with_capacity(SEAT_CLASSES.len());
+            for (_, seat_class_name) in SEAT_CLASSES {
+                println!("Enter new {seat_class_name} airfare with its currency, e.g. 10.10 SGD:");
+                let airfare = match read_optional_input(std_in_reader)? {
+                    Some(input) => Some(parse_money(&input)?),
+                    None => None,
+                };
+
+                println!("Enter new number of physical {seat_class_name} seats:");
+                let seats = match read_optional_input(std_in_reader)? {
+                    Some(input) => Some(
+                        input
+                            .parse::<u32>()
+                            .map_err(|_| "Invalid number of seats")?,
+                    ),
+                    None => None,
+                };
+
+                cabin_changes.push((airfare, seats));
+            }
+
+            println!("Enter new baggage capacity in kg:");
+            let baggage_capacity_kg = match read_optional_input(std_in_reader)? {
+                Some(input) => Some(
+                    input
+                        .parse::<u32>()
+                        .map_err(|_| "Invalid baggage capacity")?,
+                ),
+                None => None,
+            };
+
+            marshal_u32(flight_id, &mut buffer_to_send);
+            marshal_optional_u32(departure_time, &mut buffer_to_send);
+            for (airfare, seats) in cabin_changes {
+                marshal_optional_money(airfare, &mut buffer_to_send);
+                marshal_optional_u32(seats, &mut buffer_to_send);
+            }
+            println!("Enter new overbooking percentage:");
+            let overbooking_percent = match read_optional_input(std_in_reader)? {
+                Some(input) => Some(
+                    input
+                        .parse::<u32>()
+                        .map_err(|_| "Invalid overbooking percentage")?,
+                ),
+                None => None,
+            };
+
+            println!("Enter new departure gate:");
+            let gate = read_optional_input(std_in_reader)?;
+            if gate.as_ref().is_some_and(|gate| gate.len() > 255) {
+                return Err("Gate is too long".into());
+            }
+
+            marshal_optional_u32(baggage_capacity_kg, &mut buffer_to_send);
+            marshal_optional_u32(overbooking_percent, &mut buffer_to_send);
+            marshal_optional_string(gate.as_deref(), &mut buffer_to_send);
         }
-    };
+        3 => {
+            println!("Enter flight identifier:");
+            let flight_id = std_in_reader
+                .next()
+                .unwrap()?
+                .parse::<u32>()
+                .map_err(|_| "Invalid flight identifier")?;
+
+            marshal_u32(flight_id, &mut buffer_to_send);
+        }
+        5 => {
+            println!("Enter flight identifier:");
+            let flight_id = std_in_reader
+                .next()
+                .unwrap()?
+                .parse::<u32>()
+                .map_err(|_| "Invalid flight identifier")?;
+
+            println!("Enter the seat letters of a row with a space for each aisle, e.g. ABC DEF:");
+            let letters = std_in_reader.next().unwrap()?.trim().to_ascii_uppercase();
+            if letters.is_empty() || letters.len() > 255 {
+                return Err("Seat letters must be between 1 and 255 characters".into());
+            }
 
-    // Ensure baggage weight is less than or equal to 40kg
-    if baggage_weight > 40 {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::InvalidInput,
-            "Baggage weight must be less than or equal to 40kg",
-        )));
-    }
+            marshal_u32(flight_id, &mut buffer_to_send);
+            marshal_string(&letters, &mut buffer_to_send);
+
+            // Rows are numbered from the front, business first.
+            for seat_class in [2, 1, 0] {
+                println!("Enter number of {} rows:", seat_class_name(seat_class));
+                let num_rows = std_in_reader
+                    .next()
+                    .unwrap()?
+                    .parse::<u32>()
+                    .map_err(|_| "Invalid number of rows")?;
+                marshal_u32(num_rows, &mut buffer_to_send);
+            }
 
-    // Create a buffer to store the data to send with capacity 2048 bytes
-    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+            println!("Enter exit rows separated by spaces (leave empty for none):");
+            let exit_rows = std_in_reader
+                .next()
+                .unwrap()?
+                .split_whitespace()
+                .map(|row| row.parse::<u32>())
+                .collect::<Result<Vec<u32>, _>>()
+                .map_err(|_| "Invalid exit row")?;
+            if exit_rows.len() > 255 {
+                return Err("Too many exit rows".into());
+            }
+            marshal_u32_array(&exit_rows, &mut buffer_to_send);
+
+            println!("Enter seats that cannot be reserved separated by spaces, e.g. 4D (leave empty for none):");
+            let blocked = std_in_reader
+                .next()
+                .unwrap()?
+                .split_whitespace()
+                .map(|seat| seat.to_ascii_uppercase())
+                .collect::<Vec<String>>();
+            if blocked.len() > 255 {
+                return Err("Too many blocked seats".into());
+            }
+            marshal_u8(blocked.len() as u8, &mut buffer_to_send);
+            for seat in blocked.iter() {
+                marshal_string(seat, &mut buffer_to_send);
+            }
+        }
+        4 => {
+            println!("Enter flight identifier:");
+            let flight_id = std_in_reader
+                .next()
+                .unwrap()?
+                .parse::<u32>()
+                .map_err(|_| "Invalid flight identifier")?;
+
+            println!("Enter new status: 1 = scheduled, 2 = boarding, 3 = departed, 4 = delayed, 5 = cancelled, 6 = arrived");
+            let status = match std_in_reader.next().unwrap()?.parse::<u8>() {
+                Ok(status) if (1..=6).contains(&status) => status,
+                _ => return Err("Status must be between 1 and 6".into()),
+            };
 
-    // Add service ID as first byte
-    marshal_u8(RESERVE_BAGGAGE_SERVICE_ID, &mut buffer_to_send);
+            // Only a delay can move the departure time.
+            let departure_time = if status == 4 {
+                println!("Enter new departure time (YYYY-MM-DD HH:MM, leave empty to keep it):");
+                match read_optional_input(std_in_reader)? {
+                    Some(input) => Some(convert_datetime_input_to_unix_time(&input)?),
+                    None => None,
+                }
+            } else {
+                None
+            };
 
-    // Add flight ID
-    marshal_u32(flight_id, &mut buffer_to_send);
+            marshal_u32(flight_id, &mut buffer_to_send);
+            marshal_u8(status, &mut buffer_to_send);
+            marshal_optional_u32(departure_time, &mut buffer_to_send);
+        }
+        6 => {
+            println!("Enter flight identifier:");
+            let flight_id = std_in_reader
+                .next()
+                .unwrap()?
+                .parse::<u32>()
+                .map_err(|_| "Invalid flight identifier")?;
+
+            println!("Choose who is bumped from an overbooked cabin: 0 = latest booking first (default), 1 = lowest fare first");
+            let policy = match read_optional_input(std_in_reader)? {
+                Some(input) => match input.parse::<u8>() {
+                    Ok(policy) if policy <= 1 => policy,
+                    _ => return Err("Bump policy must be 0 or 1".into()),
+                },
+                None => 0,
+            };
+
+            marshal_u32(flight_id, &mut buffer_to_send);
+            marshal_u8(policy, &mut buffer_to_send);
+        }
+        _ => {
+            println!("Enter flight identifier:");
+            let flight_id = std_in_reader
+                .next()
+                .unwrap()?
+                .parse::<u32>()
+                .map_err(|_| "Invalid flight identifier")?;
+
+            println!("Choose a format: 0 = CSV (default), 1 = JSON");
+            let format = match read_optional_input(std_in_reader)? {
+                Some(input) => match input.parse::<u8>() {
+                    Ok(format) if format <= 1 => format,
+                    _ => return Err("Format must be 0 or 1".into()),
+                },
+                None => 0,
+            };
+
+            // Long manifests are sent a page at a time.
+            println!("Enter the passenger to start from (default 1):");
+            let start = match read_optional_input(std_in_reader)? {
+                Some(input) => match input.parse::<u32>() {
+                    Ok(start) if start >= 1 => start,
+                    _ => return Err("Passenger must be at least 1".into()),
+                },
+                None => 1,
+            };
 
-    // Add baggage weight
-    marshal_u32(baggage_weight, &mut buffer_to_send);
+            marshal_u32(flight_id, &mut buffer_to_send);
+            marshal_u8(format, &mut buffer_to_send);
+            marshal_u32(start - 1, &mut buffer_to_send);
+        }
+    }
 
     // Return the buffer
     Ok(buffer_to_send)
 }
 
+// The session for services that act for a user, or an error if the user has not logged in.
+fn require_login(session: Option<&Session>) -> Result<&Session, Box<dyn Error>> {
+    session.ok_or_else(|| "Log in first (service 19) to use this service".into())
+}
+
+// Adds the session token and the traveller being acted for, if any.
+fn marshal_session(session: &Session, buffer_to_send: &mut Vec<u8>) {
+    marshal_string(&session.token, buffer_to_send);
+    marshal_optional_string(session.acting_for.as_deref(), buffer_to_send);
+}
+
+// Reads a line from stdin, returning None if the user left it empty.
+fn read_optional_input(
+    std_in_reader: &mut Lines<StdinLock>,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let input = std_in_reader.next().unwrap()?;
+    let input = input.trim();
+    if input.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(input.to_string()))
+    }
+}
+
+pub fn convert_datetime_input_to_unix_time(input: &str) -> Result<u32, Box<dyn Error>> {
+    let naive = NaiveDateTime::parse_from_str(input, "%Y-%m-%d %H:%M")
+        .map_err(|_| "Date and time must be in the format YYYY-MM-DD HH:MM")?;
+    let datetime = Local
+        .from_local_datetime(&naive)
+        .single()
+        .ok_or("Date and time is ambiguous in the local time zone")?;
+    u32::try_from(datetime.timestamp()).map_err(|_| "Date and time is out of range".into())
+}
+
+// Reads an amount followed by its currency code, e.g. "10.10 SGD".
+fn parse_money(input: &str) -> Result<Money, Box<dyn Error>> {
+    let (amount, currency) = input
+        .trim()
+        .rsplit_once(' ')
+        .ok_or("Airfare must be an amount followed by its currency, e.g. 10.10 SGD")?;
+    let currency = Currency::parse(&currency.to_ascii_uppercase())
+        .ok_or("Currency must be a three letter code, e.g. SGD")?;
+    Money::parse(amount.trim(), currency).ok_or_else(|| {
+        format!(
+            "Invalid airfare. {currency} amounts have at most {} decimal places",
+            currency.minor_unit_digits()
+        )
+        .into()
+    })
+}
+
+// Reads the currency to show fares in, or None for the currency the server prices flights in.
+fn read_display_currency(
+    std_in_reader: &mut Lines<StdinLock>,
+) -> Result<Option<String>, Box<dyn Error>> {
+    println!("Enter currency to show fares in, e.g. USD (leave empty for the server's currency):");
+    match read_optional_input(std_in_reader)? {
+        Some(input) => {
+            let currency = Currency::parse(&input.to_ascii_uppercase())
+                .ok_or("Currency must be a three letter code, e.g. USD")?;
+            Ok(Some(currency.to_string()))
+        }
+        None => Ok(None),
+    }
+}
+
+// Reads the quote ID from a flight summary, or None to book at the current fare.
+fn read_quote_id(std_in_reader: &mut Lines<StdinLock>) -> Result<Option<u32>, Box<dyn Error>> {
+    println!("Enter quote ID from the flight summary (leave empty to book at the current fare):");
+    match read_optional_input(std_in_reader)? {
+        Some(input) => Ok(Some(input.parse::<u32>().map_err(|_| "Invalid quote ID")?)),
+        None => Ok(None),
+    }
+}
+
+// Reads a seat class, defaulting to economy if the user left it empty.
+fn read_seat_class(std_in_reader: &mut Lines<StdinLock>) -> Result<u8, Box<dyn Error>> {
+    match read_optional_input(std_in_reader)? {
+        Some(input) => parse_seat_class(&input),
+        None => Ok(0),
+    }
+}
+
+// Accepts either the number or the name of a seat class.
+fn parse_seat_class(input: &str) -> Result<u8, Box<dyn Error>> {
+    SEAT_CLASSES
+        .iter()
+        .find(|(seat_class, name)| {
+            input == seat_class.to_string() || input.eq_ignore_ascii_case(name)
+        })
+        .map(|(seat_class, _)| *seat_class)
+        .ok_or_else(|| "Seat class must be 0 (economy), 1 (premium) or 2 (business)".into())
+}
+
+// Reads money from the server. An amount in a currency code the client cannot read is shown in XXX, the ISO
+// 4217 code for no currency, instead of failing the whole response.
+fn unmarshal_money(buf: &[u8], i: usize) -> (Money, usize) {
+    match marshaling::unmarshal_money(buf, i) {
+        (Ok(money), j) => (money, j),
+        (Err(_), j) => {
+            let minor_units = i64::from_be_bytes(buf[i..i + 8].try_into().unwrap());
+            (Money::new(minor_units, Currency::parse("XXX").unwrap()), j)
+        }
+    }
+}
+
+pub fn seat_class_name(seat_class: u8) -> &'static str {
+    SEAT_CLASSES
+        .iter()
+        .find(|(id, _)| *id == seat_class)
+        .map_or("unknown", |(_, name)| name)
+}
+
+pub fn role_name(role: u8) -> &'static str {
+    match role {
+        0 => "traveller",
+        1 => "agent",
+        2 => "admin",
+        _ => "unknown role",
+    }
+}
+
+pub fn flight_status_name(status: u8) -> &'static str {
+    match status {
+        1 => "scheduled",
+        2 => "boarding",
+        3 => "departed",
+        4 => "delayed",
+        5 => "cancelled",
+        6 => "arrived",
+        _ => "unknown",
+    }
+}
+
 pub fn convert_unix_time_to_datetime(timestamp: u32) -> DateTime<Local> {
     let naive = NaiveDateTime::from_timestamp_opt(timestamp as i64, 0).unwrap();
     Local.from_local_datetime(&naive).unwrap()
@@ -586,4 +3621,4 @@ pub fn print_padded_string(s: &str) {
     let padding = "=".repeat(padding_len);
 
     println!("\n{}{}{}", padding, s, padding);
-}
\ No newline at end of file
+}
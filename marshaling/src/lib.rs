@@ -1,8 +1,18 @@
+mod money;
+
+pub use money::{Currency, Money};
+
 pub fn marshal_string(string: &str, buf: &mut Vec<u8>) {
     buf.extend_from_slice(&(string.len() as u8).to_be_bytes());
     buf.extend_from_slice(string.as_bytes());
 }
 
+// For text that can be longer than 255 bytes, prefixed by a u16 length instead.
+pub fn marshal_long_string(string: &str, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&(string.len() as u16).to_be_bytes());
+    buf.extend_from_slice(string.as_bytes());
+}
+
 pub fn marshal_u8(number: u8, buf: &mut Vec<u8>) {
     buf.push(number);
 }
@@ -11,10 +21,6 @@ pub fn marshal_u32(number: u32, buf: &mut Vec<u8>) {
     buf.extend_from_slice(&number.to_be_bytes());
 }
 
-pub fn marshal_f32(number: f32, buf: &mut Vec<u8>) {
-    buf.extend_from_slice(&number.to_be_bytes());
-}
-
 pub fn marshal_u32_array(numbers: &[u32], buf: &mut Vec<u8>) {
     buf.extend_from_slice(&(numbers.len() as u8).to_be_bytes());
     for number in numbers {
@@ -22,41 +28,74 @@ pub fn marshal_u32_array(numbers: &[u32], buf: &mut Vec<u8>) {
     }
 }
 
+// Optional values are prefixed with a presence byte: 0 for None, 1 for Some followed by the value.
+pub fn marshal_optional_string(string: Option<&str>, buf: &mut Vec<u8>) {
+    match string {
+        Some(string) => {
+            buf.push(1);
+            marshal_string(string, buf);
+        }
+        None => buf.push(0),
+    }
+}
+
+pub fn marshal_optional_u8(number: Option<u8>, buf: &mut Vec<u8>) {
+    match number {
+        Some(number) => {
+            buf.push(1);
+            marshal_u8(number, buf);
+        }
+        None => buf.push(0),
+    }
+}
+
+pub fn marshal_optional_u32(number: Option<u32>, buf: &mut Vec<u8>) {
+    match number {
+        Some(number) => {
+            buf.push(1);
+            marshal_u32(number, buf);
+        }
+        None => buf.push(0),
+    }
+}
+
 pub fn unmarshal_string(buf: &[u8], mut i: usize) -> (String, usize) {
     // First read the first byte to determine length of string
     let string_length: usize = buf[i].into();
     i += 1;
 
     // Then read the string from utf
-    let my_string = String::from_utf8_lossy(&buf[i..i+string_length]).to_string();
+    let my_string = String::from_utf8_lossy(&buf[i..i + string_length]).to_string();
+    i += string_length;
+
+    (my_string, i)
+}
+
+pub fn unmarshal_long_string(buf: &[u8], mut i: usize) -> (String, usize) {
+    // First read the first two bytes to determine length of string
+    let string_length: usize = u16::from_be_bytes([buf[i], buf[i + 1]]).into();
+    i += 2;
+
+    // Then read the string from utf
+    let my_string = String::from_utf8_lossy(&buf[i..i + string_length]).to_string();
     i += string_length;
 
-    return (my_string, i);
+    (my_string, i)
 }
 
 pub fn unmarshal_u8(buf: &[u8], mut i: usize) -> (u8, usize) {
     let my_u8 = buf[i];
     i += 1;
 
-    return (my_u8, i);
+    (my_u8, i)
 }
 
-
 pub fn unmarshal_u32(buf: &[u8], mut i: usize) -> (u32, usize) {
     // Then read the u32
-    let my_u32 = u32::from_be_bytes([buf[i], buf[i+1], buf[i+2], buf[i+3]]);
+    let my_u32 = u32::from_be_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]]);
     i += 4;
 
-    return (my_u32, i);
-}
-
-
-pub fn unmarshal_f32(buf: &[u8], mut i: usize) -> (f32, usize) {
-    // Then read the f32
-    let my_f32 = f32::from_be_bytes([buf[i], buf[i+1], buf[i+2], buf[i+3]]);
-    i += 4;
-
-    return (my_f32, i);
+    (my_u32, i)
 }
 
 pub fn unmarshal_u32_array(buf: &[u8], mut i: usize) -> (Vec<u32>, usize) {
@@ -67,10 +106,118 @@ pub fn unmarshal_u32_array(buf: &[u8], mut i: usize) -> (Vec<u32>, usize) {
     // Then read the array
     let mut my_array: Vec<u32> = Vec::with_capacity(array_length.into());
     for _ in 0..array_length {
-        let my_u32 = u32::from_be_bytes([buf[i], buf[i+1], buf[i+2], buf[i+3]]);
+        let my_u32 = u32::from_be_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]]);
         i += 4;
         my_array.push(my_u32);
     }
 
-    return (my_array, i);
-}
\ No newline at end of file
+    (my_array, i)
+}
+
+pub fn unmarshal_optional_string(buf: &[u8], i: usize) -> (Option<String>, usize) {
+    let (is_present, i) = unmarshal_u8(buf, i);
+    if is_present == 0 {
+        return (None, i);
+    }
+    let (my_string, i) = unmarshal_string(buf, i);
+    (Some(my_string), i)
+}
+
+pub fn unmarshal_optional_u8(buf: &[u8], i: usize) -> (Option<u8>, usize) {
+    let (is_present, i) = unmarshal_u8(buf, i);
+    if is_present == 0 {
+        return (None, i);
+    }
+    let (my_u8, i) = unmarshal_u8(buf, i);
+    (Some(my_u8), i)
+}
+
+pub fn unmarshal_optional_u32(buf: &[u8], i: usize) -> (Option<u32>, usize) {
+    let (is_present, i) = unmarshal_u8(buf, i);
+    if is_present == 0 {
+        return (None, i);
+    }
+    let (my_u32, i) = unmarshal_u32(buf, i);
+    (Some(my_u32), i)
+}
+
+pub fn marshal_money(money: Money, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&money.minor_units.to_be_bytes());
+    buf.extend_from_slice(&money.currency.as_bytes());
+}
+
+pub fn marshal_optional_money(money: Option<Money>, buf: &mut Vec<u8>) {
+    match money {
+        Some(money) => {
+            buf.push(1);
+            marshal_money(money, buf);
+        }
+        None => buf.push(0),
+    }
+}
+
+// Panics like the other unmarshalers if the buffer is too short. The currency code comes from the sender, so
+// a code that is not three upper case letters is an error instead. The index moves past the money either way.
+pub fn unmarshal_money(buf: &[u8], mut i: usize) -> (Result<Money, String>, usize) {
+    let minor_units = i64::from_be_bytes(buf[i..i + 8].try_into().unwrap());
+    i += 8;
+    let currency = Currency::from_bytes([buf[i], buf[i + 1], buf[i + 2]]);
+    i += 3;
+
+    match currency {
+        Some(currency) => (Ok(Money::new(minor_units, currency)), i),
+        None => (
+            Err("A currency code must be three upper case letters.".to_string()),
+            i,
+        ),
+    }
+}
+
+pub fn unmarshal_optional_money(buf: &[u8], i: usize) -> (Result<Option<Money>, String>, usize) {
+    let (is_present, i) = unmarshal_u8(buf, i);
+    if is_present == 0 {
+        return (Ok(None), i);
+    }
+    let (money, i) = unmarshal_money(buf, i);
+    (money.map(Some), i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn money_round_trips() {
+        let money = Money::new(-1010, Currency::parse("SGD").unwrap());
+        let mut buf = vec![];
+        marshal_money(money, &mut buf);
+        marshal_optional_money(None, &mut buf);
+        marshal_optional_money(Some(money), &mut buf);
+
+        let (unmarshaled, i) = unmarshal_money(&buf, 0);
+        assert_eq!(unmarshaled, Ok(money));
+        let (unmarshaled, i) = unmarshal_optional_money(&buf, i);
+        assert_eq!(unmarshaled, Ok(None));
+        let (unmarshaled, i) = unmarshal_optional_money(&buf, i);
+        assert_eq!(unmarshaled, Ok(Some(money)));
+        assert_eq!(i, buf.len());
+    }
+
+    #[test]
+    fn invalid_currency_is_an_error_instead_of_a_panic() {
+        let mut buf = vec![];
+        marshal_u32(0, &mut buf);
+        marshal_u32(1000, &mut buf);
+        buf.extend_from_slice(b"s\xffD");
+        marshal_u8(7, &mut buf);
+
+        let (money, i) = unmarshal_money(&buf, 0);
+        assert!(money.is_err());
+        // The rest of the buffer can still be read.
+        assert_eq!(unmarshal_u8(&buf, i), (7, buf.len()));
+
+        let mut optional = vec![1];
+        optional.extend_from_slice(&buf);
+        assert!(unmarshal_optional_money(&optional, 0).0.is_err());
+    }
+}
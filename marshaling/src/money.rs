@@ -0,0 +1,205 @@
+use std::fmt;
+
+// An ISO 4217 currency code, e.g. SGD.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    // Accepts three upper case letters.
+    pub fn parse(code: &str) -> Option<Currency> {
+        let bytes: [u8; 3] = code.as_bytes().try_into().ok()?;
+        if bytes.iter().all(|byte| byte.is_ascii_uppercase()) {
+            Some(Currency(bytes))
+        } else {
+            None
+        }
+    }
+
+    pub fn from_bytes(bytes: [u8; 3]) -> Option<Currency> {
+        Currency::parse(std::str::from_utf8(&bytes).ok()?)
+    }
+
+    pub fn as_bytes(&self) -> [u8; 3] {
+        self.0
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only ever built from ASCII letters.
+        std::str::from_utf8(&self.0).unwrap()
+    }
+
+    // The number of digits after the decimal point in amounts of the currency, e.g. 2 for cents. Most
+    // currencies have 2; the exceptions below are the ISO 4217 currencies with 0 or 3.
+    pub fn minor_unit_digits(&self) -> u32 {
+        match self.as_str() {
+            "BIF" | "CLP" | "DJF" | "GNF" | "ISK" | "JPY" | "KMF" | "KRW" | "PYG" | "RWF"
+            | "UGX" | "UYI" | "VND" | "VUV" | "XAF" | "XOF" | "XPF" => 0,
+            "BHD" | "IQD" | "JOD" | "KWD" | "LYD" | "OMR" | "TND" => 3,
+            _ => 2,
+        }
+    }
+
+    // The number of minor units in one unit of the currency, e.g. 100 cents in a dollar.
+    pub fn minor_units_per_unit(&self) -> i64 {
+        10_i64.pow(self.minor_unit_digits())
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+// An exact amount of money, counted in the minor unit of its currency, e.g. 1010 SGD cents for 10.10 SGD.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Money {
+    pub minor_units: i64,
+    pub currency: Currency,
+}
+
+impl Money {
+    pub fn new(minor_units: i64, currency: Currency) -> Money {
+        Money {
+            minor_units,
+            currency,
+        }
+    }
+
+    // Parses a decimal amount such as "10", "10.1" or "10.10" exactly. Fails if the amount has more digits
+    // after the decimal point than the currency has minor units.
+    pub fn parse(amount: &str, currency: Currency) -> Option<Money> {
+        let (is_negative, amount) = match amount.strip_prefix('-') {
+            Some(amount) => (true, amount),
+            None => (false, amount),
+        };
+        let (units, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+        let digits = currency.minor_unit_digits() as usize;
+        if units.is_empty()
+            || fraction.len() > digits
+            || !units
+                .chars()
+                .chain(fraction.chars())
+                .all(|c| c.is_ascii_digit())
+        {
+            return None;
+        }
+
+        let minor_units = units
+            .parse::<i64>()
+            .ok()?
+            .checked_mul(currency.minor_units_per_unit())?
+            .checked_add(format!("{fraction:0<digits$}").parse::<i64>().unwrap_or(0))?;
+        Some(Money::new(
+            if is_negative {
+                -minor_units
+            } else {
+                minor_units
+            },
+            currency,
+        ))
+    }
+
+    // Multiplies the amount by the factor, rounding to the nearest minor unit.
+    pub fn scale(&self, factor: f64) -> Money {
+        Money::new(
+            (self.minor_units as f64 * factor).round() as i64,
+            self.currency,
+        )
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.minor_units < 0
+    }
+}
+
+impl fmt::Display for Money {
+    // Shows the amount with every minor unit digit, e.g. "10.10 SGD" or "1200 JPY".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.is_negative() { "-" } else { "" };
+        let minor_units = self.minor_units.unsigned_abs();
+        let per_unit = self.currency.minor_units_per_unit() as u64;
+        match self.currency.minor_unit_digits() as usize {
+            0 => write!(f, "{sign}{minor_units} {}", self.currency),
+            digits => write!(
+                f,
+                "{sign}{}.{:0digits$} {}",
+                minor_units / per_unit,
+                minor_units % per_unit,
+                self.currency
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn currency(code: &str) -> Currency {
+        Currency::parse(code).unwrap()
+    }
+
+    #[test]
+    fn currency_codes_are_three_upper_case_letters() {
+        assert_eq!(currency("SGD").as_str(), "SGD");
+        assert_eq!(Currency::parse("sgd"), None);
+        assert_eq!(Currency::parse("SG"), None);
+        assert_eq!(Currency::parse("SGDX"), None);
+        assert_eq!(Currency::parse("S1D"), None);
+        assert_eq!(Currency::from_bytes([b'U', b'S', 0xff]), None);
+    }
+
+    #[test]
+    fn amounts_are_parsed_exactly() {
+        let sgd = currency("SGD");
+        assert_eq!(Money::parse("10", sgd), Some(Money::new(1000, sgd)));
+        assert_eq!(Money::parse("10.1", sgd), Some(Money::new(1010, sgd)));
+        assert_eq!(Money::parse("10.10", sgd), Some(Money::new(1010, sgd)));
+        assert_eq!(Money::parse("0.07", sgd), Some(Money::new(7, sgd)));
+        assert_eq!(Money::parse("-2.50", sgd), Some(Money::new(-250, sgd)));
+        assert_eq!(
+            Money::parse("1200", currency("JPY")),
+            Some(Money::new(1200, currency("JPY")))
+        );
+        assert_eq!(
+            Money::parse("1.234", currency("KWD")),
+            Some(Money::new(1234, currency("KWD")))
+        );
+    }
+
+    #[test]
+    fn invalid_amounts_are_rejected() {
+        let sgd = currency("SGD");
+        // More digits after the decimal point than the currency has minor units.
+        assert_eq!(Money::parse("10.101", sgd), None);
+        assert_eq!(Money::parse("1.5", currency("JPY")), None);
+        assert_eq!(Money::parse("", sgd), None);
+        assert_eq!(Money::parse(".5", sgd), None);
+        assert_eq!(Money::parse("--1", sgd), None);
+        assert_eq!(Money::parse("+1", sgd), None);
+        assert_eq!(Money::parse("1e3", sgd), None);
+        assert_eq!(Money::parse("1.2.3", sgd), None);
+        assert_eq!(Money::parse("99999999999999999999", sgd), None);
+    }
+
+    #[test]
+    fn amounts_show_every_minor_unit_digit() {
+        assert_eq!(Money::new(1010, currency("SGD")).to_string(), "10.10 SGD");
+        assert_eq!(Money::new(7, currency("SGD")).to_string(), "0.07 SGD");
+        assert_eq!(Money::new(-250, currency("SGD")).to_string(), "-2.50 SGD");
+        assert_eq!(Money::new(-7, currency("SGD")).to_string(), "-0.07 SGD");
+        assert_eq!(Money::new(1200, currency("JPY")).to_string(), "1200 JPY");
+        assert_eq!(Money::new(1234, currency("KWD")).to_string(), "1.234 KWD");
+    }
+
+    #[test]
+    fn scaling_rounds_to_the_nearest_minor_unit() {
+        let sgd = currency("SGD");
+        assert_eq!(Money::new(1000, sgd).scale(1.5), Money::new(1500, sgd));
+        assert_eq!(Money::new(1313, sgd).scale(0.5), Money::new(657, sgd));
+        assert_eq!(Money::new(999, sgd).scale(1.0 / 3.0), Money::new(333, sgd));
+        assert!(!Money::new(0, sgd).is_negative());
+        assert!(Money::new(-1, sgd).is_negative());
+    }
+}
@@ -0,0 +1,292 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fs,
+    net::SocketAddr,
+    ops::Range,
+};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const TAG_LENGTH: usize = 32;
+const MIN_KEY_LENGTH: usize = 16;
+
+// The timestamp of a datagram must be less than this far from the receiver's clock. Nonces are remembered
+// until the timestamp is this old, so a datagram replayed later is rejected by its timestamp instead.
+const MAX_CLOCK_SKEW_MS: u64 = 30_000;
+
+// Signs the datagrams this side sends and checks the ones it receives, with keys shared in advance between
+// the server and each client. An authenticated datagram is the key ID, the time it was sent in milliseconds,
+// a random nonce, the unauthenticated datagram and an HMAC-SHA256 tag over everything before the tag.
+pub struct Authenticator {
+    // Keys by key ID. A client only has its own.
+    keys: HashMap<String, Vec<u8>>,
+    // The key a client signs with. The server signs with the key each client last used.
+    own_key_id: Option<String>,
+    peer_key_ids: HashMap<SocketAddr, String>,
+    // Each key's recently seen nonces, and the same nonces ordered by when they can be forgotten.
+    seen_nonces: HashSet<(String, u64)>,
+    nonce_expiry: BTreeSet<(u64, String, u64)>,
+    rejected: u64,
+}
+
+impl Authenticator {
+    // The server side, which accepts datagrams signed with any of the keys.
+    pub fn for_server(keys: HashMap<String, Vec<u8>>) -> Authenticator {
+        Authenticator {
+            keys,
+            own_key_id: None,
+            peer_key_ids: HashMap::new(),
+            seen_nonces: HashSet::new(),
+            nonce_expiry: BTreeSet::new(),
+            rejected: 0,
+        }
+    }
+
+    // A client, which signs with its own key and only accepts datagrams signed with it.
+    pub fn for_client(key_id: &str, key: Vec<u8>) -> Authenticator {
+        Authenticator {
+            keys: HashMap::from([(key_id.to_string(), key)]),
+            own_key_id: Some(key_id.to_string()),
+            peer_key_ids: HashMap::new(),
+            seen_nonces: HashSet::new(),
+            nonce_expiry: BTreeSet::new(),
+            rejected: 0,
+        }
+    }
+
+    // How many datagrams have been rejected so far.
+    pub fn rejected_count(&self) -> u64 {
+        self.rejected
+    }
+
+    // Wraps a datagram to the address in an authenticated envelope. Fails if there is no key for the address,
+    // i.e. the server has not received an authenticated datagram from it.
+    pub fn seal(&self, addr: &SocketAddr, datagram: &[u8], now_ms: u64) -> Result<Vec<u8>, String> {
+        let key_id = self
+            .own_key_id
+            .as_ref()
+            .or_else(|| self.peer_key_ids.get(addr))
+            .ok_or_else(|| format!("No key is known for {addr}."))?;
+        let mut nonce = [0; 8];
+        getrandom::getrandom(&mut nonce).map_err(|error| error.to_string())?;
+
+        let mut sealed = Vec::with_capacity(datagram.len() + key_id.len() + 49);
+        sealed.push(key_id.len() as u8);
+        sealed.extend_from_slice(key_id.as_bytes());
+        sealed.extend_from_slice(&now_ms.to_be_bytes());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(datagram);
+        let tag = self.mac(key_id, &sealed).finalize().into_bytes();
+        sealed.extend_from_slice(&tag);
+        Ok(sealed)
+    }
+
+    // Checks an authenticated datagram from the address and returns where the datagram inside it is. A
+    // datagram is rejected if its key is unknown, its tag is wrong, it was sent too long ago or too far in the
+    // future, or its nonce was already seen. Rejections are counted.
+    pub fn open(
+        &mut self,
+        addr: &SocketAddr,
+        sealed: &[u8],
+        now_ms: u64,
+    ) -> Result<Range<usize>, String> {
+        let result = self.check(sealed, now_ms);
+        match &result {
+            Ok((key_id, _)) => {
+                if self.own_key_id.is_none() {
+                    self.peer_key_ids.insert(*addr, key_id.clone());
+                }
+            }
+            Err(_) => self.rejected += 1,
+        }
+        result.map(|(_, datagram)| datagram)
+    }
+
+    fn check(&mut self, sealed: &[u8], now_ms: u64) -> Result<(String, Range<usize>), String> {
+        let key_id_length = *sealed.first().ok_or("The datagram is empty.")? as usize;
+        let datagram_start = 1 + key_id_length + 16;
+        if sealed.len() < datagram_start + TAG_LENGTH {
+            return Err("The datagram is too short to be authenticated.".to_string());
+        }
+        let key_id = String::from_utf8_lossy(&sealed[1..1 + key_id_length]).to_string();
+        if !self.keys.contains_key(&key_id) {
+            return Err(format!("The key {key_id:?} is unknown."));
+        }
+
+        let tag_start = sealed.len() - TAG_LENGTH;
+        self.mac(&key_id, &sealed[..tag_start])
+            .verify_slice(&sealed[tag_start..])
+            .map_err(|_| format!("The tag is wrong for the key {key_id:?}."))?;
+
+        // Only a datagram with a valid tag is checked for replay, so that forged datagrams cannot fill up the
+        // nonces.
+        let timestamp_start = 1 + key_id_length;
+        let sent_at_ms = u64::from_be_bytes(
+            sealed[timestamp_start..timestamp_start + 8]
+                .try_into()
+                .unwrap(),
+        );
+        let nonce = u64::from_be_bytes(
+            sealed[timestamp_start + 8..datagram_start]
+                .try_into()
+                .unwrap(),
+        );
+        if sent_at_ms.abs_diff(now_ms) >= MAX_CLOCK_SKEW_MS {
+            return Err(format!(
+                "The datagram was sent {} ms from now, not less than the {MAX_CLOCK_SKEW_MS} ms allowed.",
+                sent_at_ms.abs_diff(now_ms)
+            ));
+        }
+        self.forget_nonces(now_ms);
+        if !self.seen_nonces.insert((key_id.clone(), nonce)) {
+            return Err(format!(
+                "The nonce was already used with the key {key_id:?}."
+            ));
+        }
+        self.nonce_expiry
+            .insert((sent_at_ms + MAX_CLOCK_SKEW_MS, key_id.clone(), nonce));
+
+        Ok((key_id, datagram_start..tag_start))
+    }
+
+    // Forgets the nonces of datagrams whose timestamps are now too old to be accepted anyway, oldest first.
+    fn forget_nonces(&mut self, now_ms: u64) {
+        while let Some((forget_at_ms, _, _)) = self.nonce_expiry.first() {
+            if *forget_at_ms > now_ms {
+                break;
+            }
+            let (_, key_id, nonce) = self.nonce_expiry.pop_first().unwrap();
+            self.seen_nonces.remove(&(key_id, nonce));
+        }
+    }
+
+    fn mac(&self, key_id: &str, message: &[u8]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.keys[key_id]).unwrap();
+        mac.update(message);
+        mac
+    }
+}
+
+// Loads a key file. Every line that is not blank or a comment gives a key ID and the key in hex, e.g.
+// "client-1 00112233445566778899aabbccddeeff". Keys are at least 16 bytes long. Comments start with '#'.
+pub fn load_key_file(path: &str) -> Result<HashMap<String, Vec<u8>>, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|error| format!("Could not read the key file {path}: {error}"))?;
+
+    let mut keys = HashMap::new();
+    for (line_index, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let line_error = |message: &str| format!("{path}:{}: {message}", line_index + 1);
+        let (key_id, key) = match line.split_whitespace().collect::<Vec<&str>>()[..] {
+            [key_id, key] => (key_id, key),
+            _ => return Err(line_error("Expected a key ID and a key.")),
+        };
+        if key_id.len() > u8::MAX as usize {
+            return Err(line_error("A key ID must be at most 255 bytes long."));
+        }
+        let key = from_hex(key)
+            .filter(|key| key.len() >= MIN_KEY_LENGTH)
+            .ok_or_else(|| {
+                line_error(&format!(
+                    "A key must be at least {MIN_KEY_LENGTH} bytes, written in hex."
+                ))
+            })?;
+        if keys.insert(key_id.to_string(), key).is_some() {
+            return Err(line_error("The key ID is given twice."));
+        }
+    }
+    Ok(keys)
+}
+
+// Decodes hex, e.g. a key or a signature. Returns None if it is not valid hex.
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW_MS: u64 = 1_700_000_000_000;
+
+    fn addr() -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], 9000))
+    }
+
+    fn authenticators() -> (Authenticator, Authenticator) {
+        let key = vec![7; MIN_KEY_LENGTH];
+        let server =
+            Authenticator::for_server(HashMap::from([("client-1".to_string(), key.clone())]));
+        (Authenticator::for_client("client-1", key), server)
+    }
+
+    #[test]
+    fn sealed_datagrams_open_once() {
+        let (client, mut server) = authenticators();
+        let sealed = client.seal(&addr(), b"hello", NOW_MS).unwrap();
+        let datagram = server.open(&addr(), &sealed, NOW_MS).unwrap();
+        assert_eq!(&sealed[datagram], b"hello");
+        assert!(server.open(&addr(), &sealed, NOW_MS).is_err());
+
+        let mut forged = client.seal(&addr(), b"hello", NOW_MS).unwrap();
+        let last = forged.len() - 1;
+        forged[last] ^= 1;
+        assert!(server.open(&addr(), &forged, NOW_MS).is_err());
+        assert_eq!(server.rejected_count(), 2);
+    }
+
+    #[test]
+    fn timestamps_are_accepted_just_less_than_the_skew_away() {
+        let (client, mut server) = authenticators();
+        for sent_at_ms in [
+            NOW_MS - MAX_CLOCK_SKEW_MS + 1,
+            NOW_MS + MAX_CLOCK_SKEW_MS - 1,
+        ] {
+            let sealed = client.seal(&addr(), b"hello", sent_at_ms).unwrap();
+            assert!(server.open(&addr(), &sealed, NOW_MS).is_ok());
+        }
+        for sent_at_ms in [NOW_MS - MAX_CLOCK_SKEW_MS, NOW_MS + MAX_CLOCK_SKEW_MS] {
+            let sealed = client.seal(&addr(), b"hello", sent_at_ms).unwrap();
+            assert!(server.open(&addr(), &sealed, NOW_MS).is_err());
+        }
+    }
+
+    #[test]
+    fn replays_are_rejected_until_the_timestamp_is_too_old() {
+        let (client, mut server) = authenticators();
+        let sealed = client.seal(&addr(), b"hello", NOW_MS).unwrap();
+        assert!(server.open(&addr(), &sealed, NOW_MS).is_ok());
+
+        // The nonce is still remembered at the last moment the timestamp is accepted.
+        let last_accepted_ms = NOW_MS + MAX_CLOCK_SKEW_MS - 1;
+        let other = client.seal(&addr(), b"other", last_accepted_ms).unwrap();
+        assert!(server.open(&addr(), &other, last_accepted_ms).is_ok());
+        assert!(server.open(&addr(), &sealed, last_accepted_ms).is_err());
+        assert_eq!(server.seen_nonces.len(), 2);
+
+        // Once it is forgotten, the timestamp rejects the replay instead.
+        let forgotten_ms = NOW_MS + MAX_CLOCK_SKEW_MS;
+        let other = client.seal(&addr(), b"other", forgotten_ms).unwrap();
+        assert!(server.open(&addr(), &other, forgotten_ms).is_ok());
+        let nonce_start = 1 + "client-1".len() + 8;
+        let nonce = u64::from_be_bytes(sealed[nonce_start..nonce_start + 8].try_into().unwrap());
+        assert!(!server
+            .seen_nonces
+            .contains(&("client-1".to_string(), nonce)));
+        assert!(server.open(&addr(), &sealed, forgotten_ms).is_err());
+        assert_eq!(server.seen_nonces.len(), 2);
+        assert_eq!(server.nonce_expiry.len(), 2);
+    }
+}
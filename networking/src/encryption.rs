@@ -0,0 +1,447 @@
+use std::{collections::HashMap, net::SocketAddr};
+
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Nonce,
+};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+// The first byte of every datagram, saying how the rest of it is carried. A client that never starts a
+// session sends and receives plaintext, which is easier to debug.
+pub const PLAINTEXT: u8 = 0;
+// A client's X25519 public key, asking the server to start a session.
+pub const HANDSHAKE_REQUEST: u8 = 1;
+// The server's X25519 public key, then the client's from the request it answers, starting the session.
+pub const HANDSHAKE_RESPONSE: u8 = 2;
+// A counter, then the datagram encrypted with ChaCha20-Poly1305 under the session key of the direction.
+pub const ENCRYPTED: u8 = 3;
+
+const PUBLIC_KEY_LENGTH: usize = 32;
+const COUNTER_LENGTH: usize = 8;
+// How many counters below the highest one received are still accepted. Datagrams can arrive out of order,
+// e.g. when a client sends from more than one thread, so any counter in this window that was not received
+// yet is accepted.
+const REPLAY_WINDOW: u64 = 64;
+
+// How many sessions the server keeps. When a new client starts one beyond this, the session used least
+// recently is forgotten, and that client's datagrams are dropped until it starts a new one.
+const MAX_SESSIONS: usize = 4096;
+
+// An encrypted session with one peer. Each direction has its own key, and every datagram sent in a direction
+// has a higher counter than the one before, which is used as the nonce. Each counter is only accepted once.
+struct Session {
+    sending: ChaCha20Poly1305,
+    receiving: ChaCha20Poly1305,
+    next_sent_counter: u64,
+    // The highest counter received, and which of the counters in the window below it were received, as
+    // bits: bit i is set if the highest counter minus i was.
+    highest_received_counter: Option<u64>,
+    received_counters: u64,
+    // When a datagram was last sent or received in the session, in uses of all sessions.
+    last_used: u64,
+}
+
+impl Session {
+    // Derives the key of each direction from the shared secret of the handshake and both public keys.
+    fn new(
+        secret: EphemeralSecret,
+        client_public: &PublicKey,
+        server_public: &PublicKey,
+        is_client: bool,
+    ) -> Result<Session, String> {
+        let their_public = if is_client {
+            server_public
+        } else {
+            client_public
+        };
+        let shared_secret = secret.diffie_hellman(their_public);
+        if !shared_secret.was_contributory() {
+            return Err("The handshake public key is not valid.".to_string());
+        }
+
+        let salt = [
+            client_public.as_bytes().as_slice(),
+            server_public.as_bytes(),
+        ]
+        .concat();
+        let hkdf = Hkdf::<Sha256>::new(Some(&salt), shared_secret.as_bytes());
+        let mut client_to_server = [0; 32];
+        let mut server_to_client = [0; 32];
+        hkdf.expand(b"client to server", &mut client_to_server)
+            .unwrap();
+        hkdf.expand(b"server to client", &mut server_to_client)
+            .unwrap();
+        let (sending, receiving) = if is_client {
+            (client_to_server, server_to_client)
+        } else {
+            (server_to_client, client_to_server)
+        };
+        Ok(Session {
+            sending: ChaCha20Poly1305::new(&sending.into()),
+            receiving: ChaCha20Poly1305::new(&receiving.into()),
+            next_sent_counter: 0,
+            highest_received_counter: None,
+            received_counters: 0,
+            last_used: 0,
+        })
+    }
+
+    // Whether the counter was not received yet and is not too old to tell.
+    fn is_new_counter(&self, counter: u64) -> bool {
+        match self.highest_received_counter {
+            None => true,
+            Some(highest) if counter > highest => true,
+            Some(highest) => {
+                let age = highest - counter;
+                age < REPLAY_WINDOW && self.received_counters & (1 << age) == 0
+            }
+        }
+    }
+
+    // Records a counter that was new, once its datagram has been decrypted.
+    fn receive_counter(&mut self, counter: u64) {
+        match self.highest_received_counter {
+            Some(highest) if counter <= highest => {
+                self.received_counters |= 1 << (highest - counter);
+            }
+            highest => {
+                let shift = highest.map_or(REPLAY_WINDOW, |highest| counter - highest);
+                self.received_counters = if shift < REPLAY_WINDOW {
+                    self.received_counters << shift
+                } else {
+                    0
+                };
+                self.received_counters |= 1;
+                self.highest_received_counter = Some(counter);
+            }
+        }
+    }
+}
+
+// The encrypted sessions of this side, by peer. The server has one for every client that started one, and a
+// client has at most one, with the server.
+pub struct Sessions {
+    sessions: HashMap<SocketAddr, Session>,
+    max_sessions: usize,
+    // How many datagrams have been sent or received in all sessions, to order them by when they were used.
+    uses: u64,
+}
+
+impl Sessions {
+    pub fn new() -> Sessions {
+        Sessions {
+            sessions: HashMap::new(),
+            max_sessions: MAX_SESSIONS,
+            uses: 0,
+        }
+    }
+
+    // Whether there is a session with the peer, so that its datagrams must be encrypted.
+    pub fn has_session(&self, addr: &SocketAddr) -> bool {
+        self.sessions.contains_key(addr)
+    }
+
+    // The first half of a client's handshake: a new key pair, and the request to send with its public key.
+    pub fn start_handshake() -> (EphemeralSecret, Vec<u8>) {
+        let secret = EphemeralSecret::random();
+        let mut request = vec![HANDSHAKE_REQUEST];
+        request.extend_from_slice(PublicKey::from(&secret).as_bytes());
+        (secret, request)
+    }
+
+    // The second half of a client's handshake: starts the session with the server from its response, which
+    // must answer the request with the given public key.
+    pub fn finish_handshake(
+        &mut self,
+        server_addr: &SocketAddr,
+        secret: EphemeralSecret,
+        request_public_key: &[u8],
+        response: &[u8],
+    ) -> Result<(), String> {
+        let client_public = PublicKey::from(&secret);
+        if response.len() != 1 + 2 * PUBLIC_KEY_LENGTH
+            || response[1 + PUBLIC_KEY_LENGTH..] != *request_public_key
+        {
+            return Err("The handshake response is for a different request.".to_string());
+        }
+        let server_public =
+            read_public_key(&response[..1 + PUBLIC_KEY_LENGTH], HANDSHAKE_RESPONSE)?;
+        let session = Session::new(secret, &client_public, &server_public, true)?;
+        self.insert(*server_addr, session);
+        Ok(())
+    }
+
+    // The server's side of a handshake: starts a session with the client and returns the response to send
+    // back. Anyone can send a handshake from the client's address unless the request was authenticated, so
+    // a session the client has already used is only replaced by an authenticated handshake. A session the
+    // client has not used yet is replaced, since the client sends its handshake again if the response is lost.
+    pub fn accept_handshake(
+        &mut self,
+        client_addr: &SocketAddr,
+        request: &[u8],
+        authenticated: bool,
+    ) -> Result<Vec<u8>, String> {
+        let client_public = read_public_key(request, HANDSHAKE_REQUEST)?;
+        if !authenticated
+            && self
+                .sessions
+                .get(client_addr)
+                .is_some_and(|session| session.highest_received_counter.is_some())
+        {
+            return Err(
+                "There is already a session with the sender, which only an authenticated handshake can replace."
+                    .to_string(),
+            );
+        }
+        let secret = EphemeralSecret::random();
+        let server_public = PublicKey::from(&secret);
+        let session = Session::new(secret, &client_public, &server_public, false)?;
+        self.insert(*client_addr, session);
+
+        let mut response = vec![HANDSHAKE_RESPONSE];
+        response.extend_from_slice(server_public.as_bytes());
+        response.extend_from_slice(client_public.as_bytes());
+        Ok(response)
+    }
+
+    // Adds the header byte to a datagram to the peer, encrypting it if there is a session with the peer.
+    pub fn wrap(&mut self, addr: &SocketAddr, datagram: &[u8]) -> Vec<u8> {
+        let session = match self.sessions.get_mut(addr) {
+            Some(session) => session,
+            None => return [&[PLAINTEXT], datagram].concat(),
+        };
+        let counter = session.next_sent_counter;
+        session.next_sent_counter += 1;
+        self.uses += 1;
+        session.last_used = self.uses;
+
+        let mut wrapped = vec![ENCRYPTED];
+        wrapped.extend_from_slice(&counter.to_be_bytes());
+        let ciphertext = session
+            .sending
+            .encrypt(
+                &nonce(counter),
+                Payload {
+                    msg: datagram,
+                    aad: &wrapped,
+                },
+            )
+            .expect("Encryption failed");
+        wrapped.extend_from_slice(&ciphertext);
+        wrapped
+    }
+
+    // Decrypts an encrypted datagram from the peer, i.e. one starting with `ENCRYPTED`.
+    pub fn unwrap(&mut self, addr: &SocketAddr, wrapped: &[u8]) -> Result<Vec<u8>, String> {
+        let session = self
+            .sessions
+            .get_mut(addr)
+            .ok_or("The datagram is encrypted, but there is no session with the sender.")?;
+        let header_length = 1 + COUNTER_LENGTH;
+        if wrapped.len() < header_length {
+            return Err("The encrypted datagram is too short.".to_string());
+        }
+        let counter = u64::from_be_bytes(wrapped[1..header_length].try_into().unwrap());
+        if !session.is_new_counter(counter) {
+            return Err(format!(
+                "The counter {counter} was already received or is too old, so the datagram is a replay."
+            ));
+        }
+
+        let datagram = session
+            .receiving
+            .decrypt(
+                &nonce(counter),
+                Payload {
+                    msg: &wrapped[header_length..],
+                    aad: &wrapped[..header_length],
+                },
+            )
+            .map_err(|_| "The encrypted datagram could not be decrypted.".to_string())?;
+        session.receive_counter(counter);
+        self.uses += 1;
+        session.last_used = self.uses;
+        Ok(datagram)
+    }
+
+    // Adds or replaces the session with the peer, first forgetting the session used least recently if there
+    // are already as many as are kept.
+    fn insert(&mut self, addr: SocketAddr, mut session: Session) {
+        if !self.sessions.contains_key(&addr) && self.sessions.len() >= self.max_sessions {
+            let least_recently_used = self
+                .sessions
+                .iter()
+                .min_by_key(|(_, session)| session.last_used)
+                .map(|(addr, _)| *addr);
+            if let Some(least_recently_used) = least_recently_used {
+                self.sessions.remove(&least_recently_used);
+            }
+        }
+        self.uses += 1;
+        session.last_used = self.uses;
+        self.sessions.insert(addr, session);
+    }
+}
+
+// The 96-bit nonce for a counter.
+fn nonce(counter: u64) -> Nonce {
+    let mut nonce = [0; 12];
+    nonce[4..].copy_from_slice(&counter.to_be_bytes());
+    nonce.into()
+}
+
+// Reads the public key of a handshake datagram of the given kind.
+fn read_public_key(datagram: &[u8], kind: u8) -> Result<PublicKey, String> {
+    match datagram {
+        [header, public_key @ ..] if *header == kind && public_key.len() == PUBLIC_KEY_LENGTH => {
+            let public_key: [u8; PUBLIC_KEY_LENGTH] = public_key.try_into().unwrap();
+            Ok(PublicKey::from(public_key))
+        }
+        _ => Err("The handshake datagram is malformed.".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client_addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    fn server_addr() -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], 7878))
+    }
+
+    // Runs a whole handshake between a new client at the address and the server.
+    fn handshake(server: &mut Sessions, addr: SocketAddr) -> Sessions {
+        let mut client = Sessions::new();
+        let (secret, request) = Sessions::start_handshake();
+        let response = server.accept_handshake(&addr, &request, false).unwrap();
+        client
+            .finish_handshake(&server_addr(), secret, &request[1..], &response)
+            .unwrap();
+        client
+    }
+
+    #[test]
+    fn datagrams_round_trip_after_a_handshake() {
+        let mut server = Sessions::new();
+        let mut client = handshake(&mut server, client_addr(9000));
+
+        let request = client.wrap(&server_addr(), b"request");
+        assert_eq!(request[0], ENCRYPTED);
+        assert_eq!(
+            server.unwrap(&client_addr(9000), &request),
+            Ok(b"request".to_vec())
+        );
+        let response = server.wrap(&client_addr(9000), b"response");
+        assert_eq!(
+            client.unwrap(&server_addr(), &response),
+            Ok(b"response".to_vec())
+        );
+
+        // Each direction has its own key, so a datagram cannot be reflected back to its sender.
+        assert!(client.unwrap(&server_addr(), &request).is_err());
+        assert_eq!(
+            client.wrap(&client_addr(9001), b"hello"),
+            [&[PLAINTEXT], b"hello".as_slice()].concat()
+        );
+    }
+
+    #[test]
+    fn replayed_counters_are_rejected() {
+        let mut server = Sessions::new();
+        let mut client = handshake(&mut server, client_addr(9000));
+        let first = client.wrap(&server_addr(), b"first");
+        let second = client.wrap(&server_addr(), b"second");
+
+        assert!(server.unwrap(&client_addr(9000), &second).is_ok());
+        assert!(server.unwrap(&client_addr(9000), &second).is_err());
+        assert!(server.unwrap(&client_addr(9000), &first).is_ok());
+        assert!(server.unwrap(&client_addr(9000), &first).is_err());
+        assert!(server.unwrap(&client_addr(9000), &second).is_err());
+
+        // A datagram with its counter changed fails to decrypt instead of passing the counter check.
+        let mut tampered = client.wrap(&server_addr(), b"fourth");
+        tampered[COUNTER_LENGTH] += 1;
+        assert!(server.unwrap(&client_addr(9000), &tampered).is_err());
+    }
+
+    #[test]
+    fn out_of_order_counters_within_the_window_are_accepted() {
+        let mut server = Sessions::new();
+        let mut client = handshake(&mut server, client_addr(9000));
+        let wrapped = (0..REPLAY_WINDOW + 2)
+            .map(|_| client.wrap(&server_addr(), b"datagram"))
+            .collect::<Vec<Vec<u8>>>();
+
+        // Counters 3, 1, 0 and 2 arrive in that order.
+        for index in [3, 1, 0, 2] {
+            assert!(server.unwrap(&client_addr(9000), &wrapped[index]).is_ok());
+        }
+        // Counter 65 moves the window past counter 1, which was already received, but not past counter 4.
+        let last = REPLAY_WINDOW as usize + 1;
+        assert!(server.unwrap(&client_addr(9000), &wrapped[last]).is_ok());
+        assert!(server.unwrap(&client_addr(9000), &wrapped[4]).is_ok());
+        assert!(server.unwrap(&client_addr(9000), &wrapped[4]).is_err());
+        assert!(server.unwrap(&client_addr(9000), &wrapped[1]).is_err());
+        assert!(server.unwrap(&client_addr(9000), &wrapped[last]).is_err());
+        assert!(server
+            .unwrap(&client_addr(9000), &wrapped[last - 1])
+            .is_ok());
+
+        // Counters older than the window are rejected, even if they were never received.
+        let mut server = Sessions::new();
+        let mut client = handshake(&mut server, client_addr(9000));
+        let wrapped = (0..REPLAY_WINDOW + 1)
+            .map(|_| client.wrap(&server_addr(), b"datagram"))
+            .collect::<Vec<Vec<u8>>>();
+        assert!(server
+            .unwrap(&client_addr(9000), &wrapped[REPLAY_WINDOW as usize])
+            .is_ok());
+        assert!(server.unwrap(&client_addr(9000), &wrapped[1]).is_ok());
+        assert!(server.unwrap(&client_addr(9000), &wrapped[0]).is_err());
+    }
+
+    #[test]
+    fn used_sessions_are_only_replaced_by_authenticated_handshakes() {
+        let mut server = Sessions::new();
+        // A session the client has not used yet is replaced, as when the first response was lost.
+        handshake(&mut server, client_addr(9000));
+        let mut client = handshake(&mut server, client_addr(9000));
+        let request = client.wrap(&server_addr(), b"request");
+        assert!(server.unwrap(&client_addr(9000), &request).is_ok());
+
+        let (_, spoofed) = Sessions::start_handshake();
+        assert!(server
+            .accept_handshake(&client_addr(9000), &spoofed, false)
+            .is_err());
+        let request = client.wrap(&server_addr(), b"request");
+        assert!(server.unwrap(&client_addr(9000), &request).is_ok());
+
+        assert!(server
+            .accept_handshake(&client_addr(9000), &spoofed, true)
+            .is_ok());
+        let request = client.wrap(&server_addr(), b"request");
+        assert!(server.unwrap(&client_addr(9000), &request).is_err());
+    }
+
+    #[test]
+    fn the_least_recently_used_session_is_forgotten_beyond_the_limit() {
+        let mut server = Sessions::new();
+        server.max_sessions = 2;
+        let mut first = handshake(&mut server, client_addr(9000));
+        handshake(&mut server, client_addr(9001));
+        let request = first.wrap(&server_addr(), b"request");
+        assert!(server.unwrap(&client_addr(9000), &request).is_ok());
+
+        handshake(&mut server, client_addr(9002));
+        assert_eq!(server.sessions.len(), 2);
+        assert!(server.has_session(&client_addr(9000)));
+        assert!(!server.has_session(&client_addr(9001)));
+        assert!(server.has_session(&client_addr(9002)));
+    }
+}
@@ -1,4 +1,225 @@
-use std::net::{SocketAddr, UdpSocket};
+use std::{
+    io,
+    net::{SocketAddr, UdpSocket},
+    ops::Range,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        LazyLock, Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+mod auth;
+mod encryption;
+
+pub use auth::{from_hex, load_key_file, Authenticator};
+use encryption::{Sessions, ENCRYPTED, HANDSHAKE_REQUEST, HANDSHAKE_RESPONSE, PLAINTEXT};
+
+// Whether to print what is sent and received. Clients whose output is read by scripts turn it off.
+static LOGGING: AtomicBool = AtomicBool::new(true);
+
+pub fn set_logging(enabled: bool) {
+    LOGGING.store(enabled, Ordering::Relaxed);
+}
+
+macro_rules! log {
+    ($($arg:tt)*) => {
+        if LOGGING.load(Ordering::Relaxed) {
+            println!($($arg)*);
+        }
+    };
+}
+
+// Callbacks are pushed by the server without a request, so they carry this reserved request ID instead, as
+// do clients' acknowledgements of them. Clients number their requests from 1.
+pub const CALLBACK_REQUEST_ID: u32 = 0;
+
+// Signs and checks every datagram sent and received through this crate, once authentication is enabled.
+static AUTHENTICATOR: Mutex<Option<Authenticator>> = Mutex::new(None);
+
+// Turns on authentication of every datagram for the rest of the process. Both sides must enable it with
+// the same keys.
+pub fn enable_authentication(authenticator: Authenticator) {
+    *AUTHENTICATOR.lock().unwrap() = Some(authenticator);
+}
+
+// The encrypted sessions with peers. Datagrams to a peer without one are sent in plaintext.
+static SESSIONS: LazyLock<Mutex<Sessions>> = LazyLock::new(|| Mutex::new(Sessions::new()));
+
+// How many times a client sends its handshake before giving up, waiting for the socket's read timeout each
+// time.
+const HANDSHAKE_ATTEMPTS: u32 = 5;
+
+// Starts an encrypted session with the server, so that every datagram between them from now on is encrypted.
+// The key exchange is only protected against tampering when authentication is enabled as well.
+pub fn start_encrypted_session(socket: &UdpSocket, server_addr: &SocketAddr) -> io::Result<()> {
+    let mut buf = [0; 2048];
+    for _ in 0..HANDSHAKE_ATTEMPTS {
+        let (secret, request) = Sessions::start_handshake();
+        send_authenticated(&request, socket, server_addr);
+        loop {
+            match receive_authenticated(socket, &mut buf) {
+                // A response to an earlier attempt is for a different key pair, which finishing rejects.
+                Ok((datagram, addr))
+                    if addr == *server_addr && buf[datagram.start] == HANDSHAKE_RESPONSE =>
+                {
+                    let request_public_key = &request[1..];
+                    match SESSIONS.lock().unwrap().finish_handshake(
+                        server_addr,
+                        secret,
+                        request_public_key,
+                        &buf[datagram],
+                    ) {
+                        Ok(()) => return Ok(()),
+                        Err(reason) => {
+                            log!("[networking] Handshake failed: {reason}");
+                            break;
+                        }
+                    }
+                }
+                // Nothing else is expected before the session starts.
+                Ok(_) => continue,
+                Err(error)
+                    if matches!(
+                        error.kind(),
+                        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+                    ) =>
+                {
+                    log!("[networking] Timed out waiting for the handshake response");
+                    break;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::TimedOut,
+        "The server did not complete the handshake.",
+    ))
+}
+
+// Receives the next datagram into the buffer and returns its length and sender. The header byte and any
+// authentication envelope are removed and encrypted datagrams are decrypted, so that the buffer starts with
+// the request ID. Handshakes are answered here, and datagrams that fail authentication or decryption are
+// logged and dropped.
+pub fn receive(socket: &UdpSocket, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+    loop {
+        let (datagram, addr) = receive_authenticated(socket, buf)?;
+        let result = match buf[datagram.start] {
+            // Otherwise anyone could send requests as a client that encrypts its datagrams.
+            PLAINTEXT if SESSIONS.lock().unwrap().has_session(&addr) => Err(
+                "The datagram is in plaintext, but there is an encrypted session with the sender."
+                    .to_string(),
+            ),
+            PLAINTEXT => {
+                let amt = datagram.len() - 1;
+                buf.copy_within(datagram.start + 1..datagram.end, 0);
+                return Ok((amt, addr));
+            }
+            ENCRYPTED => SESSIONS.lock().unwrap().unwrap(&addr, &buf[datagram]),
+            HANDSHAKE_REQUEST => {
+                // Authentication already dropped the handshake if it is enabled and the request is forged.
+                let authenticated = AUTHENTICATOR.lock().unwrap().is_some();
+                let response =
+                    SESSIONS
+                        .lock()
+                        .unwrap()
+                        .accept_handshake(&addr, &buf[datagram], authenticated);
+                match response {
+                    Ok(response) => {
+                        log!("[networking] Started an encrypted session with {addr}");
+                        send_authenticated(&response, socket, &addr);
+                        continue;
+                    }
+                    Err(reason) => Err(reason),
+                }
+            }
+            header => Err(format!("The header byte {header} is not expected here.")),
+        };
+        match result {
+            Ok(plaintext) => {
+                buf[..plaintext.len()].copy_from_slice(&plaintext);
+                return Ok((plaintext.len(), addr));
+            }
+            Err(reason) => log!("[networking] Dropped datagram from {addr}: {reason}"),
+        }
+    }
+}
+
+// Receives the next datagram that passes authentication, if it is enabled, and returns where it is in the
+// buffer without the authentication envelope. Empty datagrams are dropped, so it always has a header byte.
+fn receive_authenticated(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+) -> io::Result<(Range<usize>, SocketAddr)> {
+    loop {
+        let (amt, addr) = socket.recv_from(buf)?;
+        let mut authenticator = AUTHENTICATOR.lock().unwrap();
+        let datagram = match authenticator.as_mut() {
+            Some(authenticator) => {
+                match authenticator.open(&addr, &buf[..amt], current_time_ms()) {
+                    Ok(datagram) => datagram,
+                    Err(reason) => {
+                        log!(
+                            "[networking] Rejected datagram from {addr}: {reason} ({} rejected so far)",
+                            authenticator.rejected_count()
+                        );
+                        continue;
+                    }
+                }
+            }
+            None => 0..amt,
+        };
+        if !datagram.is_empty() {
+            return Ok((datagram, addr));
+        }
+    }
+}
+
+// Sends a datagram, encrypted if there is a session with the peer.
+fn send_datagram(datagram: Vec<u8>, socket: &UdpSocket, addr: &SocketAddr) {
+    let wrapped = SESSIONS.lock().unwrap().wrap(addr, &datagram);
+    send_authenticated(&wrapped, socket, addr);
+}
+
+// Sends a datagram that already has its header byte, in an authenticated envelope if authentication is
+// enabled.
+fn send_authenticated(datagram: &[u8], socket: &UdpSocket, addr: &SocketAddr) {
+    let sealed;
+    let datagram = match AUTHENTICATOR.lock().unwrap().as_ref() {
+        Some(authenticator) => match authenticator.seal(addr, datagram, current_time_ms()) {
+            Ok(envelope) => {
+                sealed = envelope;
+                &sealed
+            }
+            Err(reason) => {
+                log!("[networking] Not sending to {addr}: {reason}");
+                return;
+            }
+        },
+        None => datagram,
+    };
+    socket.send_to(datagram, addr).expect("Error on send");
+}
+
+// Describes a datagram for the log by its request ID, first byte and length. The rest of the payload is left
+// out, since it can hold passwords and session tokens.
+fn describe(request_id: u32, payload: &[u8]) -> String {
+    match payload.first() {
+        Some(first_byte) => format!(
+            "request ID {request_id}, service or handler byte {first_byte}, {} payload bytes",
+            payload.len()
+        ),
+        None => format!("request ID {request_id}, no payload"),
+    }
+}
+
+fn current_time_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
 
 pub fn send_request(
     request_id: u32,
@@ -16,10 +237,11 @@ pub fn send_request(
     // Add payload to buffer
     buffer_to_send.extend_from_slice(&payload);
 
-    socket
-        .send_to(&buffer_to_send, &server_addr)
-        .expect("Error on send");
-    println!("[networking] Sent request: {:?}", buffer_to_send);
+    log!(
+        "[networking] Sent request: {}",
+        describe(request_id, &payload)
+    );
+    send_datagram(buffer_to_send, socket, server_addr);
 }
 
 // Should be able to simulate failure by not sending the response back to the client. Accepts a parameter to simulate failure.
@@ -31,7 +253,7 @@ pub fn send_response(
     simulate_failure: bool,
 ) {
     if simulate_failure {
-        println!("[networking] Simulating failure");
+        log!("[networking] Simulating failure");
         return;
     }
     // Send the response back to the client after prepending some information.
@@ -46,8 +268,15 @@ pub fn send_response(
     buffer_to_send.extend_from_slice(&payload);
     // println!("Appending payload: {:?}", payload);
 
-    socket
-        .send_to(&buffer_to_send, &client_addr)
-        .expect("Error on send");
-    println!("[networking] Sent response: {:?}", buffer_to_send);
+    log!(
+        "[networking] Sent response: {}",
+        describe(request_id, &payload)
+    );
+    send_datagram(buffer_to_send, socket, client_addr);
+}
+
+// Sends a callback to a client that is not in response to any request. Callbacks are never dropped by the
+// failure simulation, since the client has no way to ask for them again.
+pub fn send_callback(payload: Vec<u8>, socket: &UdpSocket, client_addr: &SocketAddr) {
+    send_response(CALLBACK_REQUEST_ID, payload, socket, client_addr, false);
 }
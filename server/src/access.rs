@@ -0,0 +1,51 @@
+use crate::account::Role;
+
+// Who may use a service.
+#[derive(Clone, Copy)]
+pub enum Access {
+    // Anyone, without logging in.
+    Public,
+    // Anyone, but logged in users with one of the roles can give their session too. Requests start with a
+    // byte saying whether they do, then the session token if so.
+    Optional(&'static [Role]),
+    // Logged in users with one of the roles. Requests start with the session token.
+    Roles(&'static [Role]),
+}
+
+const ANY_ROLE: &[Role] = &[Role::Traveller, Role::Agent, Role::Admin];
+const ADMIN_ONLY: &[Role] = &[Role::Admin];
+
+// The permission table, by service ID.
+const PERMISSIONS: [(u8, Access); 23] = [
+    (1, Access::Public),             // Flight identifiers
+    (2, Access::Optional(ANY_ROLE)), // Flight summary
+    (3, Access::Roles(ANY_ROLE)),    // Reserve seats
+    (4, Access::Roles(ANY_ROLE)),    // Monitor seat availability
+    (5, Access::Public),             // Earliest flight identifiers
+    (6, Access::Roles(ANY_ROLE)),    // Reserve baggage
+    (7, Access::Public),             // Search flights
+    (8, Access::Public),             // Next departures
+    (9, Access::Roles(ADMIN_ONLY)),  // Manage flights
+    (12, Access::Public),            // Seat map
+    (13, Access::Roles(ANY_ROLE)),   // Reserve specific seats
+    (14, Access::Roles(ANY_ROLE)),   // Join waitlist
+    (15, Access::Roles(ANY_ROLE)),   // Cancel booking
+    (16, Access::Roles(ANY_ROLE)),   // Passenger details
+    (17, Access::Roles(ANY_ROLE)),   // Check in
+    (18, Access::Public),            // Register
+    (19, Access::Public),            // Log in
+    (20, Access::Roles(ANY_ROLE)),   // List bookings
+    (21, Access::Roles(ADMIN_ONLY)), // Set role
+    (23, Access::Roles(ANY_ROLE)),   // Unsubscribe
+    (24, Access::Roles(ANY_ROLE)),   // List subscriptions
+    (25, Access::Roles(ANY_ROLE)),   // Resync subscription
+    (26, Access::Roles(ANY_ROLE)),   // Monitor route
+];
+
+// Who may use the service. Unknown services are public, so that dispatch reports them as unknown.
+pub fn access(service_id: u8) -> Access {
+    PERMISSIONS
+        .iter()
+        .find(|(id, _)| *id == service_id)
+        .map_or(Access::Public, |(_, access)| *access)
+}
@@ -0,0 +1,246 @@
+use std::{collections::HashMap, fmt};
+
+use hmac::{Hmac, Mac};
+use networking::from_hex;
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// How long a session token can be used for after logging in.
+pub const SESSION_SECONDS: u32 = 24 * 3600;
+
+// PBKDF2 rounds for each password hash. High enough to slow down guessing a stolen hash, low enough that
+// logging in does not hold up the other clients of the server for long.
+const PASSWORD_HASH_ROUNDS: u32 = 20_000;
+
+const MIN_USERNAME_LENGTH: usize = 3;
+const MAX_USERNAME_LENGTH: usize = 32;
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+// What a user may do. Travellers manage their own bookings, agents can also manage bookings for
+// travellers, and admins can also manage flights and users.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Traveller,
+    Agent,
+    Admin,
+}
+
+impl Role {
+    pub fn from_u8(value: u8) -> Option<Role> {
+        match value {
+            0 => Some(Role::Traveller),
+            1 => Some(Role::Agent),
+            2 => Some(Role::Admin),
+            _ => None,
+        }
+    }
+
+    pub fn to_u8(self) -> u8 {
+        match self {
+            Role::Traveller => 0,
+            Role::Agent => 1,
+            Role::Admin => 2,
+        }
+    }
+
+    // Whether the role can use the services that act for a user on behalf of a traveller.
+    pub fn can_act_for_others(self) -> bool {
+        matches!(self, Role::Agent | Role::Admin)
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Role::Traveller => write!(f, "traveller"),
+            Role::Agent => write!(f, "agent"),
+            Role::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+// A registered user. Passwords are only kept as salted hashes.
+pub struct Account {
+    pub id: u32,
+    pub username: String,
+    pub role: Role,
+    salt: [u8; 16],
+    password_hash: [u8; 32],
+}
+
+pub struct AccountStore {
+    accounts: HashMap<u32, Account>,
+    // User IDs by username in lower case, so that names differing only in case are the same user.
+    user_ids: HashMap<String, u32>,
+    next_user_id: u32,
+}
+
+impl AccountStore {
+    pub fn new() -> AccountStore {
+        AccountStore {
+            accounts: HashMap::new(),
+            user_ids: HashMap::new(),
+            next_user_id: 1,
+        }
+    }
+
+    // Creates an account with the role and returns the new user's ID. Usernames are 3 to 32 letters,
+    // digits, '_', '-' or '.', and passwords are at least 8 characters.
+    pub fn register(&mut self, username: &str, password: &str, role: Role) -> Result<u32, String> {
+        if !(MIN_USERNAME_LENGTH..=MAX_USERNAME_LENGTH).contains(&username.len())
+            || !username
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        {
+            return Err(format!("A username must be {MIN_USERNAME_LENGTH} to {MAX_USERNAME_LENGTH} letters, digits, underscores, hyphens or full stops."));
+        }
+        if password.chars().count() < MIN_PASSWORD_LENGTH {
+            return Err(format!(
+                "A password must be at least {MIN_PASSWORD_LENGTH} characters long."
+            ));
+        }
+        let key = username.to_ascii_lowercase();
+        if self.user_ids.contains_key(&key) {
+            return Err("That username is already taken.".to_string());
+        }
+
+        let mut salt = [0; 16];
+        getrandom::getrandom(&mut salt).map_err(|error| error.to_string())?;
+        let id = self.next_user_id;
+        self.next_user_id += 1;
+        self.user_ids.insert(key, id);
+        self.accounts.insert(
+            id,
+            Account {
+                id,
+                username: username.to_string(),
+                role,
+                salt,
+                password_hash: hash_password(password, &salt),
+            },
+        );
+        Ok(id)
+    }
+
+    // Returns the account if the password is right. Unknown users and wrong passwords give the same error, so
+    // that the error does not reveal which usernames exist.
+    pub fn login(&self, username: &str, password: &str) -> Result<&Account, String> {
+        self.find(username)
+            .filter(|account| hash_password(password, &account.salt) == account.password_hash)
+            .ok_or_else(|| "Incorrect username or password.".to_string())
+    }
+
+    pub fn get(&self, user_id: &u32) -> Option<&Account> {
+        self.accounts.get(user_id)
+    }
+
+    pub fn find(&self, username: &str) -> Option<&Account> {
+        self.user_ids
+            .get(&username.to_ascii_lowercase())
+            .and_then(|user_id| self.accounts.get(user_id))
+    }
+
+    // Changes the role of the user and returns their user ID. Sessions already issued to them get the new
+    // role straight away, since roles are looked up on every request. The last admin cannot be demoted, even
+    // by themselves, since only admins can make other users admins.
+    pub fn set_role(&mut self, username: &str, role: Role) -> Result<u32, String> {
+        let user_id = *self
+            .user_ids
+            .get(&username.to_ascii_lowercase())
+            .ok_or("No user found with the given username.")?;
+        let num_admins = self
+            .accounts
+            .values()
+            .filter(|account| account.role == Role::Admin)
+            .count();
+        if self.accounts[&user_id].role == Role::Admin && role != Role::Admin && num_admins == 1 {
+            return Err(
+                "The last admin cannot be demoted. Make another user an admin first.".to_string(),
+            );
+        }
+        self.accounts.get_mut(&user_id).unwrap().role = role;
+        Ok(user_id)
+    }
+}
+
+fn hash_password(password: &str, salt: &[u8]) -> [u8; 32] {
+    pbkdf2::pbkdf2_hmac_array::<Sha256, 32>(password.as_bytes(), salt, PASSWORD_HASH_ROUNDS)
+}
+
+// Issues and checks session tokens. A token is "<user ID>.<expiry time>.<signature>", signed with a key
+// made when the server starts, so the server does not need to remember sessions and every token stops
+// working when the server restarts.
+pub struct SessionSigner {
+    key: [u8; 32],
+}
+
+impl SessionSigner {
+    pub fn new() -> SessionSigner {
+        let mut key = [0; 32];
+        getrandom::getrandom(&mut key).expect("Could not generate the session signing key.");
+        SessionSigner { key }
+    }
+
+    // Returns a token for the user and the time it expires at.
+    pub fn issue(&self, user_id: u32, now: u32) -> (String, u32) {
+        let expires_at = now.saturating_add(SESSION_SECONDS);
+        let claims = format!("{user_id}.{expires_at}");
+        let signature = to_hex(&self.mac(&claims).finalize().into_bytes());
+        (format!("{claims}.{signature}"), expires_at)
+    }
+
+    // Returns the user the token was issued to, if it was signed by this server and has not expired.
+    pub fn verify(&self, token: &str, now: u32) -> Result<u32, String> {
+        let invalid = || "Invalid session. Log in again.".to_string();
+        let (claims, signature) = token.rsplit_once('.').ok_or_else(invalid)?;
+        let signature = from_hex(signature).ok_or_else(invalid)?;
+        self.mac(claims)
+            .verify_slice(&signature)
+            .map_err(|_| invalid())?;
+
+        let (user_id, expires_at) = claims.split_once('.').ok_or_else(invalid)?;
+        let user_id = user_id.parse::<u32>().map_err(|_| invalid())?;
+        let expires_at = expires_at.parse::<u32>().map_err(|_| invalid())?;
+        if now >= expires_at {
+            return Err("Your session has expired. Log in again.".to_string());
+        }
+        Ok(user_id)
+    }
+
+    fn mac(&self, claims: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).unwrap();
+        mac.update(claims.as_bytes());
+        mac
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_last_admin_cannot_be_demoted() {
+        let mut accounts = AccountStore::new();
+        let alice = accounts
+            .register("alice", "password1", Role::Admin)
+            .unwrap();
+        accounts
+            .register("bob", "password2", Role::Traveller)
+            .unwrap();
+
+        assert!(accounts.set_role("Alice", Role::Agent).is_err());
+        assert!(accounts.set_role("alice", Role::Admin).is_ok());
+        assert_eq!(accounts.get(&alice).unwrap().role, Role::Admin);
+
+        // Once there is another admin, either of them can be demoted, but not both.
+        accounts.set_role("bob", Role::Admin).unwrap();
+        assert!(accounts.set_role("alice", Role::Traveller).is_ok());
+        assert!(accounts.set_role("bob", Role::Agent).is_err());
+        assert_eq!(accounts.find("bob").unwrap().role, Role::Admin);
+    }
+}
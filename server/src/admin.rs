@@ -0,0 +1,627 @@
+use std::{
+    collections::HashSet,
+    net::{SocketAddr, UdpSocket},
+};
+
+use marshaling::{
+    marshal_long_string, marshal_u32, marshal_u32_array, unmarshal_money, unmarshal_optional_money,
+    unmarshal_optional_string, unmarshal_optional_u32, unmarshal_string, unmarshal_u32,
+    unmarshal_u32_array, unmarshal_u8, Currency, Money,
+};
+
+use crate::{
+    booking::BookingStore,
+    current_unix_time, error_handler,
+    flight::{Cabin, Flight, FlightStatus, SeatClass},
+    flight_store::FlightStore,
+    inform_status_changed,
+    manifest::{export_manifest, ManifestFormat, ManifestPage},
+    overbooking::{select_bumped_bookings, BumpPolicy},
+    pricing::PricingEngine,
+    seat_map::SeatMap,
+    subscription::SubscriptionStore,
+    waitlist::Waitlist,
+};
+
+// Operations accepted by the admin service, sent as the first byte after the session.
+const ADD_FLIGHT: u8 = 1;
+const UPDATE_FLIGHT: u8 = 2;
+const CANCEL_FLIGHT: u8 = 3;
+const SET_FLIGHT_STATUS: u8 = 4;
+const SET_SEAT_MAP: u8 = 5;
+const CHECK_IN: u8 = 6;
+const EXPORT_MANIFEST: u8 = 7;
+
+// Upper bounds on the length of a flight's source, destination and gate, so that a page of
+// flight_store::MAX_PAGE_SIZE flight listings fits in one datagram.
+const MAX_PLACE_LENGTH: usize = 8;
+const MAX_GATE_LENGTH: usize = 8;
+
+// What an admin operation did, sent back to the admin client.
+struct AdminOutcome {
+    flight_id: u32,
+    // How many clients were told about the change.
+    num_notified: u32,
+    // Bookings bumped from an overbooked flight at check-in.
+    bumped_booking_ids: Vec<u32>,
+    // A page of the passenger manifest, when one was exported.
+    manifest: Option<ManifestPage>,
+}
+
+impl AdminOutcome {
+    fn new(flight_id: u32, num_notified: u32) -> AdminOutcome {
+        AdminOutcome {
+            flight_id,
+            num_notified,
+            bumped_booking_ids: vec![],
+            manifest: None,
+        }
+    }
+}
+
+// Fares are set in the currency every flight is priced in. Only admins reach this handler, as checked
+// against the permission table in dispatch.
+#[allow(clippy::too_many_arguments)]
+pub fn admin_handler(
+    buf: &[u8],
+    pricing_currency: Currency,
+    pricing: &PricingEngine,
+    flight_db: &mut FlightStore,
+    booking_db: &mut BookingStore,
+    waitlist: &mut Waitlist,
+    subscription_db: &mut SubscriptionStore,
+    socket: &UdpSocket,
+) -> Vec<u8> {
+    let (operation, i) = unmarshal_u8(buf, 0);
+    let result = match operation {
+        ADD_FLIGHT => add_flight(&buf[i..], pricing_currency, flight_db),
+        UPDATE_FLIGHT => update_flight(
+            &buf[i..],
+            pricing_currency,
+            flight_db,
+            booking_db,
+            waitlist,
+            subscription_db,
+            socket,
+        ),
+        CANCEL_FLIGHT => cancel_flight(
+            &buf[i..],
+            flight_db,
+            booking_db,
+            waitlist,
+            subscription_db,
+            socket,
+        ),
+        SET_FLIGHT_STATUS => set_flight_status(
+            &buf[i..],
+            flight_db,
+            booking_db,
+            waitlist,
+            subscription_db,
+            socket,
+        ),
+        SET_SEAT_MAP => set_seat_map(
+            &buf[i..],
+            flight_db,
+            booking_db,
+            waitlist,
+            socket,
+        ),
+        CHECK_IN => check_in(&buf[i..], flight_db, booking_db, socket),
+        EXPORT_MANIFEST => export_flight_manifest(&buf[i..], flight_db, booking_db),
+        _ => Err(
+            "Invalid admin operation. Use 1 (add), 2 (update), 3 (cancel), 4 (set status), 5 (set seat map), 6 (check in) or 7 (export manifest)."
+                .to_string(),
+        ),
+    };
+
+    let outcome = match result {
+        Ok(outcome) => outcome,
+        Err(error_message) => return error_handler(&error_message),
+    };
+
+    // Fares follow the flight's seats and status, so bring them up to date for the clients about to be told.
+    if let Some(flight) = flight_db.get_mut(&outcome.flight_id) {
+        flight.reprice(pricing, current_unix_time());
+    }
+
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add the handler byte.
+    buffer_to_send.push(9);
+
+    // Add the affected flight ID, how many clients were notified of the change and any bumped bookings.
+    marshal_u32(outcome.flight_id, &mut buffer_to_send);
+    marshal_u32(outcome.num_notified, &mut buffer_to_send);
+    marshal_u32_array(&outcome.bumped_booking_ids, &mut buffer_to_send);
+
+    // Add the manifest page if one was exported, with where it starts among all passengers.
+    match outcome.manifest {
+        Some(page) => {
+            buffer_to_send.push(1);
+            marshal_u32(page.total_passengers, &mut buffer_to_send);
+            marshal_u32(page.offset, &mut buffer_to_send);
+            marshal_u32(page.num_passengers, &mut buffer_to_send);
+            marshal_long_string(&page.text, &mut buffer_to_send);
+        }
+        None => buffer_to_send.push(0),
+    }
+
+    buffer_to_send
+}
+
+// Compares every byte so that the time taken does not reveal how much of the key was correct.
+pub fn keys_match(expected: &str, actual: &str) -> bool {
+    expected.len() == actual.len()
+        && expected
+            .bytes()
+            .zip(actual.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn add_flight(
+    buf: &[u8],
+    pricing_currency: Currency,
+    flight_db: &mut FlightStore,
+) -> Result<AdminOutcome, String> {
+    let (source, i) = unmarshal_string(buf, 0);
+    let (destination, i) = unmarshal_string(buf, i);
+    let (departure_time, mut i) = unmarshal_u32(buf, i);
+
+    // The seats and airfare of each class, in seat class order.
+    let mut cabins = [Cabin::new(0, Money::new(0, pricing_currency)); 3];
+    for cabin in cabins.iter_mut() {
+        let (seats, j) = unmarshal_u32(buf, i);
+        let (airfare, j) = unmarshal_money(buf, j);
+        *cabin = Cabin::new(seats, airfare?);
+        i = j;
+    }
+    let (baggage_capacity_kg, _) = unmarshal_u32(buf, i);
+
+    if source.is_empty() || destination.is_empty() {
+        return Err("Source and destination must not be empty.".to_string());
+    }
+    if source.len() > MAX_PLACE_LENGTH || destination.len() > MAX_PLACE_LENGTH {
+        return Err(format!(
+            "Source and destination must be at most {MAX_PLACE_LENGTH} bytes long."
+        ));
+    }
+    if departure_time <= current_unix_time() {
+        return Err("Departure time must be in the future.".to_string());
+    }
+    for cabin in cabins.iter() {
+        check_airfare(cabin.base_airfare, pricing_currency)?;
+    }
+
+    let flight_id = flight_db.next_flight_id();
+    flight_db.insert(Flight {
+        id: flight_id,
+        source,
+        destination,
+        departure_time,
+        cabins,
+        baggage_capacity_kg,
+        baggage_reserved_kg: 0,
+        status: FlightStatus::Scheduled,
+        gate: "TBA".to_string(),
+        seat_map: None,
+        overbooking_percent: 0,
+    });
+    println!("Added flight {flight_id}.");
+
+    Ok(AdminOutcome::new(flight_id, 0))
+}
+
+// A base fare must not be negative, and must be in the currency flights are priced in so that it is kept exact.
+fn check_airfare(airfare: Money, pricing_currency: Currency) -> Result<(), String> {
+    if airfare.currency != pricing_currency {
+        return Err(format!(
+            "Airfare must be given in {pricing_currency}, the currency flights are priced in."
+        ));
+    }
+    if airfare.is_negative() {
+        return Err("Airfare must not be negative.".to_string());
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_flight(
+    buf: &[u8],
+    pricing_currency: Currency,
+    flight_db: &mut FlightStore,
+    booking_db: &mut BookingStore,
+    waitlist: &mut Waitlist,
+    subscription_db: &mut SubscriptionStore,
+    socket: &UdpSocket,
+) -> Result<AdminOutcome, String> {
+    // Every field is optional; only the ones present are changed.
+    let (flight_id, i) = unmarshal_u32(buf, 0);
+    let (departure_time, mut i) = unmarshal_optional_u32(buf, i);
+
+    // The optional airfare and seats of each class, in seat class order.
+    let mut cabin_changes = Vec::with_capacity(SeatClass::ALL.len());
+    for _ in SeatClass::ALL {
+        let (airfare, j) = unmarshal_optional_money(buf, i);
+        let (seats, j) = unmarshal_optional_u32(buf, j);
+        cabin_changes.push((airfare?, seats));
+        i = j;
+    }
+    let (baggage_capacity_kg, i) = unmarshal_optional_u32(buf, i);
+    let (overbooking_percent, i) = unmarshal_optional_u32(buf, i);
+    let (gate, _) = unmarshal_optional_string(buf, i);
+
+    let flight = flight_db
+        .get_mut(&flight_id)
+        .ok_or("No flight found for the given flight ID.")?;
+
+    if matches!(
+        flight.status,
+        FlightStatus::Departed | FlightStatus::Arrived | FlightStatus::Cancelled
+    ) {
+        return Err(format!("A {} flight cannot be changed.", flight.status));
+    }
+    if departure_time.is_some_and(|departure_time| departure_time <= current_unix_time()) {
+        return Err("Departure time must be in the future.".to_string());
+    }
+    if baggage_capacity_kg.is_some_and(|kg| kg < flight.baggage_reserved_kg) {
+        return Err(format!(
+            "{} kg of baggage is already reserved, more than the new baggage capacity.",
+            flight.baggage_reserved_kg
+        ));
+    }
+    for (airfare, _) in cabin_changes.iter() {
+        if let Some(airfare) = *airfare {
+            check_airfare(airfare, pricing_currency)?;
+        }
+    }
+    if flight.seat_map.is_some() && cabin_changes.iter().any(|(_, seats)| seats.is_some()) {
+        return Err(
+            "The seats of a flight with a seat map follow the map. Set a new seat map instead."
+                .to_string(),
+        );
+    }
+    if gate.as_ref().is_some_and(|gate| gate.trim().is_empty()) {
+        return Err("The gate must not be empty.".to_string());
+    }
+    if gate
+        .as_ref()
+        .is_some_and(|gate| gate.trim().len() > MAX_GATE_LENGTH)
+    {
+        return Err(format!(
+            "The gate must be at most {MAX_GATE_LENGTH} bytes long."
+        ));
+    }
+    if flight.seat_map.is_some() && overbooking_percent.is_some() {
+        return Err("A flight with a seat map cannot be overbooked.".to_string());
+    }
+
+    let mut changes = Vec::new();
+    if let Some(departure_time) = departure_time {
+        flight.departure_time = departure_time;
+        changes.push(format!("departure time is now {departure_time}"));
+    }
+    for (seat_class, (airfare, seats)) in SeatClass::ALL.into_iter().zip(cabin_changes.iter()) {
+        let cabin = flight.cabin_mut(seat_class);
+        if let Some(airfare) = *airfare {
+            cabin.base_airfare = airfare;
+            changes.push(format!("{seat_class} base airfare is now {airfare}"));
+        }
+        if let Some(seats) = *seats {
+            cabin.seats = seats;
+            changes.push(format!("the {seat_class} cabin now has {seats} seats"));
+        }
+    }
+    if let Some(baggage_capacity_kg) = baggage_capacity_kg {
+        flight.baggage_capacity_kg = baggage_capacity_kg;
+        changes.push(format!(
+            "{} kg of baggage capacity is now available",
+            flight.remaining_baggage_kg()
+        ));
+    }
+    if let Some(overbooking_percent) = overbooking_percent {
+        flight.overbooking_percent = overbooking_percent;
+        changes.push(format!(
+            "up to {overbooking_percent}% more seats than physical seats can now be sold"
+        ));
+    }
+
+    if let Some(gate) = gate {
+        flight.gate = gate.trim().to_string();
+        changes.push(format!("it now departs from gate {}", flight.gate));
+    }
+    if changes.is_empty() {
+        return Err("No changes given for the flight.".to_string());
+    }
+    println!("Updated flight {flight_id}.");
+
+    // New seats go to waiting clients first, so that monitoring clients are told the seats left after that.
+    if cabin_changes.iter().any(|(_, seats)| seats.is_some()) || overbooking_percent.is_some() {
+        waitlist.promote(flight, booking_db, socket);
+    }
+
+    let message = format!("Flight {flight_id} has changed: {}.", changes.join(", "));
+    let client_addrs = affected_clients(flight_id, booking_db, waitlist, subscription_db);
+    for client_addr in client_addrs.iter() {
+        inform_flight_changed(socket, *client_addr, flight_id, &message);
+    }
+
+    Ok(AdminOutcome::new(flight_id, client_addrs.len() as u32))
+}
+
+fn cancel_flight(
+    buf: &[u8],
+    flight_db: &mut FlightStore,
+    booking_db: &mut BookingStore,
+    waitlist: &mut Waitlist,
+    subscription_db: &mut SubscriptionStore,
+    socket: &UdpSocket,
+) -> Result<AdminOutcome, String> {
+    let (flight_id, _) = unmarshal_u32(buf, 0);
+    change_status(
+        flight_id,
+        FlightStatus::Cancelled,
+        None,
+        flight_db,
+        booking_db,
+        waitlist,
+        subscription_db,
+        socket,
+    )
+}
+
+fn set_flight_status(
+    buf: &[u8],
+    flight_db: &mut FlightStore,
+    booking_db: &mut BookingStore,
+    waitlist: &mut Waitlist,
+    subscription_db: &mut SubscriptionStore,
+    socket: &UdpSocket,
+) -> Result<AdminOutcome, String> {
+    // A delay may come with a new departure time.
+    let (flight_id, i) = unmarshal_u32(buf, 0);
+    let (status, i) = unmarshal_u8(buf, i);
+    let (departure_time, _) = unmarshal_optional_u32(buf, i);
+
+    let status = FlightStatus::from_u8(status).ok_or("Invalid flight status.")?;
+    if departure_time.is_some() && status != FlightStatus::Delayed {
+        return Err("A new departure time can only be given for a delay.".to_string());
+    }
+    if departure_time.is_some_and(|departure_time| departure_time <= current_unix_time()) {
+        return Err("Departure time must be in the future.".to_string());
+    }
+
+    change_status(
+        flight_id,
+        status,
+        departure_time,
+        flight_db,
+        booking_db,
+        waitlist,
+        subscription_db,
+        socket,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn change_status(
+    flight_id: u32,
+    status: FlightStatus,
+    departure_time: Option<u32>,
+    flight_db: &mut FlightStore,
+    booking_db: &BookingStore,
+    waitlist: &mut Waitlist,
+    subscription_db: &mut SubscriptionStore,
+    socket: &UdpSocket,
+) -> Result<AdminOutcome, String> {
+    let flight = flight_db
+        .get_mut(&flight_id)
+        .ok_or("No flight found for the given flight ID.")?;
+
+    if !flight.status.can_transition_to(status) {
+        return Err(format!(
+            "A {} flight cannot become {}.",
+            flight.status, status
+        ));
+    }
+
+    flight.status = status;
+    if let Some(departure_time) = departure_time {
+        flight.departure_time = departure_time;
+    }
+    println!("Flight {flight_id} is now {status}.");
+
+    let client_addrs = affected_clients(flight_id, booking_db, waitlist, subscription_db);
+    // Clients watching the status hear about it from their subscription instead, which is sent until they
+    // acknowledge it. Subscriptions to a cancelled flight are dropped below, so everyone is called back then.
+    let status_watchers = match status {
+        FlightStatus::Cancelled => HashSet::new(),
+        _ => subscription_db.status_watchers(flight_id, current_unix_time()),
+    };
+    for client_addr in client_addrs.difference(&status_watchers) {
+        inform_status_changed(socket, *client_addr, flight);
+    }
+
+    // Nothing more can happen to a cancelled flight, so there is no point monitoring it any longer.
+    if status == FlightStatus::Cancelled {
+        subscription_db.remove_flight(flight_id);
+    }
+    // Waiting clients can no longer get seats once the flight stops taking bookings.
+    if !flight.is_bookable() {
+        waitlist.remove_flight(flight_id);
+    }
+
+    Ok(AdminOutcome::new(flight_id, client_addrs.len() as u32))
+}
+
+fn set_seat_map(
+    buf: &[u8],
+    flight_db: &mut FlightStore,
+    booking_db: &mut BookingStore,
+    waitlist: &mut Waitlist,
+    socket: &UdpSocket,
+) -> Result<AdminOutcome, String> {
+    // The seat letters, then the number of rows of each class from the front of the cabin (business,
+    // premium, then economy), then the exit rows and the blocked seats.
+    let (flight_id, i) = unmarshal_u32(buf, 0);
+    let (letters, mut i) = unmarshal_string(buf, i);
+    let mut row_classes = Vec::new();
+    for seat_class in [SeatClass::Business, SeatClass::Premium, SeatClass::Economy] {
+        let (num_rows, j) = unmarshal_u32(buf, i);
+        if row_classes.len() + num_rows as usize > 255 {
+            return Err("A seat map must have between 1 and 255 rows.".to_string());
+        }
+        row_classes.extend((0..num_rows).map(|_| seat_class));
+        i = j;
+    }
+    let (exit_rows, i) = unmarshal_u32_array(buf, i);
+    let (num_blocked, mut i) = unmarshal_u8(buf, i);
+    let mut blocked = Vec::with_capacity(num_blocked as usize);
+    for _ in 0..num_blocked {
+        let (seat, j) = unmarshal_string(buf, i);
+        blocked.push(seat);
+        i = j;
+    }
+
+    let flight = flight_db
+        .get_mut(&flight_id)
+        .ok_or("No flight found for the given flight ID.")?;
+
+    // Seats already assigned would be lost, so the map can only be set before the first booking.
+    if !booking_db.for_flight(flight_id).is_empty() {
+        return Err("The seat map cannot be changed once the flight has bookings.".to_string());
+    }
+
+    flight.set_seat_map(SeatMap::new(&letters, row_classes, &exit_rows, &blocked)?);
+    println!("Set the seat map of flight {flight_id}.");
+
+    // The seat counts now follow the map, and any new seats go to waiting clients first.
+    waitlist.promote(flight, booking_db, socket);
+
+    Ok(AdminOutcome::new(flight_id, 0))
+}
+
+fn check_in(
+    buf: &[u8],
+    flight_db: &mut FlightStore,
+    booking_db: &mut BookingStore,
+    socket: &UdpSocket,
+) -> Result<AdminOutcome, String> {
+    let (flight_id, i) = unmarshal_u32(buf, 0);
+    let (policy, _) = unmarshal_u8(buf, i);
+
+    let policy = BumpPolicy::from_u8(policy)
+        .ok_or("Invalid bump policy. Use 0 (latest booking first) or 1 (lowest fare first).")?;
+    let flight = flight_db
+        .get_mut(&flight_id)
+        .ok_or("No flight found for the given flight ID.")?;
+
+    // Seats can no longer be sold once boarding starts, so the bookings that are bumped stay bumped.
+    if flight.status != FlightStatus::Boarding {
+        return Err(format!(
+            "Check-in only bumps bookings once the flight is boarding, but it is {}.",
+            flight.status
+        ));
+    }
+
+    let bumped_booking_ids =
+        select_bumped_bookings(flight, &booking_db.for_flight(flight_id), policy);
+    let mut client_addrs = HashSet::new();
+    for booking_id in bumped_booking_ids.iter() {
+        if let Some(booking) = booking_db.cancel(*booking_id, flight) {
+            let message =
+                format!("Booking {booking_id} was bumped because the flight is overbooked.");
+            inform_flight_changed(socket, booking.client_addr, flight_id, &message);
+            client_addrs.insert(booking.client_addr);
+        }
+    }
+    println!(
+        "Checked in flight {flight_id}, bumping {} bookings.",
+        bumped_booking_ids.len()
+    );
+
+    Ok(AdminOutcome {
+        flight_id,
+        num_notified: client_addrs.len() as u32,
+        bumped_booking_ids,
+        manifest: None,
+    })
+}
+
+// Exports one page of the flight's passenger manifest, starting at the given passenger.
+fn export_flight_manifest(
+    buf: &[u8],
+    flight_db: &FlightStore,
+    booking_db: &BookingStore,
+) -> Result<AdminOutcome, String> {
+    let (flight_id, i) = unmarshal_u32(buf, 0);
+    let (format, i) = unmarshal_u8(buf, i);
+    let (offset, _) = unmarshal_u32(buf, i);
+
+    let format = ManifestFormat::from_u8(format)
+        .ok_or("Invalid manifest format. Use 0 (CSV) or 1 (JSON).")?;
+    if !flight_db.contains_key(&flight_id) {
+        return Err("No flight found for the given flight ID.".to_string());
+    }
+
+    let page = export_manifest(&booking_db.for_flight(flight_id), format, offset);
+    println!(
+        "Exported {} of {} passengers of flight {flight_id}.",
+        page.num_passengers, page.total_passengers
+    );
+
+    Ok(AdminOutcome {
+        manifest: Some(page),
+        ..AdminOutcome::new(flight_id, 0)
+    })
+}
+
+// Every booking holder, waiting client and monitoring client of the flight, once per address.
+fn affected_clients(
+    flight_id: u32,
+    booking_db: &BookingStore,
+    waitlist: &Waitlist,
+    subscription_db: &mut SubscriptionStore,
+) -> HashSet<SocketAddr> {
+    let mut client_addrs = waitlist.client_addrs(flight_id);
+    client_addrs.extend(
+        booking_db
+            .for_flight(flight_id)
+            .iter()
+            .map(|booking| booking.client_addr)
+            .chain(
+                subscription_db
+                    .active_for_flight(flight_id, current_unix_time())
+                    .into_iter()
+                    .map(|subscription| subscription.client_addr),
+            ),
+    );
+    client_addrs
+}
+
+// Sends a message to the socket to tell them that a flight they booked or monitor has changed.
+fn inform_flight_changed(
+    socket: &UdpSocket,
+    client_addr: SocketAddr,
+    flight_id: u32,
+    message: &str,
+) {
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add the handler byte. 10 is reserved for flight changed callbacks.
+    buffer_to_send.push(10);
+
+    // Add the flight ID and a description of the change, which can be longer than 255 bytes.
+    marshal_u32(flight_id, &mut buffer_to_send);
+    marshal_long_string(message, &mut buffer_to_send);
+
+    println!(
+        "Informing client: {}, flight_id: {}, change: {}",
+        client_addr, flight_id, message
+    );
+    // Send the message to the client.
+    networking::send_callback(buffer_to_send, socket, &client_addr);
+}
@@ -0,0 +1,87 @@
+use marshaling::{Currency, Money};
+
+use crate::flight::SeatClass;
+
+// The bags checked in by one passenger of a booking.
+#[derive(Clone, Default)]
+pub struct PassengerBaggage {
+    pub pieces_kg: Vec<u32>,
+}
+
+impl PassengerBaggage {
+    pub fn total_kg(&self) -> u32 {
+        self.pieces_kg.iter().sum()
+    }
+}
+
+// The limits on checked baggage and the fee for weight above the free allowance, which depends on the class.
+pub struct BaggagePolicy {
+    pub max_piece_kg: u32,
+    pub max_pieces_per_passenger: u32,
+    pub max_kg_per_passenger: u32,
+    pub free_allowance_kg: [u32; 3], // Indexed by SeatClass
+    pub excess_fee_per_kg: Money,
+}
+
+impl BaggagePolicy {
+    // Up to three bags of 32 kg each and 40 kg in total per passenger, with 20, 25 and 30 kg free in economy,
+    // premium and business, and 15.00 for every kg above that, in the currency flights are priced in.
+    pub fn standard(currency: Currency) -> BaggagePolicy {
+        BaggagePolicy {
+            max_piece_kg: 32,
+            max_pieces_per_passenger: 3,
+            max_kg_per_passenger: 40,
+            free_allowance_kg: [20, 25, 30],
+            excess_fee_per_kg: Money::new(15 * currency.minor_units_per_unit(), currency),
+        }
+    }
+
+    pub fn free_allowance_kg(&self, seat_class: SeatClass) -> u32 {
+        self.free_allowance_kg[seat_class.to_u8() as usize]
+    }
+
+    // Checks that a passenger may add the pieces to the bags they already have.
+    pub fn check(&self, baggage: &PassengerBaggage, new_pieces_kg: &[u32]) -> Result<(), String> {
+        if new_pieces_kg.is_empty() {
+            return Err("Give the weight of at least one piece of baggage.".to_string());
+        }
+        if let Some(weight_kg) = new_pieces_kg
+            .iter()
+            .find(|weight_kg| **weight_kg == 0 || **weight_kg > self.max_piece_kg)
+        {
+            return Err(format!(
+                "Every piece of baggage must weigh between 1 and {} kg, but one weighs {weight_kg} kg.",
+                self.max_piece_kg
+            ));
+        }
+
+        let num_pieces = baggage.pieces_kg.len() + new_pieces_kg.len();
+        if num_pieces > self.max_pieces_per_passenger as usize {
+            return Err(format!(
+                "A passenger can check in at most {} pieces of baggage, and already has {}.",
+                self.max_pieces_per_passenger,
+                baggage.pieces_kg.len()
+            ));
+        }
+        let total_kg = baggage.total_kg() + new_pieces_kg.iter().sum::<u32>();
+        if total_kg > self.max_kg_per_passenger {
+            return Err(format!(
+                "A passenger can check in at most {} kg of baggage, and already has {} kg.",
+                self.max_kg_per_passenger,
+                baggage.total_kg()
+            ));
+        }
+        Ok(())
+    }
+
+    // The fee for all of a passenger's baggage in the class, for the weight above the free allowance.
+    pub fn fee(&self, seat_class: SeatClass, baggage: &PassengerBaggage) -> Money {
+        let excess_kg = baggage
+            .total_kg()
+            .saturating_sub(self.free_allowance_kg(seat_class));
+        Money::new(
+            self.excess_fee_per_kg.minor_units * i64::from(excess_kg),
+            self.excess_fee_per_kg.currency,
+        )
+    }
+}
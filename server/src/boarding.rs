@@ -0,0 +1,344 @@
+use std::collections::HashMap;
+
+use crate::{
+    baggage::{BaggagePolicy, PassengerBaggage},
+    booking::Booking,
+    flight::{Flight, SeatClass},
+    passenger::{days_from_civil, Passenger},
+};
+
+// Check-in opens this long before departure...
+pub const CHECK_IN_OPENS_SECONDS: u32 = 24 * 3600;
+// ...and closes this long before it.
+pub const CHECK_IN_CLOSES_SECONDS: u32 = 3600;
+
+// Printed on boarding passes as the operating carrier.
+const AIRLINE_CODE: &str = "RF";
+
+// What a passenger needs to board, issued once at check-in. The gate is not part of it, since it can
+// change after check-in.
+#[derive(Clone)]
+pub struct BoardingPass {
+    pub seat: String,
+    pub boarding_group: u8,
+    // The order in which passengers of the flight checked in, from 1.
+    pub sequence_number: u32,
+    // A BCBP-style string for a barcode.
+    pub barcode: String,
+}
+
+// The numbers handed out at check-in on each flight. Numbers are never reused, even if a checked in
+// booking is cancelled.
+pub struct CheckInCounters {
+    sequence_numbers: HashMap<u32, u32>,
+    // Seats of flights without a seat map are numbered within each class at check-in.
+    open_seats: HashMap<(u32, SeatClass), u32>,
+}
+
+impl CheckInCounters {
+    pub fn new() -> CheckInCounters {
+        CheckInCounters {
+            sequence_numbers: HashMap::new(),
+            open_seats: HashMap::new(),
+        }
+    }
+
+    // Checks in one passenger of the booking and returns their boarding pass. A passenger who has already
+    // checked in gets the same boarding pass again. The passenger's details must have been given, and their
+    // baggage must be within the limits of the policy.
+    pub fn check_in<'a>(
+        &mut self,
+        flight: &Flight,
+        booking: &'a mut Booking,
+        passenger_number: u8,
+        baggage_policy: &BaggagePolicy,
+        now: u32,
+    ) -> Result<&'a BoardingPass, String> {
+        check_in_open(flight, now)?;
+
+        let seat_class = booking.seat_class;
+        let booking_id = booking.id;
+        let seat = booking
+            .seats
+            .get(usize::from(passenger_number).wrapping_sub(1))
+            .cloned();
+        let passenger = booking.passenger_mut(passenger_number)?;
+        if passenger.boarding_pass.is_none() {
+            let details = passenger
+                .details
+                .as_ref()
+                .ok_or("Give the passenger's details before checking in.")?;
+            if !passenger.baggage.pieces_kg.is_empty() {
+                baggage_policy.check(&PassengerBaggage::default(), &passenger.baggage.pieces_kg)?;
+            }
+
+            // Seats of flights with a seat map are assigned at booking.
+            let seat = seat.unwrap_or_else(|| {
+                let number = self.open_seats.entry((flight.id, seat_class)).or_insert(0);
+                *number += 1;
+                format!("{}{}", compartment_code(seat_class), number)
+            });
+            let sequence_number = self.sequence_numbers.entry(flight.id).or_insert(0);
+            *sequence_number += 1;
+
+            passenger.boarding_pass = Some(BoardingPass {
+                barcode: bcbp_barcode(
+                    details,
+                    booking_id,
+                    flight,
+                    seat_class,
+                    &seat,
+                    *sequence_number,
+                ),
+                seat,
+                boarding_group: boarding_group(seat_class),
+                sequence_number: *sequence_number,
+            });
+        }
+        Ok(passenger.boarding_pass.as_ref().unwrap())
+    }
+}
+
+// Check-in is open from `CHECK_IN_OPENS_SECONDS` until `CHECK_IN_CLOSES_SECONDS` before departure, while
+// the flight is still taking bookings.
+fn check_in_open(flight: &Flight, now: u32) -> Result<(), String> {
+    if !flight.is_bookable() {
+        return Err(format!(
+            "Check-in is closed because the flight is {}.",
+            flight.status
+        ));
+    }
+    if now < flight.departure_time.saturating_sub(CHECK_IN_OPENS_SECONDS) {
+        return Err(format!(
+            "Check-in opens {} hours before departure.",
+            CHECK_IN_OPENS_SECONDS / 3600
+        ));
+    }
+    if now
+        > flight
+            .departure_time
+            .saturating_sub(CHECK_IN_CLOSES_SECONDS)
+    {
+        return Err(format!(
+            "Check-in closed {} minutes before departure.",
+            CHECK_IN_CLOSES_SECONDS / 60
+        ));
+    }
+    Ok(())
+}
+
+// Business boards first, then premium, then economy.
+fn boarding_group(seat_class: SeatClass) -> u8 {
+    match seat_class {
+        SeatClass::Business => 1,
+        SeatClass::Premium => 2,
+        SeatClass::Economy => 3,
+    }
+}
+
+fn compartment_code(seat_class: SeatClass) -> char {
+    match seat_class {
+        SeatClass::Business => 'J',
+        SeatClass::Premium => 'W',
+        SeatClass::Economy => 'Y',
+    }
+}
+
+// The mandatory fields of an IATA bar coded boarding pass for one leg: format code and number of legs,
+// passenger name, electronic ticket indicator, booking reference, from, to, carrier, flight number, day of
+// the year of departure, compartment, seat, check-in sequence number, passenger status (1, checked in) and
+// the size of the conditional fields (00, none). Every field is padded or cut to its fixed width.
+fn bcbp_barcode(
+    details: &Passenger,
+    booking_id: u32,
+    flight: &Flight,
+    seat_class: SeatClass,
+    seat: &str,
+    sequence_number: u32,
+) -> String {
+    // Surname last, given names first in the passenger's name.
+    let name = match details.name.rsplit_once(' ') {
+        Some((given_names, surname)) => format!("{surname}/{given_names}"),
+        None => details.name.clone(),
+    };
+    let seat = match seat.find(|c: char| !c.is_ascii_digit()) {
+        // A row number and a letter, with the row padded to three digits.
+        Some(letter_index) if letter_index > 0 => format!("{:0>4}", seat),
+        _ => seat.to_string(),
+    };
+    format!(
+        "M1{}E{}{}{}{}{}{:03}{}{}{}100",
+        bcbp_field(&name, 20),
+        bcbp_field(&format!("{booking_id:07}"), 7),
+        bcbp_field(&flight.source, 3),
+        bcbp_field(&flight.destination, 3),
+        bcbp_field(AIRLINE_CODE, 3),
+        bcbp_field(&format!("{:04}", flight.id), 5),
+        day_of_year(flight.departure_time),
+        compartment_code(seat_class),
+        bcbp_field(&seat, 4),
+        bcbp_field(&format!("{sequence_number:04}"), 5),
+    )
+}
+
+// Upper case ASCII, with anything else dropped, padded with spaces or cut to the width.
+fn bcbp_field(value: &str, width: usize) -> String {
+    let value = value
+        .to_ascii_uppercase()
+        .chars()
+        .filter(|c| c.is_ascii_graphic() || *c == ' ')
+        .take(width)
+        .collect::<String>();
+    format!("{value:<width$}")
+}
+
+// The day of the year of a Unix time in UTC, from 1.
+fn day_of_year(unix_time: u32) -> i64 {
+    let days = i64::from(unix_time / 86400);
+    // Estimate the year, then step to the year that contains the day.
+    let mut year = 1970 + days * 400 / 146097;
+    while days_from_civil((year + 1, 1, 1)) <= days {
+        year += 1;
+    }
+    while days_from_civil((year, 1, 1)) > days {
+        year -= 1;
+    }
+    days - days_from_civil((year, 1, 1)) + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use marshaling::{Currency, Money};
+
+    use super::*;
+    use crate::flight::{Cabin, FlightStatus};
+
+    // 2024-03-01 10:00 UTC, the 61st day of a leap year.
+    const DEPARTURE: u32 = 1_709_287_200;
+
+    // The width of each mandatory field, in order.
+    const FIELD_WIDTHS: [usize; 14] = [2, 20, 1, 7, 3, 3, 3, 5, 3, 1, 4, 5, 1, 2];
+
+    fn flight(id: u32) -> Flight {
+        let sgd = |minor_units| Money::new(minor_units, Currency::parse("SGD").unwrap());
+        Flight {
+            id,
+            source: "SIN".to_string(),
+            destination: "KUL".to_string(),
+            departure_time: DEPARTURE,
+            cabins: [
+                Cabin::new(10, sgd(1000)),
+                Cabin::new(10, sgd(2000)),
+                Cabin::new(10, sgd(3000)),
+            ],
+            baggage_capacity_kg: 0,
+            baggage_reserved_kg: 0,
+            status: FlightStatus::Scheduled,
+            gate: "A1".to_string(),
+            seat_map: None,
+            overbooking_percent: 0,
+        }
+    }
+
+    fn details(name: &str) -> Passenger {
+        Passenger {
+            name: name.to_string(),
+            document_number: "E1234567".to_string(),
+            date_of_birth: "1990-01-31".to_string(),
+            contact: "mary@example.com".to_string(),
+        }
+    }
+
+    // Splits the barcode into its mandatory fields.
+    fn fields(barcode: &str) -> Vec<&str> {
+        let mut start = 0;
+        FIELD_WIDTHS
+            .iter()
+            .map(|width| {
+                start += width;
+                &barcode[start - width..start]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn the_day_of_the_year_counts_from_1() {
+        let unix_time = |date| (days_from_civil(date) * 86400) as u32;
+        assert_eq!(day_of_year(0), 1);
+        assert_eq!(day_of_year(DEPARTURE), 61);
+        assert_eq!(day_of_year(unix_time((2023, 3, 1))), 60);
+        assert_eq!(day_of_year(unix_time((2023, 12, 31)) + 86399), 365);
+        assert_eq!(day_of_year(unix_time((2024, 12, 31)) + 86399), 366);
+        assert_eq!(day_of_year(unix_time((2025, 1, 1))), 1);
+        assert_eq!(day_of_year(u32::MAX), 38);
+    }
+
+    #[test]
+    fn barcodes_have_the_mandatory_fields_in_order() {
+        let barcode = bcbp_barcode(
+            &details("Mary Ann Lee"),
+            42,
+            &flight(7),
+            SeatClass::Business,
+            "12A",
+            3,
+        );
+        assert_eq!(
+            barcode,
+            "M1LEE/MARY ANN        E0000042SINKULRF 0007 061J012A0003 100"
+        );
+        assert_eq!(
+            fields(&barcode),
+            [
+                "M1",
+                "LEE/MARY ANN        ",
+                "E",
+                "0000042",
+                "SIN",
+                "KUL",
+                "RF ",
+                "0007 ",
+                "061",
+                "J",
+                "012A",
+                "0003 ",
+                "1",
+                "00"
+            ]
+        );
+    }
+
+    #[test]
+    fn barcode_fields_are_cut_to_their_widths() {
+        let barcode = bcbp_barcode(
+            &details("Ångström Hubert Müllerwolfeschlegelsteinhausen"),
+            123_456_789,
+            &flight(1_234_567),
+            SeatClass::Economy,
+            "Y12345",
+            1_234_567,
+        );
+        assert_eq!(barcode.len(), FIELD_WIDTHS.iter().sum::<usize>());
+        let cut_fields = fields(&barcode);
+        // Non-ASCII letters are dropped.
+        assert_eq!(cut_fields[1], "MLLERWOLFESCHLEGELST");
+        assert_eq!(cut_fields[3], "1234567");
+        assert_eq!(cut_fields[7], "12345");
+        assert_eq!(cut_fields[9], "Y");
+        assert_eq!(cut_fields[10], "Y123");
+        assert_eq!(cut_fields[11], "12345");
+
+        // A name without spaces is used as it is.
+        let barcode = bcbp_barcode(
+            &details("Madonna"),
+            1,
+            &flight(1),
+            SeatClass::Premium,
+            "W1",
+            1,
+        );
+        assert_eq!(barcode.len(), FIELD_WIDTHS.iter().sum::<usize>());
+        assert_eq!(fields(&barcode)[1], "MADONNA             ");
+        assert_eq!(fields(&barcode)[10], "W1  ");
+    }
+}
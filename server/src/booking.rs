@@ -0,0 +1,172 @@
+use std::{collections::HashMap, net::SocketAddr};
+
+use marshaling::Money;
+
+use crate::{
+    baggage::PassengerBaggage,
+    boarding::BoardingPass,
+    flight::{Flight, SeatClass, SeatSelection},
+    passenger::Passenger,
+};
+
+// A successful seat reservation, kept so that the holder can be told about changes to their flight.
+pub struct Booking {
+    pub id: u32,
+    pub flight_id: u32,
+    // The user who made the booking. Only they can change it.
+    pub user_id: u32,
+    // Where the user made the booking from, which is told about changes to the flight.
+    pub client_addr: SocketAddr,
+    pub seat_class: SeatClass,
+    pub num_seats: u32,
+    // The fare of each seat when it was booked.
+    pub airfare: Money,
+    // The assigned seats, empty if the flight has no seat map.
+    pub seats: Vec<String>,
+    // One for every seat, in the order of the seats.
+    pub passengers: Vec<BookedPassenger>,
+}
+
+// Who travels on one seat of a booking, and what they have done since it was booked.
+#[derive(Clone, Default)]
+pub struct BookedPassenger {
+    // None until the client gives them.
+    pub details: Option<Passenger>,
+    pub baggage: PassengerBaggage,
+    // Issued at check-in.
+    pub boarding_pass: Option<BoardingPass>,
+}
+
+impl Booking {
+    pub fn baggage_kg(&self) -> u32 {
+        self.passengers
+            .iter()
+            .map(|passenger| passenger.baggage.total_kg())
+            .sum()
+    }
+
+    // Passengers are numbered from 1 in the order of the seats.
+    pub fn passenger_mut(&mut self, passenger_number: u8) -> Result<&mut BookedPassenger, String> {
+        let num_passengers = self.passengers.len();
+        self.passengers
+            .get_mut(usize::from(passenger_number).wrapping_sub(1))
+            .ok_or_else(|| {
+                format!("Invalid passenger. The booking has passengers 1 to {num_passengers}.")
+            })
+    }
+}
+
+pub struct BookingStore {
+    bookings: HashMap<u32, Booking>,
+    next_booking_id: u32,
+}
+
+impl BookingStore {
+    pub fn new() -> BookingStore {
+        BookingStore {
+            bookings: HashMap::new(),
+            next_booking_id: 1,
+        }
+    }
+
+    // Reserves the selected seats on the flight for the user at the given fare per seat and records the
+    // booking. Either every seat is reserved or none are. Returns the booking reference and the assigned seats.
+    pub fn book(
+        &mut self,
+        flight: &mut Flight,
+        user_id: u32,
+        client_addr: SocketAddr,
+        seat_class: SeatClass,
+        selection: &SeatSelection,
+        airfare: Money,
+    ) -> Result<(u32, Vec<String>), String> {
+        if !flight.is_bookable() {
+            return Err(format!(
+                "Seats can no longer be reserved on this flight because it is {}.",
+                flight.status
+            ));
+        }
+
+        let num_seats = selection.num_seats();
+        let current_seats = flight.available_seats(Some(seat_class));
+        if current_seats < num_seats {
+            return Err(format!("Not enough seats available. You tried to reserve {num_seats} {seat_class} seats, but there are only {current_seats} {seat_class} seats available."));
+        }
+
+        // Work out the seats before taking any of them, so that a taken seat fails the whole reservation.
+        let seats = flight.select_seats(seat_class, selection)?;
+        flight.reserve_seats(seat_class, num_seats);
+
+        let id = self.next_booking_id;
+        self.next_booking_id += 1;
+        if let Some(seat_map) = flight.seat_map.as_mut() {
+            seat_map.occupy(&seats, id);
+        }
+        self.bookings.insert(
+            id,
+            Booking {
+                id,
+                flight_id: flight.id,
+                user_id,
+                client_addr,
+                seat_class,
+                num_seats,
+                airfare,
+                seats: seats.clone(),
+                passengers: vec![BookedPassenger::default(); num_seats as usize],
+            },
+        );
+        Ok((id, seats))
+    }
+
+    pub fn get(&self, booking_id: &u32) -> Option<&Booking> {
+        self.bookings.get(booking_id)
+    }
+
+    pub fn get_mut(&mut self, booking_id: &u32) -> Option<&mut Booking> {
+        self.bookings.get_mut(booking_id)
+    }
+
+    // The booking, if it was made by the user.
+    pub fn get_owned(&self, booking_id: &u32, user_id: u32) -> Option<&Booking> {
+        self.get(booking_id)
+            .filter(|booking| booking.user_id == user_id)
+    }
+
+    pub fn get_owned_mut(&mut self, booking_id: &u32, user_id: u32) -> Option<&mut Booking> {
+        self.get_mut(booking_id)
+            .filter(|booking| booking.user_id == user_id)
+    }
+
+    // Removes the booking and gives its seats and baggage capacity back to the flight.
+    pub fn cancel(&mut self, booking_id: u32, flight: &mut Flight) -> Option<Booking> {
+        let booking = self.bookings.remove(&booking_id)?;
+        let cabin = flight.cabin_mut(booking.seat_class);
+        cabin.sold = cabin.sold.saturating_sub(booking.num_seats);
+        if let Some(seat_map) = flight.seat_map.as_mut() {
+            seat_map.release(&booking.seats);
+        }
+        flight.baggage_reserved_kg = flight
+            .baggage_reserved_kg
+            .saturating_sub(booking.baggage_kg());
+        Some(booking)
+    }
+
+    pub fn for_flight(&self, flight_id: u32) -> Vec<&Booking> {
+        self.bookings
+            .values()
+            .filter(|booking| booking.flight_id == flight_id)
+            .collect()
+    }
+
+    // The user's bookings, oldest first.
+    pub fn for_user(&self, user_id: u32) -> Vec<&Booking> {
+        let mut bookings = self
+            .bookings
+            .values()
+            .filter(|booking| booking.user_id == user_id)
+            .collect::<Vec<&Booking>>();
+        bookings.sort_by_key(|booking| booking.id);
+        bookings
+    }
+}
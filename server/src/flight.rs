@@ -0,0 +1,311 @@
+use std::fmt;
+
+use marshaling::{marshal_money, marshal_string, marshal_u32, marshal_u8, Money};
+
+use crate::{overbooking::sellable_seats, pricing::PricingEngine, seat_map::SeatMap};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FlightStatus {
+    Scheduled,
+    Boarding,
+    Departed,
+    Delayed,
+    Cancelled,
+    Arrived,
+}
+
+impl FlightStatus {
+    pub fn from_u8(value: u8) -> Option<FlightStatus> {
+        match value {
+            1 => Some(FlightStatus::Scheduled),
+            2 => Some(FlightStatus::Boarding),
+            3 => Some(FlightStatus::Departed),
+            4 => Some(FlightStatus::Delayed),
+            5 => Some(FlightStatus::Cancelled),
+            6 => Some(FlightStatus::Arrived),
+            _ => None,
+        }
+    }
+
+    pub fn to_u8(self) -> u8 {
+        match self {
+            FlightStatus::Scheduled => 1,
+            FlightStatus::Boarding => 2,
+            FlightStatus::Departed => 3,
+            FlightStatus::Delayed => 4,
+            FlightStatus::Cancelled => 5,
+            FlightStatus::Arrived => 6,
+        }
+    }
+
+    // A flight moves forward through its lifecycle, except that a delay can be lifted back to scheduled
+    // and boarding can be interrupted by a delay. Cancelled and arrived flights never change again.
+    pub fn can_transition_to(self, next: FlightStatus) -> bool {
+        use FlightStatus::*;
+        matches!(
+            (self, next),
+            (Scheduled, Boarding | Delayed | Cancelled)
+                | (Delayed, Scheduled | Boarding | Delayed | Cancelled)
+                | (Boarding, Departed | Delayed | Cancelled)
+                | (Departed, Arrived)
+        )
+    }
+}
+
+impl fmt::Display for FlightStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FlightStatus::Scheduled => write!(f, "scheduled"),
+            FlightStatus::Boarding => write!(f, "boarding"),
+            FlightStatus::Departed => write!(f, "departed"),
+            FlightStatus::Delayed => write!(f, "delayed"),
+            FlightStatus::Cancelled => write!(f, "cancelled"),
+            FlightStatus::Arrived => write!(f, "arrived"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Eq, Hash, PartialEq)]
+pub enum SeatClass {
+    Economy,
+    Premium,
+    Business,
+}
+
+impl SeatClass {
+    pub const ALL: [SeatClass; 3] = [SeatClass::Economy, SeatClass::Premium, SeatClass::Business];
+
+    pub fn from_u8(value: u8) -> Option<SeatClass> {
+        match value {
+            0 => Some(SeatClass::Economy),
+            1 => Some(SeatClass::Premium),
+            2 => Some(SeatClass::Business),
+            _ => None,
+        }
+    }
+
+    pub fn to_u8(self) -> u8 {
+        match self {
+            SeatClass::Economy => 0,
+            SeatClass::Premium => 1,
+            SeatClass::Business => 2,
+        }
+    }
+}
+
+impl fmt::Display for SeatClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SeatClass::Economy => write!(f, "economy"),
+            SeatClass::Premium => write!(f, "premium"),
+            SeatClass::Business => write!(f, "business"),
+        }
+    }
+}
+
+// The seat inventory and fare of one seat class on a flight.
+#[derive(Clone, Copy)]
+pub struct Cabin {
+    // Physical seats in the cabin.
+    pub seats: u32,
+    // Seats sold, which can be more than the physical seats on an overbooked flight.
+    pub sold: u32,
+    // The fare set for the class, which the pricing engine adjusts to give the current fare.
+    pub base_airfare: Money,
+    pub airfare: Money,
+}
+
+impl Cabin {
+    // An empty cabin, priced at its base fare until it is first repriced.
+    pub fn new(seats: u32, base_airfare: Money) -> Cabin {
+        Cabin {
+            seats,
+            sold: 0,
+            base_airfare,
+            airfare: base_airfare,
+        }
+    }
+}
+
+// How seats are chosen when reserving on a flight with a seat map.
+pub enum SeatSelection {
+    // Let the server pick this many seats, adjacent where possible.
+    Auto(u32),
+    // Exactly these seats, e.g. "12A".
+    Chosen(Vec<String>),
+}
+
+impl SeatSelection {
+    pub fn num_seats(&self) -> u32 {
+        match self {
+            SeatSelection::Auto(num_seats) => *num_seats,
+            SeatSelection::Chosen(seats) => seats.len() as u32,
+        }
+    }
+}
+
+pub struct Flight {
+    pub id: u32,
+    pub source: String,
+    pub destination: String,
+    pub departure_time: u32, // Unix time
+    pub cabins: [Cabin; 3],  // Indexed by SeatClass
+    // The physical baggage capacity, and how much of it is reserved.
+    pub baggage_capacity_kg: u32,
+    pub baggage_reserved_kg: u32,
+    pub status: FlightStatus,
+    // The departure gate, "TBA" until it is announced.
+    pub gate: String,
+    // Flights without a seat map only track seat counts.
+    pub seat_map: Option<SeatMap>,
+    // How many seats beyond the physical seats of each class can be sold, as a percentage of them.
+    pub overbooking_percent: u32,
+}
+
+impl Flight {
+    // Seats and baggage can only be reserved before boarding starts.
+    pub fn is_bookable(&self) -> bool {
+        matches!(self.status, FlightStatus::Scheduled | FlightStatus::Delayed)
+    }
+
+    pub fn cabin(&self, seat_class: SeatClass) -> &Cabin {
+        &self.cabins[seat_class.to_u8() as usize]
+    }
+
+    pub fn cabin_mut(&mut self, seat_class: SeatClass) -> &mut Cabin {
+        &mut self.cabins[seat_class.to_u8() as usize]
+    }
+
+    // Seats that can be sold in the class. Every seat of a flight with a seat map is a physical seat, so
+    // those flights are never overbooked.
+    pub fn sellable_seats(&self, seat_class: SeatClass) -> u32 {
+        let cabin = self.cabin(seat_class);
+        match self.seat_map {
+            Some(_) => cabin.seats,
+            None => sellable_seats(cabin.seats, self.overbooking_percent),
+        }
+    }
+
+    // Seats still for sale in the given class, or across every class if none is given.
+    pub fn available_seats(&self, seat_class: Option<SeatClass>) -> u32 {
+        match seat_class {
+            Some(seat_class) => self
+                .sellable_seats(seat_class)
+                .saturating_sub(self.cabin(seat_class).sold),
+            None => SeatClass::ALL
+                .into_iter()
+                .map(|seat_class| self.available_seats(Some(seat_class)))
+                .sum(),
+        }
+    }
+
+    // The fare of the given class, or the cheapest fare that can still be booked if no class is given.
+    // A sold out flight quotes its cheapest fare.
+    pub fn airfare(&self, seat_class: Option<SeatClass>) -> Money {
+        match seat_class {
+            Some(seat_class) => self.cabin(seat_class).airfare,
+            None => SeatClass::ALL
+                .into_iter()
+                .filter(|seat_class| self.available_seats(Some(*seat_class)) > 0)
+                .map(|seat_class| self.cabin(seat_class).airfare)
+                .min_by_key(|airfare| airfare.minor_units)
+                .unwrap_or_else(|| {
+                    self.cabins
+                        .iter()
+                        .map(|cabin| cabin.airfare)
+                        .min_by_key(|airfare| airfare.minor_units)
+                        .unwrap()
+                }),
+        }
+    }
+
+    // Works out which seats a reservation would take, without taking them. Flights without a seat map
+    // assign no seats, and do not accept chosen seats.
+    pub fn select_seats(
+        &self,
+        seat_class: SeatClass,
+        selection: &SeatSelection,
+    ) -> Result<Vec<String>, String> {
+        match (&self.seat_map, selection) {
+            (None, SeatSelection::Auto(_)) => Ok(vec![]),
+            (None, SeatSelection::Chosen(_)) => {
+                Err("This flight does not have a seat map to choose seats from.".to_string())
+            }
+            (Some(seat_map), SeatSelection::Auto(num_seats)) => {
+                seat_map.pick_seats(seat_class, *num_seats)
+            }
+            (Some(seat_map), SeatSelection::Chosen(seats)) => {
+                seat_map.check_seats(seat_class, seats)
+            }
+        }
+    }
+
+    // Replaces the seat map, after which the seats of each class follow the map. The flight must not have
+    // any bookings yet.
+    pub fn set_seat_map(&mut self, seat_map: SeatMap) {
+        for seat_class in SeatClass::ALL {
+            self.cabin_mut(seat_class).seats = seat_map.free_seats(seat_class);
+        }
+        self.seat_map = Some(seat_map);
+    }
+
+    // Updates the current fare of every class.
+    pub fn reprice(&mut self, pricing: &PricingEngine, now: u32) {
+        for seat_class in SeatClass::ALL {
+            let airfare = pricing.fare(self, seat_class, now);
+            self.cabin_mut(seat_class).airfare = airfare;
+        }
+    }
+
+    pub fn reserve_seats(&mut self, seat_class: SeatClass, num_seats: u32) -> bool {
+        if self.available_seats(Some(seat_class)) >= num_seats {
+            self.cabin_mut(seat_class).sold += num_seats;
+            true
+        } else {
+            false
+        }
+    }
+
+    // Baggage capacity that is not reserved yet.
+    pub fn remaining_baggage_kg(&self) -> u32 {
+        self.baggage_capacity_kg
+            .saturating_sub(self.baggage_reserved_kg)
+    }
+
+    pub fn reserve_baggage(&mut self, baggage_kg: u32) -> bool {
+        if self.remaining_baggage_kg() >= baggage_kg {
+            self.baggage_reserved_kg += baggage_kg;
+            true
+        } else {
+            false
+        }
+    }
+
+    // Appends the full listing of a flight to the buffer, used by services that return many flights at once.
+    // The listing gives the seats across every class and the cheapest fare; see `marshal_cabins` for the rest.
+    // Fares are shown as converted by `display`.
+    pub fn marshal_listing(&self, display: &impl Fn(Money) -> Money, buf: &mut Vec<u8>) {
+        marshal_u32(self.id, buf);
+        marshal_string(&self.source, buf);
+        marshal_string(&self.destination, buf);
+        marshal_u32(self.departure_time, buf);
+        marshal_money(display(self.airfare(None)), buf);
+        marshal_u32(self.available_seats(None), buf);
+        marshal_u32(self.remaining_baggage_kg(), buf);
+        marshal_u8(self.status.to_u8(), buf);
+    }
+
+    // Appends the seats for sale and fare of every class to the buffer, followed by the seats sold and
+    // the physical seats, prefixed by the number of classes. Fares are shown as converted by `display`.
+    pub fn marshal_cabins(&self, display: &impl Fn(Money) -> Money, buf: &mut Vec<u8>) {
+        marshal_u8(SeatClass::ALL.len() as u8, buf);
+        for seat_class in SeatClass::ALL {
+            let cabin = self.cabin(seat_class);
+            marshal_u8(seat_class.to_u8(), buf);
+            marshal_u32(self.available_seats(Some(seat_class)), buf);
+            marshal_money(display(cabin.airfare), buf);
+            marshal_u32(cabin.sold, buf);
+            marshal_u32(cabin.seats, buf);
+        }
+    }
+}
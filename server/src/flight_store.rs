@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+
+use marshaling::Money;
+
+use crate::{
+    flight::{Flight, SeatClass},
+    pricing::PricingEngine,
+};
+
+// Upper bound on the number of flights returned in a single page, so that a response always fits in one datagram.
+pub const MAX_PAGE_SIZE: u32 = 40;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SortKey {
+    DepartureTime,
+    Airfare,
+    // Most seats available first.
+    Seats,
+}
+
+impl SortKey {
+    pub fn from_u8(value: u8) -> Option<SortKey> {
+        match value {
+            0 => Some(SortKey::DepartureTime),
+            1 => Some(SortKey::Airfare),
+            2 => Some(SortKey::Seats),
+            _ => None,
+        }
+    }
+}
+
+// A search over the flight store. Every filter is optional; `None` means "do not filter on this field".
+pub struct FlightQuery {
+    pub source: Option<String>,
+    pub destination: Option<String>,
+    pub departure_after: Option<u32>,
+    pub departure_before: Option<u32>,
+    // When given, the seat and airfare filters and sorting look at this class only.
+    pub seat_class: Option<SeatClass>,
+    pub min_seats: Option<u32>,
+    // In the currency the flights are priced in.
+    pub max_airfare: Option<Money>,
+    pub min_baggage_capacity_kg: Option<u32>,
+    // Only match flights that can still be booked, leaving out cancelled and departed ones.
+    pub bookable_only: bool,
+    pub sort_by: SortKey,
+    pub offset: u32,
+    pub limit: u32,
+}
+
+impl Default for FlightQuery {
+    fn default() -> FlightQuery {
+        FlightQuery {
+            source: None,
+            destination: None,
+            departure_after: None,
+            departure_before: None,
+            seat_class: None,
+            min_seats: None,
+            max_airfare: None,
+            min_baggage_capacity_kg: None,
+            bookable_only: false,
+            sort_by: SortKey::DepartureTime,
+            offset: 0,
+            limit: MAX_PAGE_SIZE,
+        }
+    }
+}
+
+impl FlightQuery {
+    pub fn matches(&self, flight: &Flight) -> bool {
+        self.source
+            .as_ref()
+            .is_none_or(|source| flight.source == *source)
+            && self
+                .destination
+                .as_ref()
+                .is_none_or(|destination| flight.destination == *destination)
+            && self
+                .departure_after
+                .is_none_or(|after| flight.departure_time >= after)
+            && self
+                .departure_before
+                .is_none_or(|before| flight.departure_time <= before)
+            && self
+                .min_seats
+                .is_none_or(|seats| flight.available_seats(self.seat_class) >= seats)
+            && self.max_airfare.is_none_or(|airfare| {
+                flight.airfare(self.seat_class).minor_units <= airfare.minor_units
+            })
+            && self
+                .min_baggage_capacity_kg
+                .is_none_or(|kg| flight.remaining_baggage_kg() >= kg)
+            && (!self.bookable_only || flight.is_bookable())
+    }
+}
+
+// The result of running a `FlightQuery`: one page of flights plus the total number of matches across all pages.
+pub struct QueryPage<'a> {
+    pub total_matches: u32,
+    pub flights: Vec<&'a Flight>,
+}
+
+pub struct FlightStore {
+    flights: HashMap<u32, Flight>,
+    // The IDs of the flights on each route, by source and destination, which never change once a flight is added.
+    routes: HashMap<(String, String), Vec<u32>>,
+}
+
+impl FlightStore {
+    pub fn new() -> FlightStore {
+        FlightStore {
+            flights: HashMap::new(),
+            routes: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, flight: Flight) {
+        let flight_id = flight.id;
+        let route = (flight.source.clone(), flight.destination.clone());
+        if self.flights.insert(flight_id, flight).is_none() {
+            self.routes.entry(route).or_default().push(flight_id);
+        }
+    }
+
+    pub fn get(&self, flight_id: &u32) -> Option<&Flight> {
+        self.flights.get(flight_id)
+    }
+
+    pub fn get_mut(&mut self, flight_id: &u32) -> Option<&mut Flight> {
+        self.flights.get_mut(flight_id)
+    }
+
+    // The lowest flight ID above every flight currently in the store.
+    pub fn next_flight_id(&self) -> u32 {
+        self.flights.keys().max().map_or(1, |max_id| max_id + 1)
+    }
+
+    pub fn contains_key(&self, flight_id: &u32) -> bool {
+        self.flights.contains_key(flight_id)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Flight> {
+        self.flights.values()
+    }
+
+    // The flights from the source to the destination, in the order they were added.
+    pub fn on_route<'a>(
+        &'a self,
+        source: &str,
+        destination: &str,
+    ) -> impl Iterator<Item = &'a Flight> + 'a {
+        self.routes
+            .get(&(source.to_string(), destination.to_string()))
+            .into_iter()
+            .flatten()
+            .filter_map(|flight_id| self.flights.get(flight_id))
+    }
+
+    // Updates the current fares of every flight, which change over time as well as with every booking.
+    pub fn reprice_all(&mut self, pricing: &PricingEngine, now: u32) {
+        for flight in self.flights.values_mut() {
+            flight.reprice(pricing, now);
+        }
+    }
+
+    pub fn query(&self, query: &FlightQuery) -> QueryPage<'_> {
+        // Only the flights on the route need to be looked at when the query gives one.
+        let candidates: Box<dyn Iterator<Item = &Flight>> =
+            match (&query.source, &query.destination) {
+                (Some(source), Some(destination)) => Box::new(self.on_route(source, destination)),
+                _ => Box::new(self.flights.values()),
+            };
+        let mut matches = candidates
+            .filter(|flight| query.matches(flight))
+            .collect::<Vec<&Flight>>();
+
+        // Sort by the requested key, falling back to the flight ID so that pages are stable between requests.
+        matches.sort_by(|a, b| {
+            let ordering = match query.sort_by {
+                SortKey::DepartureTime => a.departure_time.cmp(&b.departure_time),
+                SortKey::Airfare => a
+                    .airfare(query.seat_class)
+                    .minor_units
+                    .cmp(&b.airfare(query.seat_class).minor_units),
+                SortKey::Seats => b
+                    .available_seats(query.seat_class)
+                    .cmp(&a.available_seats(query.seat_class)),
+            };
+            ordering.then(a.id.cmp(&b.id))
+        });
+
+        let total_matches = matches.len() as u32;
+        let flights = matches
+            .into_iter()
+            .skip(query.offset as usize)
+            .take(query.limit.min(MAX_PAGE_SIZE) as usize)
+            .collect();
+
+        QueryPage {
+            total_matches,
+            flights,
+        }
+    }
+}
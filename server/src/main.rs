@@ -1,44 +1,52 @@
 use std::{
     collections::HashMap,
-    fmt,
+    fmt, io,
     net::{SocketAddr, UdpSocket},
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use marshaling::{
-    self, marshal_f32, marshal_string, marshal_u32, marshal_u32_array, unmarshal_string,
-    unmarshal_u32, unmarshal_u8,
+    self, marshal_money, marshal_string, marshal_u32, marshal_u32_array, marshal_u8,
+    unmarshal_optional_money, unmarshal_optional_string, unmarshal_optional_u32,
+    unmarshal_optional_u8, unmarshal_string, unmarshal_u32, unmarshal_u32_array, unmarshal_u8,
+    Currency, Money,
 };
-use networking;
-
-struct Flight {
-    id: u32,
-    source: String,
-    destination: String,
-    departure_time: u32, // Unix time
-    seats: u32,
-    airfare: f32,
-    baggage_capacity_kg: u32,
-}
-impl Flight {
-    fn reserve_seats(&mut self, num_seats: u32) -> bool {
-        if self.seats >= num_seats {
-            self.seats -= num_seats;
-            true
-        } else {
-            false
-        }
-    }
 
-    fn reserve_baggage(&mut self, baggage_kg: u32) -> bool {
-        if self.baggage_capacity_kg >= baggage_kg {
-            self.baggage_capacity_kg -= baggage_kg;
-            true
-        } else {
-            false
-        }
-    }
-}
+mod access;
+mod account;
+mod admin;
+mod baggage;
+mod boarding;
+mod booking;
+mod flight;
+mod flight_store;
+mod manifest;
+mod overbooking;
+mod passenger;
+mod pricing;
+mod quote;
+mod rates;
+mod seat_map;
+mod subscription;
+mod waitlist;
+
+use access::Access;
+use account::{AccountStore, Role, SessionSigner};
+use baggage::BaggagePolicy;
+use boarding::CheckInCounters;
+use booking::BookingStore;
+use flight::{Cabin, Flight, FlightStatus, SeatClass, SeatSelection};
+use flight_store::{FlightQuery, FlightStore, SortKey};
+use passenger::Passenger;
+use pricing::PricingEngine;
+use quote::QuoteStore;
+use rates::RateTable;
+use seat_map::SeatMap;
+use subscription::{
+    marshal_route_flight, marshal_target, marshal_watched_values, SubscriptionStore, Target, Watch,
+    ALL_FIELDS, ROUTE_FLIGHT_LISTED,
+};
+use waitlist::Waitlist;
 
 struct ResponseCacheValue {
     response_payload: Vec<u8>,
@@ -50,8 +58,31 @@ struct ResponseCacheKey {
     client_addr: SocketAddr,
 }
 
-#[derive(Eq, Hash, PartialEq, Clone)]
-struct WatchlistEntry(u32, SocketAddr);
+// The status byte of a response refusing a request because the caller's role may not use the service.
+const FORBIDDEN: u8 = 255;
+
+// Upper bound on the bookings in one response of the list bookings service, so that it fits in one datagram.
+const MAX_LISTED_BOOKINGS: usize = 16;
+
+// How often the server checks for seat updates to resend while waiting for requests.
+const RETRANSMIT_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+// How often the server drops expired subscriptions. Subscriptions expire on whole seconds.
+const EXPIRY_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+// Upper bound on the subscriptions in one response of the list subscriptions service. A subscription to a
+// route takes up to 75 bytes.
+const MAX_LISTED_SUBSCRIPTIONS: usize = 24;
+
+// Upper bound on the flights of a route in one resync response.
+const MAX_RESYNCED_ROUTE_FLIGHTS: usize = 40;
+
+// Upper bound on the length of the source and destination of a monitored route, so that subscriptions can be
+// listed in one response.
+const MAX_ROUTE_NAME_LENGTH: usize = 32;
+
+// Upper bound on how long a subscription lasts, one year in seconds.
+const MAX_MONITOR_INTERVAL: u32 = 31_536_000;
 
 #[derive(PartialEq)]
 enum InvocationSemantics {
@@ -71,7 +102,8 @@ impl fmt::Debug for InvocationSemantics {
 fn main() -> std::io::Result<()> {
     let args: Vec<String> = std::env::args().collect();
 
-    let usage_message = "Usage: cargo run --bin server alo|amo true|false";
+    let usage_message =
+        "Usage: cargo run --bin server alo|amo true|false [admin_key] [rate_file] [key_file]";
 
     // If the number of arguments is not 2, then print usage and exit.
     if args.len() < 3 {
@@ -92,6 +124,42 @@ fn main() -> std::io::Result<()> {
         return Ok(());
     }
 
+    // Admins can only be registered when an admin key is given.
+    let admin_key = args.get(3).map(|admin_key| admin_key.as_str());
+    if admin_key.is_some_and(|admin_key| admin_key.is_empty() || admin_key.len() > 255) {
+        println!("Error: The admin key must be between 1 and 255 bytes long.");
+        return Ok(());
+    }
+
+    // Fares are priced in the base currency of the rate file, and can be shown in any currency it has a
+    // rate for. Without a rate file, fares are priced and shown in SGD only.
+    let rates = match args.get(4) {
+        Some(rate_file) => match RateTable::load(rate_file) {
+            Ok(rates) => rates,
+            Err(error_message) => {
+                println!("Error: {error_message}");
+                return Ok(());
+            }
+        },
+        None => RateTable::new(Currency::parse("SGD").unwrap()),
+    };
+
+    // With a key file, every datagram must be signed with the pre-shared key of one of its clients.
+    let num_client_keys = match args.get(5) {
+        Some(key_file) => match networking::load_key_file(key_file) {
+            Ok(keys) => {
+                let num_client_keys = keys.len();
+                networking::enable_authentication(networking::Authenticator::for_server(keys));
+                Some(num_client_keys)
+            }
+            Err(error_message) => {
+                println!("Error: {error_message}");
+                return Ok(());
+            }
+        },
+        None => None,
+    };
+
     // Parse the invocation semantics.
     let invocation_semantics = match invocation_semantics.as_str() {
         "alo" => InvocationSemantics::AtLeastOnce,
@@ -102,58 +170,125 @@ fn main() -> std::io::Result<()> {
         }
     };
 
-    let mut flight_db = HashMap::new();
-    flight_db.insert(
-        1,
-        Flight {
-            id: 1,
-            source: "A".to_string(),
-            destination: "B".to_string(),
-            departure_time: 1700000000,
-            seats: 10,
-            airfare: 10.1,
-            baggage_capacity_kg: 1000,
-        },
-    );
-
-    flight_db.insert(
-        2,
-        Flight {
-            id: 2,
-            source: "A".to_string(),
-            destination: "B".to_string(),
-            departure_time: 1700000000,
-            seats: 20,
-            airfare: 20.2,
-            baggage_capacity_kg: 1000,
-        },
-    );
-
-    flight_db.insert(
-        3,
-        Flight {
-            id: 3,
-            source: "C".to_string(),
-            destination: "D".to_string(),
-            departure_time: 1700000000,
-            seats: 30,
-            airfare: 30.3,
-            baggage_capacity_kg: 1000,
-        },
-    );
-
-    // Build a hashmap of flight ID to a vector of WatchlistEntry
-    let mut watchlist_db: HashMap<u32, Vec<WatchlistEntry>> = HashMap::new();
+    // Seed flights depart relative to server start so that departure-time services have something to return.
+    let now = current_unix_time();
+    let currency = rates.base();
+    let mut flight_db = FlightStore::new();
+    flight_db.insert(Flight {
+        id: 1,
+        source: "A".to_string(),
+        destination: "B".to_string(),
+        departure_time: now + 86400,
+        cabins: [
+            Cabin::new(7, Money::new(1010, currency)),
+            Cabin::new(4, Money::new(2020, currency)),
+            Cabin::new(4, Money::new(4040, currency)),
+        ],
+        baggage_capacity_kg: 1000,
+        baggage_reserved_kg: 0,
+        status: FlightStatus::Scheduled,
+        gate: "A1".to_string(),
+        seat_map: None,
+        overbooking_percent: 0,
+    });
+    // Flight 1 has a seat map: one business row, one premium row and two economy rows of four seats, with
+    // an exit row and a seat that cannot be sold. Its seat counts follow the map.
+    let seat_map = SeatMap::new(
+        "AB CD",
+        vec![
+            SeatClass::Business,
+            SeatClass::Premium,
+            SeatClass::Economy,
+            SeatClass::Economy,
+        ],
+        &[3],
+        &["4D".to_string()],
+    )
+    .unwrap();
+    flight_db.get_mut(&1).unwrap().set_seat_map(seat_map);
+
+    flight_db.insert(Flight {
+        id: 2,
+        source: "A".to_string(),
+        destination: "B".to_string(),
+        departure_time: now + 86400,
+        cabins: [
+            Cabin::new(12, Money::new(2020, currency)),
+            Cabin::new(4, Money::new(4040, currency)),
+            Cabin::new(4, Money::new(8080, currency)),
+        ],
+        baggage_capacity_kg: 1000,
+        baggage_reserved_kg: 0,
+        status: FlightStatus::Scheduled,
+        gate: "A2".to_string(),
+        seat_map: None,
+        overbooking_percent: 0,
+    });
+
+    flight_db.insert(Flight {
+        id: 3,
+        source: "C".to_string(),
+        destination: "D".to_string(),
+        departure_time: now + 2 * 86400,
+        cabins: [
+            Cabin::new(18, Money::new(3030, currency)),
+            Cabin::new(6, Money::new(6060, currency)),
+            Cabin::new(6, Money::new(12120, currency)),
+        ],
+        baggage_capacity_kg: 1000,
+        baggage_reserved_kg: 0,
+        status: FlightStatus::Scheduled,
+        gate: "C5".to_string(),
+        seat_map: None,
+        overbooking_percent: 0,
+    });
+
+    // Build a store of the clients monitoring flights
+    let mut subscription_db = SubscriptionStore::new();
+
+    // Build a store of the bookings made through reserve seats
+    let mut booking_db = BookingStore::new();
+
+    // Build a store of the clients waiting for seats on sold out flights
+    let mut waitlist = Waitlist::new();
+
+    // Build a store of the fares quoted in flight summaries, and the engine that sets the fares
+    let mut quote_db = QuoteStore::new();
+    let pricing = PricingEngine::default();
+
+    // Build the limits and fees for checked baggage
+    let baggage_policy = BaggagePolicy::standard(rates.base());
+
+    // Build the counters for seats and sequence numbers handed out at check-in
+    let mut check_in_counters = CheckInCounters::new();
+
+    // Build the registered users, and the key their session tokens are signed with
+    let mut accounts = AccountStore::new();
+    let session_signer = SessionSigner::new();
 
     // Build a hashmap of request ID to a ResponseCache
     let mut response_cache: HashMap<ResponseCacheKey, ResponseCacheValue> = HashMap::new();
 
     let socket = UdpSocket::bind("127.0.0.1:7878")?;
+    // Wake up regularly to resend updates that have not been acknowledged and to expire subscriptions, even when
+    // no requests arrive.
+    socket
+        .set_read_timeout(Some(RETRANSMIT_CHECK_INTERVAL))
+        .expect("Failed to set read timeout");
     println!(
         "-- Server is listening on port {}",
         socket.local_addr().unwrap().port()
     );
     println!("\n\nInvocation semantics = {:?}", invocation_semantics);
+    if admin_key.is_none() {
+        println!("Admins cannot be registered. Pass an admin key to allow it.");
+    }
+    match num_client_keys {
+        Some(num_client_keys) => {
+            println!("Datagrams are authenticated with the keys of {num_client_keys} client(s).")
+        }
+        None => println!("Datagrams are not authenticated. Pass a key file to require it."),
+    }
 
     let mut buf = [0; 2048];
 
@@ -162,15 +297,41 @@ fn main() -> std::io::Result<()> {
 
     let mut should_fail_next = true;
 
+    let mut next_expiry_sweep = Instant::now();
     loop {
-        // Receives a single datagram message on the socket.
+        subscription_db.retransmit_due(&socket);
+        if Instant::now() >= next_expiry_sweep {
+            subscription_db.expire_due(&socket, current_unix_time());
+            next_expiry_sweep = Instant::now() + EXPIRY_SWEEP_INTERVAL;
+        }
+
+        // Receives a single datagram message on the socket, dropping any that fail authentication.
         // If `buf` is too small to hold
         // the message, it will be cut off.
-        let (_, client_addr) = socket.recv_from(&mut buf)?;
+        let (_, client_addr) = match networking::receive(&socket, &mut buf) {
+            Ok(received) => received,
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+                ) =>
+            {
+                continue
+            }
+            Err(error) => return Err(error),
+        };
 
         // Read the request ID in the first 4 bytes.
         let i: usize = 0;
         let (request_id, i) = unmarshal_u32(&buf, i);
+
+        // Clients acknowledge seat updates with the callback request ID, and expect no response.
+        if request_id == networking::CALLBACK_REQUEST_ID {
+            let (subscription_id, i) = unmarshal_u32(&buf, i);
+            let (sequence, _) = unmarshal_u32(&buf, i);
+            subscription_db.acknowledge(&client_addr, subscription_id, sequence);
+            continue;
+        }
         println!(
             "[server] Received Request ID: {} from Client: {}",
             request_id, client_addr
@@ -215,30 +376,129 @@ fn main() -> std::io::Result<()> {
             }
         }
 
+        // Fares move with time, so bring them up to date before handling the request.
+        flight_db.reprice_all(&pricing, current_unix_time());
+
         // Read the service ID in the next byte.
         let (service_id, i) = unmarshal_u8(&buf, i);
         print!("[server] Handling Service {}...", service_id);
 
-        // Call the handler for the service. It should return a u8 vector payload.
-        let payload: Vec<u8> = match service_id {
-            1 => get_flight_ids_handler(&buf[i..], &flight_db),
-            2 => get_flight_summary_handler(&buf[i..], &flight_db),
-            3 => reserve_seats_handler(&buf[i..], &mut flight_db, &mut watchlist_db, &socket),
-            4 => monitor_seat_availability_handler(
-                &buf[i..],
-                &mut flight_db,
-                &mut watchlist_db,
-                &client_addr,
-            ),
-            5 => get_earliest_flight_ids(&buf[i..], &flight_db),
-            6 => reserve_baggage_handler(&buf[i..], &mut flight_db),
-            _ => {
-                println!("Error: Handler byte is not 1-6.");
-                vec![]
-            }
+        // Call the handler for the service. It should return a u8 vector payload. Callers the permission
+        // table does not allow are refused before the handler is called.
+        let payload: Vec<u8> = match authorize(service_id, &buf, i, &session_signer, &accounts) {
+            Err(refusal) => refusal,
+            Ok((user_id, i)) => match service_id {
+                1 => get_flight_ids_handler(&buf[i..], &flight_db),
+                2 => get_flight_summary_handler(
+                    &buf[i..],
+                    user_id,
+                    &flight_db,
+                    &mut quote_db,
+                    &rates,
+                ),
+                3 => reserve_seats_handler(
+                    &buf[i..],
+                    user_id,
+                    &mut flight_db,
+                    &mut booking_db,
+                    &mut quote_db,
+                    &pricing,
+                    &client_addr,
+                ),
+                4 => monitor_seat_availability_handler(
+                    &buf[i..],
+                    user_id,
+                    rates.base(),
+                    &flight_db,
+                    &mut subscription_db,
+                    &client_addr,
+                ),
+                5 => get_earliest_flight_ids(&buf[i..], &flight_db),
+                6 => reserve_baggage_handler(
+                    &buf[i..],
+                    user_id,
+                    &mut flight_db,
+                    &mut booking_db,
+                    &baggage_policy,
+                ),
+                7 => search_flights_handler(&buf[i..], &flight_db, &rates),
+                8 => get_next_departures_handler(&buf[i..], &flight_db, &rates),
+                9 => admin::admin_handler(
+                    &buf[i..],
+                    rates.base(),
+                    &pricing,
+                    &mut flight_db,
+                    &mut booking_db,
+                    &mut waitlist,
+                    &mut subscription_db,
+                    &socket,
+                ),
+                12 => get_seat_map_handler(&buf[i..], &flight_db),
+                13 => reserve_selected_seats_handler(
+                    &buf[i..],
+                    user_id,
+                    &mut flight_db,
+                    &mut booking_db,
+                    &mut quote_db,
+                    &pricing,
+                    &client_addr,
+                ),
+                14 => join_waitlist_handler(
+                    &buf[i..],
+                    user_id,
+                    &flight_db,
+                    &mut waitlist,
+                    &client_addr,
+                ),
+                15 => cancel_booking_handler(
+                    &buf[i..],
+                    user_id,
+                    &mut flight_db,
+                    &mut booking_db,
+                    &mut waitlist,
+                    &pricing,
+                    &socket,
+                ),
+                16 => set_passenger_handler(&buf[i..], user_id, &flight_db, &mut booking_db),
+                17 => check_in_handler(
+                    &buf[i..],
+                    user_id,
+                    &flight_db,
+                    &mut booking_db,
+                    &mut check_in_counters,
+                    &baggage_policy,
+                ),
+                18 => register_handler(&buf[i..], admin_key, &mut accounts),
+                19 => login_handler(&buf[i..], &accounts, &session_signer),
+                20 => list_bookings_handler(&buf[i..], user_id, &flight_db, &booking_db),
+                21 => set_role_handler(&buf[i..], &mut accounts),
+                23 => unsubscribe_handler(&buf[i..], user_id, &mut subscription_db),
+                24 => list_subscriptions_handler(user_id, &mut subscription_db),
+                25 => resync_subscription_handler(
+                    &buf[i..],
+                    user_id,
+                    &flight_db,
+                    &mut subscription_db,
+                ),
+                26 => monitor_route_handler(
+                    &buf[i..],
+                    user_id,
+                    &flight_db,
+                    &mut subscription_db,
+                    &client_addr,
+                ),
+                _ => {
+                    println!("Error: Handler byte is not 1-9, 12-21 or 23-26.");
+                    vec![]
+                }
+            },
         };
 
         println!("Done!");
+
+        // Tell monitoring clients about whatever the request changed.
+        subscription_db.publish_changes(&flight_db, &socket, current_unix_time());
+
         // Add to the response cache.
         response_cache.insert(
             ResponseCacheKey {
@@ -263,6 +523,67 @@ fn main() -> std::io::Result<()> {
     }
 }
 
+// Checks the caller against the permission table before the request reaches its handler. A request to a
+// service that needs a role starts with the session token issued at login, then the username of the
+// traveller an agent is acting for, if any. Returns the user the request acts for and where the rest of the
+// request starts, or the response refusing it. Public services have no user, which is 0 since user IDs
+// start at 1.
+fn authorize(
+    service_id: u8,
+    buf: &[u8],
+    i: usize,
+    session_signer: &SessionSigner,
+    accounts: &AccountStore,
+) -> Result<(u32, usize), Vec<u8>> {
+    let (roles, i) = match access::access(service_id) {
+        Access::Public => return Ok((0, i)),
+        Access::Optional(roles) => match unmarshal_u8(buf, i) {
+            (0, i) => return Ok((0, i)),
+            (_, i) => (roles, i),
+        },
+        Access::Roles(roles) => (roles, i),
+    };
+    let (session_token, i) = unmarshal_string(buf, i);
+    let (on_behalf_of, i) = unmarshal_optional_string(buf, i);
+
+    if session_token.is_empty() {
+        return Err(error_handler("Log in to use this service."));
+    }
+    let user_id = session_signer
+        .verify(&session_token, current_unix_time())
+        .map_err(|error_message| error_handler(&error_message))?;
+    let account = match accounts.get(&user_id) {
+        Some(account) => account,
+        None => return Err(error_handler("Invalid session. Log in again.")),
+    };
+
+    // Roles are looked up on every request, so a change of role applies to sessions already issued.
+    if !roles.contains(&account.role) {
+        return Err(forbidden_handler(&format!(
+            "Users with the {} role cannot use this service.",
+            account.role
+        )));
+    }
+
+    match on_behalf_of {
+        None => Ok((user_id, i)),
+        Some(_) if !account.role.can_act_for_others() => Err(forbidden_handler(
+            "Only agents can act on behalf of travellers.",
+        )),
+        // Only travellers can be acted for, so that acting for someone never gives more access.
+        Some(username) => match accounts.find(&username) {
+            Some(traveller) if traveller.role == Role::Traveller => {
+                println!(
+                    "User {user_id} is acting on behalf of user {}.",
+                    traveller.id
+                );
+                Ok((traveller.id, i))
+            }
+            _ => Err(error_handler("No traveller found with the given username.")),
+        },
+    }
+}
+
 fn error_handler(error_message: &str) -> Vec<u8> {
     println!("Preparing error response: {error_message}");
 
@@ -277,7 +598,23 @@ fn error_handler(error_message: &str) -> Vec<u8> {
 
     buffer_to_send
 }
-fn get_flight_ids_handler(buf: &[u8], flight_db: &HashMap<u32, Flight>) -> Vec<u8> {
+
+fn forbidden_handler(error_message: &str) -> Vec<u8> {
+    println!("Preparing forbidden response: {error_message}");
+
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add the status byte, which tells the client its role may not use the service.
+    buffer_to_send.push(FORBIDDEN);
+
+    // Add the error message to the buffer.
+    marshal_string(error_message, &mut buffer_to_send);
+
+    buffer_to_send
+}
+
+fn get_flight_ids_handler(buf: &[u8], flight_db: &FlightStore) -> Vec<u8> {
     // Read the source and destination from the buffer.
     let (source, i) = unmarshal_string(buf, 0);
     let (destination, _) = unmarshal_string(buf, i);
@@ -308,9 +645,21 @@ fn get_flight_ids_handler(buf: &[u8], flight_db: &HashMap<u32, Flight>) -> Vec<u
     buffer_to_send
 }
 
-fn get_flight_summary_handler(buf: &[u8], flight_db: &HashMap<u32, Flight>) -> Vec<u8> {
-    // Read the flight ID from the buffer.
-    let (flight_id, _) = unmarshal_u32(buf, 0);
+fn get_flight_summary_handler(
+    buf: &[u8],
+    user_id: u32,
+    flight_db: &FlightStore,
+    quote_db: &mut QuoteStore,
+    rates: &RateTable,
+) -> Vec<u8> {
+    // Read the flight ID and the optional currency to show the fares in from the buffer.
+    let (flight_id, i) = unmarshal_u32(buf, 0);
+    let (display_currency, _) = unmarshal_optional_string(buf, i);
+
+    let display = match rates.display(display_currency.as_deref()) {
+        Ok(display) => display,
+        Err(error_message) => return error_handler(&error_message),
+    };
 
     // Get the flight from the hashmap.
     let result = flight_db.get(&flight_id);
@@ -329,38 +678,52 @@ fn get_flight_summary_handler(buf: &[u8], flight_db: &HashMap<u32, Flight>) -> V
     // Add the handler byte.
     buffer_to_send.push(2);
 
-    // Add the departure time, airfare, seats and remaining baggage capacity to the buffer.
+    // Add the departure time, the seats and airfare of each class and remaining baggage capacity to the buffer.
     marshal_u32(flight.departure_time, &mut buffer_to_send);
-    marshal_f32(flight.airfare, &mut buffer_to_send);
-    marshal_u32(flight.seats, &mut buffer_to_send);
-    marshal_u32(flight.baggage_capacity_kg, &mut buffer_to_send);
+    flight.marshal_cabins(&display, &mut buffer_to_send);
+    marshal_u32(flight.remaining_baggage_kg(), &mut buffer_to_send);
+
+    // Add the flight status.
+    buffer_to_send.push(flight.status.to_u8());
+
+    // Add a quote for the fares above, which a reservation by the same user can give to book at those fares
+    // once, or 0 if the user did not log in. Seats are always charged in the currency the flight is priced in.
+    if user_id == 0 {
+        marshal_u8(0, &mut buffer_to_send);
+    } else {
+        let (quote_id, quote) = quote_db.issue(flight, user_id, current_unix_time());
+        marshal_u8(1, &mut buffer_to_send);
+        marshal_u32(quote_id, &mut buffer_to_send);
+        marshal_u32(quote.expires_at, &mut buffer_to_send);
+    }
 
     buffer_to_send
 }
 
-fn get_earliest_flight_ids(buf: &[u8], flight_db: &HashMap<u32, Flight>) -> Vec<u8> {
+fn get_earliest_flight_ids(buf: &[u8], flight_db: &FlightStore) -> Vec<u8> {
     // Read the source from the buffer
     let (source, _) = unmarshal_string(buf, 0);
 
-    // Get flights from flights_db with source and seats > 0
-    // Using the minimum departure time from these flights, only select flights with that departure time
-    // Return the flight IDs of these flights
-    let valid_flights = flight_db
-        .values()
-        .filter(|flight| flight.source == source && flight.seats > 0)
-        .collect::<Vec<&Flight>>();
-    
-    let earliest_time = valid_flights.iter().map(|flight| flight.departure_time).min().unwrap();
-
-    let earliest_flight_ids = valid_flights
-        .iter()
-        .filter(|flight| flight.departure_time == earliest_time)
-        .map(|flight| flight.id)
-        .collect::<Vec<u32>>();
-
-    // Sort the flight IDs in asc order.
-    let mut earliest_flight_ids = earliest_flight_ids;
-    earliest_flight_ids.sort();
+    // Get upcoming bookable flights from flight_db with source and seats > 0, earliest first.
+    // Using the departure time of the first of these flights, only select flights with that departure time
+    // Return the flight IDs of these flights, or an empty array if there are none.
+    let query = FlightQuery {
+        source: Some(source),
+        departure_after: Some(current_unix_time()),
+        min_seats: Some(1),
+        bookable_only: true,
+        ..FlightQuery::default()
+    };
+    let valid_flights = flight_db.query(&query).flights;
+
+    let earliest_flight_ids = match valid_flights.first() {
+        Some(earliest_flight) => valid_flights
+            .iter()
+            .filter(|flight| flight.departure_time == earliest_flight.departure_time)
+            .map(|flight| flight.id)
+            .collect::<Vec<u32>>(),
+        None => vec![],
+    };
 
     // Create a buffer to store the data to send with capacity 2048 bytes
     let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
@@ -374,15 +737,183 @@ fn get_earliest_flight_ids(buf: &[u8], flight_db: &HashMap<u32, Flight>) -> Vec<
     buffer_to_send
 }
 
+fn get_next_departures_handler(buf: &[u8], flight_db: &FlightStore, rates: &RateTable) -> Vec<u8> {
+    // Read the source, optional destination, optional start time, number of flights and optional currency
+    // to show the fares in from the buffer.
+    let (source, i) = unmarshal_string(buf, 0);
+    let (destination, i) = unmarshal_optional_string(buf, i);
+    let (departure_after, i) = unmarshal_optional_u32(buf, i);
+    let (num_flights, i) = unmarshal_u32(buf, i);
+    let (display_currency, _) = unmarshal_optional_string(buf, i);
+
+    let display = match rates.display(display_currency.as_deref()) {
+        Ok(display) => display,
+        Err(error_message) => return error_handler(&error_message),
+    };
+
+    if num_flights == 0 {
+        return error_handler("Number of flights must be at least 1.");
+    }
+
+    // Default to departures from now onwards.
+    let query = FlightQuery {
+        source: Some(source),
+        destination,
+        departure_after: Some(departure_after.unwrap_or_else(current_unix_time)),
+        min_seats: Some(1),
+        bookable_only: true,
+        limit: num_flights,
+        ..FlightQuery::default()
+    };
+    let page = flight_db.query(&query);
+
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add the handler byte.
+    buffer_to_send.push(8);
+
+    // Add the flights, prefixed by their count. An empty list means there are no upcoming departures.
+    buffer_to_send.push(page.flights.len() as u8);
+    for flight in page.flights {
+        flight.marshal_listing(&display, &mut buffer_to_send);
+    }
+
+    buffer_to_send
+}
+
 fn reserve_seats_handler(
     buf: &[u8],
-    flight_db: &mut HashMap<u32, Flight>,
-    watchlist_db: &mut HashMap<u32, Vec<WatchlistEntry>>,
-    socket: &UdpSocket,
+    user_id: u32,
+    flight_db: &mut FlightStore,
+    booking_db: &mut BookingStore,
+    quote_db: &mut QuoteStore,
+    pricing: &PricingEngine,
+    client_addr: &SocketAddr,
 ) -> Vec<u8> {
-    // Read id and num_seats from buf.
+    // Read id, num_seats, seat class and the optional quote ID from buf.
     let (flight_id, i) = unmarshal_u32(buf, 0);
-    let (num_seats, _) = unmarshal_u32(buf, i);
+    let (num_seats, i) = unmarshal_u32(buf, i);
+    let (seat_class, i) = unmarshal_u8(buf, i);
+    let (quote_id, _) = unmarshal_optional_u32(buf, i);
+
+    if num_seats == 0 {
+        return error_handler("At least one seat must be reserved.");
+    }
+
+    // Flights with a seat map assign the seats automatically.
+    let request = SeatRequest {
+        flight_id,
+        seat_class,
+        selection: SeatSelection::Auto(num_seats),
+        quote_id,
+    };
+    reserve_seats(
+        3,
+        request,
+        user_id,
+        flight_db,
+        booking_db,
+        quote_db,
+        pricing,
+        client_addr,
+    )
+}
+
+fn reserve_selected_seats_handler(
+    buf: &[u8],
+    user_id: u32,
+    flight_db: &mut FlightStore,
+    booking_db: &mut BookingStore,
+    quote_db: &mut QuoteStore,
+    pricing: &PricingEngine,
+    client_addr: &SocketAddr,
+) -> Vec<u8> {
+    // Read id, seat class and how the seats are selected from buf.
+    let (flight_id, i) = unmarshal_u32(buf, 0);
+    let (seat_class, i) = unmarshal_u8(buf, i);
+    let (selection_mode, i) = unmarshal_u8(buf, i);
+
+    let (selection, i) = match selection_mode {
+        // Specific seats, as a count followed by the seat names.
+        1 => {
+            let (num_seats, mut i) = unmarshal_u8(buf, i);
+            let mut seats = Vec::with_capacity(num_seats as usize);
+            for _ in 0..num_seats {
+                let (seat, j) = unmarshal_string(buf, i);
+                seats.push(seat);
+                i = j;
+            }
+            (SeatSelection::Chosen(seats), i)
+        }
+        // A group of seats picked by the server.
+        2 => {
+            let (num_seats, i) = unmarshal_u32(buf, i);
+            (SeatSelection::Auto(num_seats), i)
+        }
+        _ => {
+            return error_handler(
+                "Invalid seat selection. Use 1 (choose seats) or 2 (auto-assign).",
+            )
+        }
+    };
+
+    let (quote_id, _) = unmarshal_optional_u32(buf, i);
+
+    if selection.num_seats() == 0 {
+        return error_handler("At least one seat must be reserved.");
+    }
+
+    let request = SeatRequest {
+        flight_id,
+        seat_class,
+        selection,
+        quote_id,
+    };
+    reserve_seats(
+        13,
+        request,
+        user_id,
+        flight_db,
+        booking_db,
+        quote_db,
+        pricing,
+        client_addr,
+    )
+}
+
+// A request to reserve seats, as read by either reservation service.
+struct SeatRequest {
+    flight_id: u32,
+    seat_class: u8,
+    selection: SeatSelection,
+    // The quote from the flight summary whose fare the client agreed to, if any.
+    quote_id: Option<u32>,
+}
+
+// Reserves the selected seats for the user and responds with the booking reference, the fare paid per
+// seat and the assigned seats. Either every seat is reserved or none are. Seats are booked at the quoted
+// fare if a valid quote is given, or at the current fare otherwise.
+#[allow(clippy::too_many_arguments)]
+fn reserve_seats(
+    handler_byte: u8,
+    request: SeatRequest,
+    user_id: u32,
+    flight_db: &mut FlightStore,
+    booking_db: &mut BookingStore,
+    quote_db: &mut QuoteStore,
+    pricing: &PricingEngine,
+    client_addr: &SocketAddr,
+) -> Vec<u8> {
+    let flight_id = request.flight_id;
+    let seat_class = match SeatClass::from_u8(request.seat_class) {
+        Some(seat_class) => seat_class,
+        None => {
+            return error_handler(
+                "Invalid seat class. Use 0 (economy), 1 (premium) or 2 (business).",
+            )
+        }
+    };
 
     // Try to reserve the seats.
     if !flight_db.contains_key(&flight_id) {
@@ -391,91 +922,374 @@ fn reserve_seats_handler(
 
     let flight = flight_db.get_mut(&flight_id).unwrap();
 
-    let reservation_success = flight.reserve_seats(num_seats);
+    let now = current_unix_time();
+    let airfare = match request.quote_id {
+        Some(quote_id) => match quote_db.get_valid(quote_id, flight_id, user_id, now) {
+            Ok(quote) => quote.airfare(seat_class),
+            Err(error_message) => return error_handler(&error_message),
+        },
+        None => flight.airfare(Some(seat_class)),
+    };
 
-    if !reservation_success {
-        println!("Reservation failed.");
-        let current_seats = flight.seats;
-        return error_handler(&format!("Not enough seats available. You tried to reserve {num_seats} seats, but there are only {current_seats} seats available."));
+    // Keep the booking so the client can be told if the flight changes.
+    let (booking_id, seats) = match booking_db.book(
+        flight,
+        user_id,
+        *client_addr,
+        seat_class,
+        &request.selection,
+        airfare,
+    ) {
+        Ok(booking) => booking,
+        Err(error_message) => {
+            println!("Reservation failed.");
+            return error_handler(&error_message);
+        }
+    };
+
+    // A quote is only good for one booking.
+    if let Some(quote_id) = request.quote_id {
+        quote_db.remove(quote_id);
     }
 
-    if watchlist_db.contains_key(&flight_id) {
-        // First, go through each entry and obtain a cleaned vector of entries.
-        // A cleaned vector of entries is a vector of entries that have not expired.
+    // The booking changes the load factor, and so the fares monitoring clients are about to see.
+    flight.reprice(pricing, now);
 
-        let mut cleaned_watchlist = Vec::<WatchlistEntry>::new();
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
 
-        for entry in watchlist_db.get(&flight_id).unwrap() {
-            if u64::from(entry.0)
-                > SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs()
-            {
-                cleaned_watchlist.push(entry.clone());
-            }
-        }
+    // Add the handler byte.
+    buffer_to_send.push(handler_byte);
 
-        // Then, go through each entry of the cleaned vector and call inform_client.
-        for entry in cleaned_watchlist.iter() {
-            inform_client(socket, entry.1, flight_id, flight.seats);
-        }
+    // Add 1 if successful.
+    buffer_to_send.push(1);
+
+    // Add the booking reference and the fare paid per seat.
+    marshal_u32(booking_id, &mut buffer_to_send);
+    marshal_money(airfare, &mut buffer_to_send);
+
+    // Add the assigned seats, none if the flight has no seat map.
+    marshal_u8(seats.len() as u8, &mut buffer_to_send);
+    for seat in seats.iter() {
+        marshal_string(seat, &mut buffer_to_send);
+    }
+
+    buffer_to_send
+}
 
-        // Update the watchlist
-        watchlist_db.insert(flight_id, cleaned_watchlist);
+fn get_seat_map_handler(buf: &[u8], flight_db: &FlightStore) -> Vec<u8> {
+    // Read id from buf.
+    let (flight_id, _) = unmarshal_u32(buf, 0);
+
+    let flight = match flight_db.get(&flight_id) {
+        Some(flight) => flight,
+        None => return error_handler("No flight found for the given flight ID."),
+    };
+
+    let seat_map = match flight.seat_map.as_ref() {
+        Some(seat_map) => seat_map,
+        None => return error_handler("This flight does not have a seat map."),
     };
 
     // Create a buffer to store the data to send with capacity 2048 bytes
     let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
 
     // Add the handler byte.
-    buffer_to_send.push(3);
+    buffer_to_send.push(12);
 
-    // Add 1 if successful.
-    buffer_to_send.push(1);
+    seat_map.marshal(&mut buffer_to_send);
 
     buffer_to_send
 }
 
-fn monitor_seat_availability_handler(
+fn join_waitlist_handler(
     buf: &[u8],
-    flight_db: &mut HashMap<u32, Flight>,
-    watchlist_db: &mut HashMap<u32, Vec<WatchlistEntry>>,
+    user_id: u32,
+    flight_db: &FlightStore,
+    waitlist: &mut Waitlist,
     client_addr: &SocketAddr,
 ) -> Vec<u8> {
-    // Read id and monitor interval from buf.
+    // Read id, seat class and num_seats from buf.
     let (flight_id, i) = unmarshal_u32(buf, 0);
-    let (monitor_interval, _) = unmarshal_u32(buf, i);
+    let (seat_class, i) = unmarshal_u8(buf, i);
+    let (num_seats, _) = unmarshal_u32(buf, i);
 
-    // Check if the flight exists.
-    if !flight_db.contains_key(&flight_id) {
-        return error_handler("No flight found for the given flight ID.");
+    let seat_class = match SeatClass::from_u8(seat_class) {
+        Some(seat_class) => seat_class,
+        None => {
+            return error_handler(
+                "Invalid seat class. Use 0 (economy), 1 (premium) or 2 (business).",
+            )
+        }
+    };
+
+    let flight = match flight_db.get(&flight_id) {
+        Some(flight) => flight,
+        None => return error_handler("No flight found for the given flight ID."),
+    };
+
+    if !flight.is_bookable() {
+        let status = flight.status;
+        return error_handler(&format!(
+            "Seats can no longer be reserved on this flight because it is {status}."
+        ));
+    }
+    if num_seats == 0 {
+        return error_handler("At least one seat must be requested.");
+    }
+    if flight.available_seats(Some(seat_class)) >= num_seats {
+        return error_handler("Enough seats are available now. Reserve them instead.");
     }
 
-    // Add the entry to the watchlist. Note: Converting u64 to u32 here is possibly unsafe, but we are relying on client to limit duration to 1 year (31536000).
-    let entry = WatchlistEntry(
-        (SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs()
-            + u64::from(monitor_interval))
-        .try_into()
-        .unwrap(),
-        client_addr.clone(),
+    let (waitlist_id, position) =
+        waitlist.join(flight_id, user_id, *client_addr, seat_class, num_seats);
+    println!("User {user_id} joined the waitlist of flight {flight_id} at position {position}.");
+
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add the handler byte.
+    buffer_to_send.push(14);
+
+    // Add the waitlist reference and the position in the queue for the seat class.
+    marshal_u32(waitlist_id, &mut buffer_to_send);
+    marshal_u32(position, &mut buffer_to_send);
+
+    buffer_to_send
+}
+
+fn cancel_booking_handler(
+    buf: &[u8],
+    user_id: u32,
+    flight_db: &mut FlightStore,
+    booking_db: &mut BookingStore,
+    waitlist: &mut Waitlist,
+    pricing: &PricingEngine,
+    socket: &UdpSocket,
+) -> Vec<u8> {
+    // Read the booking reference from buf.
+    let (booking_id, _) = unmarshal_u32(buf, 0);
+
+    // Only the user who made the booking can cancel it.
+    let flight_id = match booking_db.get_owned(&booking_id, user_id) {
+        Some(booking) => booking.flight_id,
+        None => return error_handler("No booking found for the given booking reference."),
+    };
+
+    let flight = flight_db.get_mut(&flight_id).unwrap();
+    if !flight.is_bookable() {
+        let status = flight.status;
+        return error_handler(&format!(
+            "The booking can no longer be cancelled because the flight is {status}."
+        ));
+    }
+
+    booking_db.cancel(booking_id, flight);
+    println!("Cancelled booking {booking_id} on flight {flight_id}.");
+
+    // The freed seats go to waiting clients first, at the fare after the cancellation.
+    flight.reprice(pricing, current_unix_time());
+    waitlist.promote(flight, booking_db, socket);
+    flight.reprice(pricing, current_unix_time());
+
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add the handler byte.
+    buffer_to_send.push(15);
+
+    // Add the cancelled booking reference.
+    marshal_u32(booking_id, &mut buffer_to_send);
+
+    buffer_to_send
+}
+
+// Records the details of one passenger of a booking, replacing any given before. Only the user who made the
+// booking can give them, until boarding starts.
+fn set_passenger_handler(
+    buf: &[u8],
+    user_id: u32,
+    flight_db: &FlightStore,
+    booking_db: &mut BookingStore,
+) -> Vec<u8> {
+    // Read the booking reference, the passenger and their details from buf.
+    let (booking_id, i) = unmarshal_u32(buf, 0);
+    let (passenger_number, i) = unmarshal_u8(buf, i);
+    let (name, i) = unmarshal_string(buf, i);
+    let (document_number, i) = unmarshal_string(buf, i);
+    let (date_of_birth, i) = unmarshal_string(buf, i);
+    let (contact, _) = unmarshal_string(buf, i);
+
+    let booking = match booking_db.get_owned_mut(&booking_id, user_id) {
+        Some(booking) => booking,
+        None => return error_handler("No booking found for the given booking reference."),
+    };
+
+    if flight_db
+        .get(&booking.flight_id)
+        .is_none_or(|flight| !flight.is_bookable())
+    {
+        return error_handler("Passenger details can no longer be changed because the flight is no longer taking bookings.");
+    }
+
+    let passenger = match booking.passenger_mut(passenger_number) {
+        Ok(passenger) => passenger,
+        Err(error_message) => return error_handler(&error_message),
+    };
+
+    match Passenger::new(
+        &name,
+        &document_number,
+        &date_of_birth,
+        &contact,
+        current_unix_time(),
+    ) {
+        Ok(details) => passenger.details = Some(details),
+        Err(error_message) => return error_handler(&error_message),
+    }
+
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add the handler byte.
+    buffer_to_send.push(16);
+
+    // Add the booking reference and the passenger.
+    marshal_u32(booking_id, &mut buffer_to_send);
+    marshal_u8(passenger_number, &mut buffer_to_send);
+
+    buffer_to_send
+}
+
+// Checks in one passenger of a booking and responds with their boarding pass, the flight's gate and the
+// passenger's baggage with any excess fee. Only the user who made the booking can check in for it.
+fn check_in_handler(
+    buf: &[u8],
+    user_id: u32,
+    flight_db: &FlightStore,
+    booking_db: &mut BookingStore,
+    check_in_counters: &mut CheckInCounters,
+    baggage_policy: &BaggagePolicy,
+) -> Vec<u8> {
+    // Read the booking reference and the passenger from buf.
+    let (booking_id, i) = unmarshal_u32(buf, 0);
+    let (passenger_number, _) = unmarshal_u8(buf, i);
+
+    let booking = match booking_db.get_owned_mut(&booking_id, user_id) {
+        Some(booking) => booking,
+        None => return error_handler("No booking found for the given booking reference."),
+    };
+    let flight = match flight_db.get(&booking.flight_id) {
+        Some(flight) => flight,
+        None => return error_handler("No flight found for the given flight ID."),
+    };
+
+    let seat_class = booking.seat_class;
+    let boarding_pass = match check_in_counters.check_in(
+        flight,
+        booking,
+        passenger_number,
+        baggage_policy,
+        current_unix_time(),
+    ) {
+        Ok(boarding_pass) => boarding_pass.clone(),
+        Err(error_message) => return error_handler(&error_message),
+    };
+    // Checking in succeeded, so the passenger and their details exist.
+    let passenger = booking.passenger_mut(passenger_number).unwrap();
+    let name = passenger.details.as_ref().unwrap().name.clone();
+    println!(
+        "Checked in passenger {passenger_number} of booking {booking_id} to seat {}.",
+        boarding_pass.seat
+    );
+
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add the handler byte.
+    buffer_to_send.push(17);
+
+    // Add who is travelling on which flight.
+    marshal_u32(booking_id, &mut buffer_to_send);
+    marshal_u8(passenger_number, &mut buffer_to_send);
+    marshal_string(&name, &mut buffer_to_send);
+    marshal_u32(flight.id, &mut buffer_to_send);
+    marshal_string(&flight.source, &mut buffer_to_send);
+    marshal_string(&flight.destination, &mut buffer_to_send);
+    marshal_u32(flight.departure_time, &mut buffer_to_send);
+
+    // Add where and when to board.
+    marshal_u8(seat_class.to_u8(), &mut buffer_to_send);
+    marshal_string(&boarding_pass.seat, &mut buffer_to_send);
+    marshal_string(&flight.gate, &mut buffer_to_send);
+    marshal_u8(boarding_pass.boarding_group, &mut buffer_to_send);
+    marshal_u32(boarding_pass.sequence_number, &mut buffer_to_send);
+
+    // Add the checked baggage and the fee for any excess weight.
+    marshal_u8(passenger.baggage.pieces_kg.len() as u8, &mut buffer_to_send);
+    marshal_u32(passenger.baggage.total_kg(), &mut buffer_to_send);
+    marshal_money(
+        baggage_policy.fee(seat_class, &passenger.baggage),
+        &mut buffer_to_send,
     );
 
-    // Just append the entry to the watchlist.
-    if !watchlist_db.contains_key(&flight_id) {
-        let watchlist = Vec::<WatchlistEntry>::new();
-        watchlist_db.insert(flight_id, watchlist);
-    } 
-    let watchlist = watchlist_db.get_mut(&flight_id).unwrap();
+    // Add the barcode.
+    marshal_string(&boarding_pass.barcode, &mut buffer_to_send);
+
+    buffer_to_send
+}
+
+// Subscribes the user to changes of the chosen fields of a flight, optionally only below thresholds.
+fn monitor_seat_availability_handler(
+    buf: &[u8],
+    user_id: u32,
+    pricing_currency: Currency,
+    flight_db: &FlightStore,
+    subscription_db: &mut SubscriptionStore,
+    client_addr: &SocketAddr,
+) -> Vec<u8> {
+    // Read id, monitor interval, the fields to watch and their thresholds from buf.
+    let (flight_id, i) = unmarshal_u32(buf, 0);
+    let (monitor_interval, i) = unmarshal_u32(buf, i);
+    let (fields, i) = unmarshal_u8(buf, i);
+    let (seats_below, i) = unmarshal_optional_u32(buf, i);
+    let (baggage_kg_below, i) = unmarshal_optional_u32(buf, i);
+    let (airfare_below, _) = unmarshal_optional_money(buf, i);
+    let airfare_below = match airfare_below {
+        Ok(airfare_below) => airfare_below,
+        Err(error_message) => return error_handler(&error_message),
+    };
+
+    // Check if the flight exists.
+    let flight = match flight_db.get(&flight_id) {
+        Some(flight) => flight,
+        None => return error_handler("No flight found for the given flight ID."),
+    };
+    if fields == 0 || fields & !ALL_FIELDS != 0 {
+        return error_handler(
+            "Invalid fields to watch. Add up 1 (seats), 2 (baggage), 4 (airfare) and 8 (status).",
+        );
+    }
+    if airfare_below.is_some_and(|airfare| airfare.currency != pricing_currency) {
+        return error_handler(&format!(
+            "An airfare threshold must be in {pricing_currency}, the currency fares are priced in."
+        ));
+    }
+    let watch = Watch {
+        fields,
+        seats_below,
+        baggage_kg_below,
+        airfare_below,
+    };
 
-    // Go through each entry and remove any entries with the same client address.
-    watchlist.retain(|entry| entry.1 != *client_addr);
-    watchlist.push(entry);
+    let expires_at = match subscription_expiry(monitor_interval) {
+        Ok(expires_at) => expires_at,
+        Err(error_message) => return error_handler(&error_message),
+    };
+    let subscription_id =
+        subscription_db.subscribe(flight, user_id, *client_addr, expires_at, watch);
 
-    println!("Added entry to watchlist.");
+    println!("Added subscription {subscription_id} to flight {flight_id}.");
 
     // Create a buffer to store the data to send with capacity 2048 bytes
     let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
@@ -486,32 +1300,230 @@ fn monitor_seat_availability_handler(
     // Add 1 if successful.
     buffer_to_send.push(1);
 
+    // Add the subscription ID, which the client needs to stop monitoring early, and when it expires.
+    marshal_u32(subscription_id, &mut buffer_to_send);
+    marshal_u32(expires_at, &mut buffer_to_send);
+
+    buffer_to_send
+}
+
+// Subscribes the user to the flights of a route, to be told when one is added, cancelled or has more seats
+// available.
+fn monitor_route_handler(
+    buf: &[u8],
+    user_id: u32,
+    flight_db: &FlightStore,
+    subscription_db: &mut SubscriptionStore,
+    client_addr: &SocketAddr,
+) -> Vec<u8> {
+    // Read the source, destination and monitor interval from buf.
+    let (source, i) = unmarshal_string(buf, 0);
+    let (destination, i) = unmarshal_string(buf, i);
+    let (monitor_interval, _) = unmarshal_u32(buf, i);
+
+    if source.is_empty() || destination.is_empty() {
+        return error_handler("Source and destination must not be empty.");
+    }
+    if source.len() > MAX_ROUTE_NAME_LENGTH || destination.len() > MAX_ROUTE_NAME_LENGTH {
+        return error_handler(&format!(
+            "Source and destination must be at most {MAX_ROUTE_NAME_LENGTH} bytes long."
+        ));
+    }
+
+    let expires_at = match subscription_expiry(monitor_interval) {
+        Ok(expires_at) => expires_at,
+        Err(error_message) => return error_handler(&error_message),
+    };
+    let subscription_id = subscription_db.subscribe_route(
+        flight_db,
+        &source,
+        &destination,
+        user_id,
+        *client_addr,
+        expires_at,
+    );
+    let num_flights = flight_db.on_route(&source, &destination).count() as u32;
+
+    println!("Added subscription {subscription_id} to route {source} to {destination}.");
+
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add the handler byte.
+    buffer_to_send.push(26);
+
+    // Add the subscription ID, when it expires and the number of flights on the route so far.
+    marshal_u32(subscription_id, &mut buffer_to_send);
+    marshal_u32(expires_at, &mut buffer_to_send);
+    marshal_u32(num_flights, &mut buffer_to_send);
+
+    buffer_to_send
+}
+
+// Stops sending the updates of a subscription before it expires. Only the user who subscribed can cancel it.
+fn unsubscribe_handler(
+    buf: &[u8],
+    user_id: u32,
+    subscription_db: &mut SubscriptionStore,
+) -> Vec<u8> {
+    // Read the subscription ID from buf.
+    let (subscription_id, _) = unmarshal_u32(buf, 0);
+
+    let subscription = match subscription_db.unsubscribe(subscription_id, user_id) {
+        Some(subscription) => subscription,
+        None => return error_handler("No subscription found for the given subscription ID."),
+    };
+    println!("Removed subscription {subscription_id}.");
+
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add the handler byte.
+    buffer_to_send.push(23);
+
+    // Add the cancelled subscription ID and the flight or route it was for.
+    marshal_u32(subscription.id, &mut buffer_to_send);
+    marshal_target(&subscription.target, &mut buffer_to_send);
+
+    buffer_to_send
+}
+
+// Responds with the current values of the fields a subscription watches, or of the flights on its route, for a
+// client that missed updates, along with the sequence number of the last update sent so the client can tell
+// later ones apart.
+fn resync_subscription_handler(
+    buf: &[u8],
+    user_id: u32,
+    flight_db: &FlightStore,
+    subscription_db: &mut SubscriptionStore,
+) -> Vec<u8> {
+    // Read the subscription ID from buf.
+    let (subscription_id, _) = unmarshal_u32(buf, 0);
+
+    let subscription = match subscription_db.resync(subscription_id, user_id, current_unix_time()) {
+        Some(subscription) => subscription,
+        None => return error_handler("No subscription found for the given subscription ID."),
+    };
+
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add the handler byte.
+    buffer_to_send.push(25);
+
+    // Add the subscription ID, the sequence number and what the subscription monitors.
+    marshal_u32(subscription.id, &mut buffer_to_send);
+    marshal_u32(subscription.last_sequence, &mut buffer_to_send);
+    marshal_target(&subscription.target, &mut buffer_to_send);
+
+    match &subscription.target {
+        Target::Flight {
+            flight_id, watch, ..
+        } => {
+            // Subscriptions to a flight are dropped with it, so the flight exists. Add the watched fields and
+            // the flight's values, as in an update.
+            let flight = flight_db.get(flight_id).unwrap();
+            marshal_watched_values(flight, watch.fields, &mut buffer_to_send);
+        }
+        Target::Route {
+            source,
+            destination,
+            ..
+        } => {
+            // Add the total number of flights on the route, then as many as fit, prefixed by their count.
+            let flights: Vec<&Flight> = flight_db.on_route(source, destination).collect();
+            marshal_u32(flights.len() as u32, &mut buffer_to_send);
+            let listed = &flights[..flights.len().min(MAX_RESYNCED_ROUTE_FLIGHTS)];
+            buffer_to_send.push(listed.len() as u8);
+            for flight in listed {
+                marshal_route_flight(flight, ROUTE_FLIGHT_LISTED, &mut buffer_to_send);
+            }
+        }
+    }
+
     buffer_to_send
 }
 
+// Responds with the user's active subscriptions, oldest first.
+fn list_subscriptions_handler(user_id: u32, subscription_db: &mut SubscriptionStore) -> Vec<u8> {
+    let subscriptions = subscription_db.for_user(user_id, current_unix_time());
+
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add the handler byte.
+    buffer_to_send.push(24);
+
+    // Add the total number of subscriptions, in case there are more than fit in the response.
+    marshal_u32(subscriptions.len() as u32, &mut buffer_to_send);
+
+    // Add the subscriptions, prefixed by their count.
+    let listed = &subscriptions[..subscriptions.len().min(MAX_LISTED_SUBSCRIPTIONS)];
+    buffer_to_send.push(listed.len() as u8);
+    for subscription in listed {
+        marshal_u32(subscription.id, &mut buffer_to_send);
+        marshal_target(&subscription.target, &mut buffer_to_send);
+        marshal_u32(subscription.expires_at, &mut buffer_to_send);
+    }
+
+    buffer_to_send
+}
+
+// Checks in baggage for one passenger of a booking and responds with the passenger's baggage weight, their
+// free allowance, the fee for these pieces and the passenger's total fee. The fee is for the weight above the
+// free allowance of the class. Only the user who made the booking can add baggage to it.
 fn reserve_baggage_handler(
     buf: &[u8],
-    flight_db: &mut HashMap<u32, Flight>,
+    user_id: u32,
+    flight_db: &mut FlightStore,
+    booking_db: &mut BookingStore,
+    baggage_policy: &BaggagePolicy,
 ) -> Vec<u8> {
-    // Read id and baggage weight to reserve in kg from buf.
-    let (flight_id, i) = unmarshal_u32(buf, 0);
-    let (baggage_weight, _) = unmarshal_u32(buf, i);
+    // Read the booking reference, the passenger and the weight of each piece in kg from buf.
+    let (booking_id, i) = unmarshal_u32(buf, 0);
+    let (passenger, i) = unmarshal_u8(buf, i);
+    let (pieces_kg, _) = unmarshal_u32_array(buf, i);
+
+    let booking = match booking_db.get_owned_mut(&booking_id, user_id) {
+        Some(booking) => booking,
+        None => return error_handler("No booking found for the given booking reference."),
+    };
+    let seat_class = booking.seat_class;
+    let flight_id = booking.flight_id;
+    let baggage = match booking.passenger_mut(passenger) {
+        Ok(passenger) => &mut passenger.baggage,
+        Err(error_message) => return error_handler(&error_message),
+    };
 
-    // Try to reserve the baggage.
-    if !flight_db.contains_key(&flight_id) {
-        return error_handler("No flight found for the given flight ID.");
+    let flight = match flight_db.get_mut(&flight_id) {
+        Some(flight) => flight,
+        None => return error_handler("No flight found for the given flight ID."),
+    };
+
+    if !flight.is_bookable() {
+        let status = flight.status;
+        return error_handler(&format!(
+            "Baggage can no longer be reserved on this flight because it is {status}."
+        ));
     }
 
-    let flight = flight_db.get_mut(&flight_id).unwrap();
+    if let Err(error_message) = baggage_policy.check(baggage, &pieces_kg) {
+        return error_handler(&error_message);
+    }
 
+    let baggage_weight = pieces_kg.iter().sum();
     let reservation_success = flight.reserve_baggage(baggage_weight);
 
     if !reservation_success {
         println!("Reservation of baggage failed.");
-        let current_baggage_capacity = flight.baggage_capacity_kg;
+        let current_baggage_capacity = flight.remaining_baggage_kg();
         return error_handler(&format!("There is not enough baggage capacity. You tried to reserve {baggage_weight} kg of baggage, but there are only {current_baggage_capacity} kg of baggage remaining."));
     }
 
+    let previous_fee = baggage_policy.fee(seat_class, baggage);
+    baggage.pieces_kg.extend(pieces_kg);
+    let total_fee = baggage_policy.fee(seat_class, baggage);
+
     // Create a buffer to store the data to send with capacity 2048 bytes
     let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
 
@@ -521,27 +1533,289 @@ fn reserve_baggage_handler(
     // Add 1 if successful.
     buffer_to_send.push(1);
 
+    // Add the passenger's baggage weight and free allowance, then the fee for these pieces and the total fee.
+    marshal_u32(baggage.total_kg(), &mut buffer_to_send);
+    marshal_u32(
+        baggage_policy.free_allowance_kg(seat_class),
+        &mut buffer_to_send,
+    );
+    marshal_money(
+        Money::new(
+            total_fee.minor_units - previous_fee.minor_units,
+            total_fee.currency,
+        ),
+        &mut buffer_to_send,
+    );
+    marshal_money(total_fee, &mut buffer_to_send);
+
     buffer_to_send
 }
 
-// Sends a message to the socket to update them of the number of seats available.
-fn inform_client(socket: &UdpSocket, client_addr: SocketAddr, flight_id: u32, seats: u32) {
+fn search_flights_handler(buf: &[u8], flight_db: &FlightStore, rates: &RateTable) -> Vec<u8> {
+    // Read the optional filters, the sort key, the page and the optional currency to show the fares in from
+    // the buffer.
+    let (source, i) = unmarshal_optional_string(buf, 0);
+    let (destination, i) = unmarshal_optional_string(buf, i);
+    let (departure_after, i) = unmarshal_optional_u32(buf, i);
+    let (departure_before, i) = unmarshal_optional_u32(buf, i);
+    let (seat_class, i) = unmarshal_optional_u8(buf, i);
+    let (min_seats, i) = unmarshal_optional_u32(buf, i);
+    let (max_airfare, i) = unmarshal_optional_money(buf, i);
+    let max_airfare = match max_airfare {
+        Ok(max_airfare) => max_airfare,
+        Err(error_message) => return error_handler(&error_message),
+    };
+    let (min_baggage_capacity_kg, i) = unmarshal_optional_u32(buf, i);
+    let (sort_by, i) = unmarshal_u8(buf, i);
+    let (offset, i) = unmarshal_u32(buf, i);
+    let (limit, i) = unmarshal_u32(buf, i);
+    let (display_currency, _) = unmarshal_optional_string(buf, i);
+
+    let display = match rates.display(display_currency.as_deref()) {
+        Ok(display) => display,
+        Err(error_message) => return error_handler(&error_message),
+    };
+
+    // The maximum airfare can be given in any currency with a known rate.
+    let max_airfare = match max_airfare.map(|airfare| rates.convert(airfare, rates.base())) {
+        Some(Err(error_message)) => return error_handler(&error_message),
+        Some(Ok(airfare)) => Some(airfare),
+        None => None,
+    };
+
+    let sort_by = match SortKey::from_u8(sort_by) {
+        Some(sort_by) => sort_by,
+        None => {
+            return error_handler(
+                "Invalid sort key. Use 0 (departure time), 1 (airfare) or 2 (seats).",
+            )
+        }
+    };
+
+    if limit == 0 {
+        return error_handler("Page size must be at least 1.");
+    }
+
+    let seat_class = match seat_class.map(SeatClass::from_u8) {
+        Some(None) => {
+            return error_handler(
+                "Invalid seat class. Use 0 (economy), 1 (premium) or 2 (business).",
+            )
+        }
+        Some(seat_class) => seat_class,
+        None => None,
+    };
+
+    let query = FlightQuery {
+        source,
+        destination,
+        departure_after,
+        departure_before,
+        seat_class,
+        min_seats,
+        max_airfare,
+        min_baggage_capacity_kg,
+        bookable_only: false,
+        sort_by,
+        offset,
+        limit,
+    };
+    let page = flight_db.query(&query);
+
     // Create a buffer to store the data to send with capacity 2048 bytes
     let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
 
     // Add the handler byte.
-    buffer_to_send.push(4);
+    buffer_to_send.push(7);
+
+    // Add the total number of matches so the client knows whether there are more pages.
+    marshal_u32(page.total_matches, &mut buffer_to_send);
+
+    // Add the flights in this page, prefixed by their count.
+    buffer_to_send.push(page.flights.len() as u8);
+    for flight in page.flights {
+        flight.marshal_listing(&display, &mut buffer_to_send);
+    }
+
+    buffer_to_send
+}
+
+// Registers a traveller, or an admin if the server's admin key is given.
+fn register_handler(buf: &[u8], admin_key: Option<&str>, accounts: &mut AccountStore) -> Vec<u8> {
+    // Read the username, password and optional admin key from buf.
+    let (username, i) = unmarshal_string(buf, 0);
+    let (password, i) = unmarshal_string(buf, i);
+    let (key, _) = unmarshal_optional_string(buf, i);
+
+    let role = match (key, admin_key) {
+        (None, _) => Role::Traveller,
+        (Some(_), None) => {
+            return error_handler("Admins cannot be registered on this server.");
+        }
+        (Some(key), Some(admin_key)) if admin::keys_match(admin_key, &key) => Role::Admin,
+        (Some(_), Some(_)) => return error_handler("Invalid admin key."),
+    };
+
+    let user_id = match accounts.register(&username, &password, role) {
+        Ok(user_id) => user_id,
+        Err(error_message) => return error_handler(&error_message),
+    };
+    println!("Registered user {user_id} as {username}, a {role}.");
+
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add the handler byte.
+    buffer_to_send.push(18);
 
-    // Add the flight ID and seats to the buffer.
-    marshal_u32(flight_id, &mut buffer_to_send);
-    marshal_u32(seats, &mut buffer_to_send);
+    // Add the new user's ID.
+    marshal_u32(user_id, &mut buffer_to_send);
+
+    buffer_to_send
+}
+
+// Logs a user in and responds with a session token for the services that act for a user, and the time it
+// expires at.
+fn login_handler(buf: &[u8], accounts: &AccountStore, session_signer: &SessionSigner) -> Vec<u8> {
+    // Read the username and password from buf.
+    let (username, i) = unmarshal_string(buf, 0);
+    let (password, _) = unmarshal_string(buf, i);
+
+    let account = match accounts.login(&username, &password) {
+        Ok(account) => account,
+        Err(error_message) => return error_handler(&error_message),
+    };
+    let (session_token, expires_at) = session_signer.issue(account.id, current_unix_time());
+    println!("User {} logged in as {}.", account.id, account.username);
+
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add the handler byte.
+    buffer_to_send.push(19);
+
+    // Add the user's ID and role, the session token and when it expires.
+    marshal_u32(account.id, &mut buffer_to_send);
+    marshal_u8(account.role.to_u8(), &mut buffer_to_send);
+    marshal_string(&session_token, &mut buffer_to_send);
+    marshal_u32(expires_at, &mut buffer_to_send);
+
+    buffer_to_send
+}
+
+// Gives a user a role, such as making a traveller an agent.
+fn set_role_handler(buf: &[u8], accounts: &mut AccountStore) -> Vec<u8> {
+    // Read the username and the role from buf.
+    let (username, i) = unmarshal_string(buf, 0);
+    let (role, _) = unmarshal_u8(buf, i);
+
+    let role = match Role::from_u8(role) {
+        Some(role) => role,
+        None => return error_handler("Invalid role. Use 0 (traveller), 1 (agent) or 2 (admin)."),
+    };
+    let user_id = match accounts.set_role(&username, role) {
+        Ok(user_id) => user_id,
+        Err(error_message) => return error_handler(&error_message),
+    };
+    println!("User {user_id} is now a {role}.");
+
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add the handler byte.
+    buffer_to_send.push(21);
+
+    // Add the user's ID and new role.
+    marshal_u32(user_id, &mut buffer_to_send);
+    marshal_u8(role.to_u8(), &mut buffer_to_send);
+
+    buffer_to_send
+}
+
+// Responds with a page of the user's bookings, oldest first, starting at the offset.
+fn list_bookings_handler(
+    buf: &[u8],
+    user_id: u32,
+    flight_db: &FlightStore,
+    booking_db: &BookingStore,
+) -> Vec<u8> {
+    // Read the offset from buf.
+    let (offset, _) = unmarshal_u32(buf, 0);
+
+    let bookings = booking_db.for_user(user_id);
+    let page = bookings
+        .iter()
+        .skip(offset as usize)
+        .take(MAX_LISTED_BOOKINGS)
+        .collect::<Vec<_>>();
+
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add the handler byte.
+    buffer_to_send.push(20);
+
+    // Add the total number of bookings so the client knows whether there are more pages.
+    marshal_u32(bookings.len() as u32, &mut buffer_to_send);
+
+    // Add the bookings in this page, prefixed by their count, each with the flight it is on.
+    buffer_to_send.push(page.len() as u8);
+    for booking in page {
+        // Flights are never removed, so the flight of a booking exists.
+        let flight = flight_db.get(&booking.flight_id).unwrap();
+        marshal_u32(booking.id, &mut buffer_to_send);
+        marshal_u32(flight.id, &mut buffer_to_send);
+        marshal_string(&flight.source, &mut buffer_to_send);
+        marshal_string(&flight.destination, &mut buffer_to_send);
+        marshal_u32(flight.departure_time, &mut buffer_to_send);
+        buffer_to_send.push(flight.status.to_u8());
+        marshal_u8(booking.seat_class.to_u8(), &mut buffer_to_send);
+        marshal_u32(booking.num_seats, &mut buffer_to_send);
+        marshal_money(booking.airfare, &mut buffer_to_send);
+    }
+
+    buffer_to_send
+}
+
+// Sends a message to the socket to tell them that the status of a flight has changed.
+fn inform_status_changed(socket: &UdpSocket, client_addr: SocketAddr, flight: &Flight) {
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add the handler byte. 11 is reserved for status changed callbacks.
+    buffer_to_send.push(11);
+
+    // Add the flight ID, the new status and the departure time, which may have moved with a delay.
+    marshal_u32(flight.id, &mut buffer_to_send);
+    buffer_to_send.push(flight.status.to_u8());
+    marshal_u32(flight.departure_time, &mut buffer_to_send);
 
     println!(
-        "Informing client: {}, flight_id: {}, seats: {}",
-        client_addr, flight_id, seats
+        "Informing client: {}, flight_id: {}, status: {}",
+        client_addr, flight.id, flight.status
     );
     // Send the message to the client.
-    socket
-        .send_to(&buffer_to_send, &client_addr)
-        .expect("Error on send");
+    networking::send_callback(buffer_to_send, socket, &client_addr);
+}
+
+// When a subscription for the monitor interval, in seconds, expires. The interval comes from the client, so
+// it is checked here rather than trusting the client to limit it.
+fn subscription_expiry(monitor_interval: u32) -> Result<u32, String> {
+    if monitor_interval > MAX_MONITOR_INTERVAL {
+        return Err(format!(
+            "Monitor interval must be at most {MAX_MONITOR_INTERVAL} seconds (1 year)."
+        ));
+    }
+    current_unix_time()
+        .checked_add(monitor_interval)
+        .ok_or_else(|| "Monitor interval is too long.".to_string())
+}
+
+fn current_unix_time() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        .try_into()
+        .unwrap()
 }
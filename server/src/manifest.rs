@@ -0,0 +1,331 @@
+use crate::{
+    booking::{BookedPassenger, Booking},
+    passenger::Passenger,
+};
+
+// Upper bound on the size of the manifest text in one response, so that it always fits in one datagram.
+pub const MAX_PAGE_BYTES: usize = 1500;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ManifestFormat {
+    Csv,
+    Json,
+}
+
+impl ManifestFormat {
+    pub fn from_u8(value: u8) -> Option<ManifestFormat> {
+        match value {
+            0 => Some(ManifestFormat::Csv),
+            1 => Some(ManifestFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+// The columns of the manifest, in order.
+const COLUMNS: [&str; 9] = [
+    "booking",
+    "passenger",
+    "class",
+    "seat",
+    "name",
+    "document_number",
+    "date_of_birth",
+    "contact",
+    "baggage_kg",
+];
+
+// Columns written as JSON numbers rather than strings.
+const NUMBER_COLUMNS: [&str; 3] = ["booking", "passenger", "baggage_kg"];
+
+// One seat of a booking on the flight. Seats whose passenger details have not been given yet have empty
+// details, so that they stand out.
+struct ManifestRow<'a> {
+    booking: &'a Booking,
+    // From 1, in the order of the seats of the booking.
+    passenger_number: usize,
+    passenger: &'a BookedPassenger,
+}
+
+impl ManifestRow<'_> {
+    // The value of every column, in the order of `COLUMNS`.
+    fn values(&self) -> [String; 9] {
+        let booking = self.booking;
+        let index = self.passenger_number - 1;
+        let detail = |field: fn(&Passenger) -> &String| {
+            self.passenger
+                .details
+                .as_ref()
+                .map(field)
+                .cloned()
+                .unwrap_or_default()
+        };
+        [
+            booking.id.to_string(),
+            self.passenger_number.to_string(),
+            booking.seat_class.to_string(),
+            booking.seats.get(index).cloned().unwrap_or_default(),
+            detail(|passenger| &passenger.name),
+            detail(|passenger| &passenger.document_number),
+            detail(|passenger| &passenger.date_of_birth),
+            detail(|passenger| &passenger.contact),
+            self.passenger.baggage.total_kg().to_string(),
+        ]
+    }
+}
+
+// One page of a flight's passenger manifest.
+pub struct ManifestPage {
+    // Passengers across every page.
+    pub total_passengers: u32,
+    // The passenger the page starts at, from 0.
+    pub offset: u32,
+    pub num_passengers: u32,
+    pub text: String,
+}
+
+// Lists every seat booked on the flight, ordered by booking reference and then passenger, starting at the
+// offset and stopping before the text would grow past `MAX_PAGE_BYTES`. A CSV page starts with a header
+// row and a JSON page is an array of objects, so that every page can be read on its own.
+pub fn export_manifest(bookings: &[&Booking], format: ManifestFormat, offset: u32) -> ManifestPage {
+    let mut bookings = bookings.to_vec();
+    bookings.sort_by_key(|booking| booking.id);
+    let rows = bookings
+        .iter()
+        .flat_map(|booking| {
+            booking
+                .passengers
+                .iter()
+                .enumerate()
+                .map(|(index, passenger)| ManifestRow {
+                    booking,
+                    passenger_number: index + 1,
+                    passenger,
+                })
+        })
+        .collect::<Vec<ManifestRow>>();
+
+    let (mut text, end) = match format {
+        ManifestFormat::Csv => (csv_line(COLUMNS.map(str::to_string)), ""),
+        ManifestFormat::Json => ("[".to_string(), "]"),
+    };
+    let mut num_passengers = 0;
+    for row in rows.iter().skip(offset as usize) {
+        let line = match format {
+            ManifestFormat::Csv => csv_line(row.values()),
+            ManifestFormat::Json => {
+                let separator = if num_passengers == 0 { "" } else { "," };
+                format!("{separator}{}", json_object(row.values()))
+            }
+        };
+        if text.len() + line.len() + end.len() > MAX_PAGE_BYTES {
+            break;
+        }
+        text.push_str(&line);
+        num_passengers += 1;
+    }
+    text.push_str(end);
+
+    ManifestPage {
+        total_passengers: rows.len() as u32,
+        offset,
+        num_passengers,
+        text,
+    }
+}
+
+// Quotes fields with commas, quotes or line breaks, doubling any quotes inside them.
+fn csv_line(fields: [String; 9]) -> String {
+    let fields = fields.map(|field| {
+        if field.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field
+        }
+    });
+    format!("{}\n", fields.join(","))
+}
+
+fn json_object(values: [String; 9]) -> String {
+    let members = COLUMNS
+        .iter()
+        .zip(values)
+        .map(|(column, value)| {
+            if NUMBER_COLUMNS.contains(column) {
+                format!("\"{column}\":{value}")
+            } else {
+                format!("\"{column}\":{}", json_string(&value))
+            }
+        })
+        .collect::<Vec<String>>();
+    format!("{{{}}}", members.join(","))
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use marshaling::{Currency, Money};
+
+    use super::*;
+    use crate::{baggage::PassengerBaggage, flight::SeatClass};
+
+    fn details(name: &str, contact: &str) -> Passenger {
+        Passenger {
+            name: name.to_string(),
+            document_number: "E1234567".to_string(),
+            date_of_birth: "1990-01-31".to_string(),
+            contact: contact.to_string(),
+        }
+    }
+
+    // A booking of one seat on each passenger, without a seat map.
+    fn booking(id: u32, passengers: Vec<Option<Passenger>>) -> Booking {
+        Booking {
+            id,
+            flight_id: 1,
+            user_id: 1,
+            client_addr: "127.0.0.1:9000".parse().unwrap(),
+            seat_class: SeatClass::Economy,
+            num_seats: passengers.len() as u32,
+            airfare: Money::new(1000, Currency::parse("SGD").unwrap()),
+            seats: Vec::new(),
+            passengers: passengers
+                .into_iter()
+                .map(|details| BookedPassenger {
+                    details,
+                    baggage: PassengerBaggage {
+                        pieces_kg: vec![20],
+                    },
+                    boarding_pass: None,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn csv_fields_with_commas_quotes_or_line_breaks_are_quoted() {
+        let bookings = [
+            booking(
+                2,
+                vec![Some(details("Lee, \"Mary\"", "line\nbreak\r")), None],
+            ),
+            booking(1, vec![Some(details("José Müller", "jose@example.com"))]),
+        ];
+        let page = export_manifest(
+            &bookings.iter().collect::<Vec<&Booking>>(),
+            ManifestFormat::Csv,
+            0,
+        );
+        assert_eq!(
+            page.text,
+            "booking,passenger,class,seat,name,document_number,date_of_birth,contact,baggage_kg\n\
+             1,1,economy,,José Müller,E1234567,1990-01-31,jose@example.com,20\n\
+             2,1,economy,,\"Lee, \"\"Mary\"\"\",E1234567,1990-01-31,\"line\nbreak\r\",20\n\
+             2,2,economy,,,,,,20\n"
+        );
+        assert_eq!(page.total_passengers, 3);
+        assert_eq!(page.num_passengers, 3);
+    }
+
+    #[test]
+    fn json_strings_are_escaped_and_numbers_are_not_quoted() {
+        let bookings = [booking(
+            1,
+            vec![Some(details(
+                "Lee \"Mary\" \\ José",
+                "a\nb\r\tc\u{1}\u{7f}",
+            ))],
+        )];
+        let page = export_manifest(
+            &bookings.iter().collect::<Vec<&Booking>>(),
+            ManifestFormat::Json,
+            0,
+        );
+        assert_eq!(
+            page.text,
+            "[{\"booking\":1,\"passenger\":1,\"class\":\"economy\",\"seat\":\"\",\
+             \"name\":\"Lee \\\"Mary\\\" \\\\ José\",\"document_number\":\"E1234567\",\
+             \"date_of_birth\":\"1990-01-31\",\"contact\":\"a\\nb\\r\\tc\\u0001\\u007f\",\
+             \"baggage_kg\":20}]"
+        );
+    }
+
+    #[test]
+    fn a_row_that_exactly_fills_the_page_is_kept_on_it() {
+        // Pad the names so that the header and a whole number of rows are exactly `MAX_PAGE_BYTES` long.
+        let header_len = csv_line(COLUMNS.map(str::to_string)).len();
+        let row_len = |name_len| {
+            let booking = booking(1000, vec![Some(details(&"a".repeat(name_len), "1234567"))]);
+            export_manifest(&[&booking], ManifestFormat::Csv, 0)
+                .text
+                .len()
+                - header_len
+        };
+        let name_len = (1..=64)
+            .find(|name_len| (MAX_PAGE_BYTES - header_len).is_multiple_of(row_len(*name_len)))
+            .unwrap();
+        let rows_per_page = (MAX_PAGE_BYTES - header_len) / row_len(name_len);
+        let bookings = (1000..1000 + 2 * rows_per_page as u32)
+            .map(|id| booking(id, vec![Some(details(&"a".repeat(name_len), "1234567"))]))
+            .collect::<Vec<Booking>>();
+        let bookings = bookings.iter().collect::<Vec<&Booking>>();
+
+        let first = export_manifest(&bookings, ManifestFormat::Csv, 0);
+        assert_eq!(first.text.len(), MAX_PAGE_BYTES);
+        assert_eq!(first.num_passengers as usize, rows_per_page);
+        let second = export_manifest(&bookings, ManifestFormat::Csv, first.num_passengers);
+        assert_eq!(second.offset as usize, rows_per_page);
+        assert_eq!(second.num_passengers as usize, rows_per_page);
+        assert_eq!(second.text.len(), MAX_PAGE_BYTES);
+        let first_row = second.text.lines().nth(1).unwrap();
+        assert!(first_row.starts_with(&format!("{},1,", 1000 + rows_per_page)));
+        let past_the_end =
+            export_manifest(&bookings, ManifestFormat::Csv, 2 * rows_per_page as u32);
+        assert_eq!(past_the_end.num_passengers, 0);
+        assert_eq!(past_the_end.text, csv_line(COLUMNS.map(str::to_string)));
+    }
+
+    #[test]
+    fn json_pages_split_between_whole_objects() {
+        let bookings = (10..50)
+            .map(|id| booking(id, vec![Some(details("Mary Lee", "mary@example.com"))]))
+            .collect::<Vec<Booking>>();
+        let bookings = bookings.iter().collect::<Vec<&Booking>>();
+        let one_row = export_manifest(&bookings[..1], ManifestFormat::Json, 0).text;
+        let object_len = one_row.len() - "[]".len();
+
+        let mut offset = 0;
+        while offset < 40 {
+            let page = export_manifest(&bookings, ManifestFormat::Json, offset);
+            assert_eq!(page.offset, offset);
+            assert!(page
+                .text
+                .starts_with(&format!("[{{\"booking\":{},", offset + 10)));
+            assert!(page.text.ends_with("}]"));
+            assert!(page.text.len() <= MAX_PAGE_BYTES);
+            // The next object and its separator would not have fitted.
+            if offset + page.num_passengers < 40 {
+                assert!(page.text.len() + 1 + object_len > MAX_PAGE_BYTES);
+            }
+            offset += page.num_passengers;
+        }
+        assert_eq!(offset, 40);
+    }
+}
@@ -0,0 +1,238 @@
+use crate::{
+    booking::Booking,
+    flight::{Flight, SeatClass},
+};
+
+// The order in which bookings are bumped from an overbooked class at check-in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BumpPolicy {
+    // The most recent booking is bumped first.
+    LatestBookingFirst,
+    // The booking that paid the lowest fare per seat is bumped first, the most recent one on a tie.
+    LowestFareFirst,
+}
+
+impl BumpPolicy {
+    pub fn from_u8(value: u8) -> Option<BumpPolicy> {
+        match value {
+            0 => Some(BumpPolicy::LatestBookingFirst),
+            1 => Some(BumpPolicy::LowestFareFirst),
+            _ => None,
+        }
+    }
+}
+
+// Seats that can be sold in a cabin with the given physical seats, rounding the extra seats down.
+pub fn sellable_seats(physical_seats: u32, overbooking_percent: u32) -> u32 {
+    let extra_seats = u64::from(physical_seats) * u64::from(overbooking_percent) / 100;
+    u32::try_from(u64::from(physical_seats) + extra_seats).unwrap_or(u32::MAX)
+}
+
+// Picks the bookings to bump so that no class of the flight has more seats sold than physical seats.
+// Bookings are bumped whole, in the order of the policy, until enough seats are freed in the class.
+// Bookings of other flights are ignored. Returns the booking references to bump.
+pub fn select_bumped_bookings(
+    flight: &Flight,
+    bookings: &[&Booking],
+    policy: BumpPolicy,
+) -> Vec<u32> {
+    let mut bumped_booking_ids = Vec::new();
+    for seat_class in SeatClass::ALL {
+        let cabin = flight.cabin(seat_class);
+        let mut excess_seats = cabin.sold.saturating_sub(cabin.seats);
+        if excess_seats == 0 {
+            continue;
+        }
+
+        let mut candidates = bookings
+            .iter()
+            .filter(|booking| booking.flight_id == flight.id && booking.seat_class == seat_class)
+            .collect::<Vec<&&Booking>>();
+        // Booking references only go up, so the highest one is the most recent booking.
+        candidates.sort_by(|a, b| match policy {
+            BumpPolicy::LatestBookingFirst => b.id.cmp(&a.id),
+            BumpPolicy::LowestFareFirst => a
+                .airfare
+                .minor_units
+                .cmp(&b.airfare.minor_units)
+                .then(b.id.cmp(&a.id)),
+        });
+
+        for booking in candidates {
+            if excess_seats == 0 {
+                break;
+            }
+            bumped_booking_ids.push(booking.id);
+            excess_seats = excess_seats.saturating_sub(booking.num_seats);
+        }
+    }
+    bumped_booking_ids
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use marshaling::{Currency, Money};
+
+    use super::*;
+    use crate::flight::{Cabin, FlightStatus};
+
+    fn sgd(minor_units: i64) -> Money {
+        Money::new(minor_units, Currency::parse("SGD").unwrap())
+    }
+
+    // A flight with the given physical and sold seats in economy, and empty premium and business cabins.
+    fn flight(id: u32, seats: u32, sold: u32) -> Flight {
+        Flight {
+            id,
+            source: "A".to_string(),
+            destination: "B".to_string(),
+            departure_time: 0,
+            cabins: [
+                Cabin {
+                    sold,
+                    ..Cabin::new(seats, sgd(1000))
+                },
+                Cabin::new(0, sgd(0)),
+                Cabin::new(0, sgd(0)),
+            ],
+            baggage_capacity_kg: 0,
+            baggage_reserved_kg: 0,
+            status: FlightStatus::Boarding,
+            gate: "A1".to_string(),
+            seat_map: None,
+            overbooking_percent: 0,
+        }
+    }
+
+    fn booking(id: u32, flight_id: u32, num_seats: u32, airfare_cents: i64) -> Booking {
+        Booking {
+            id,
+            flight_id,
+            user_id: 1,
+            client_addr: SocketAddr::from(([127, 0, 0, 1], 9000)),
+            seat_class: SeatClass::Economy,
+            num_seats,
+            airfare: sgd(airfare_cents),
+            seats: vec![],
+            passengers: vec![],
+        }
+    }
+
+    fn select(flight: &Flight, bookings: &[Booking], policy: BumpPolicy) -> Vec<u32> {
+        let bookings = bookings.iter().collect::<Vec<&Booking>>();
+        select_bumped_bookings(flight, &bookings, policy)
+    }
+
+    #[test]
+    fn sellable_seats_round_extra_seats_down() {
+        assert_eq!(sellable_seats(10, 0), 10);
+        assert_eq!(sellable_seats(10, 15), 11);
+        assert_eq!(sellable_seats(10, 19), 11);
+        assert_eq!(sellable_seats(10, 20), 12);
+        assert_eq!(sellable_seats(0, 50), 0);
+        assert_eq!(sellable_seats(u32::MAX, 100), u32::MAX);
+    }
+
+    #[test]
+    fn available_seats_include_overbooking() {
+        let mut flight = flight(1, 10, 0);
+        flight.overbooking_percent = 20;
+        assert_eq!(flight.available_seats(Some(SeatClass::Economy)), 12);
+        assert!(flight.reserve_seats(SeatClass::Economy, 11));
+        assert_eq!(flight.available_seats(Some(SeatClass::Economy)), 1);
+        assert!(!flight.reserve_seats(SeatClass::Economy, 2));
+        assert_eq!(flight.cabin(SeatClass::Economy).sold, 11);
+    }
+
+    #[test]
+    fn available_seats_do_not_underflow_when_physical_seats_shrink() {
+        let flight = flight(1, 5, 8);
+        assert_eq!(flight.available_seats(Some(SeatClass::Economy)), 0);
+        assert_eq!(flight.available_seats(None), 0);
+    }
+
+    #[test]
+    fn nothing_is_bumped_without_excess_seats() {
+        let bookings = [booking(1, 1, 5, 1000), booking(2, 1, 5, 1000)];
+        assert!(select(
+            &flight(1, 10, 10),
+            &bookings,
+            BumpPolicy::LatestBookingFirst
+        )
+        .is_empty());
+        assert!(select(&flight(1, 10, 7), &bookings, BumpPolicy::LowestFareFirst).is_empty());
+    }
+
+    #[test]
+    fn latest_booking_is_bumped_first() {
+        let bookings = [
+            booking(3, 1, 1, 1000),
+            booking(1, 1, 1, 1000),
+            booking(2, 1, 1, 1000),
+        ];
+        let flight = flight(1, 1, 3);
+        assert_eq!(
+            select(&flight, &bookings, BumpPolicy::LatestBookingFirst),
+            vec![3, 2]
+        );
+    }
+
+    #[test]
+    fn lowest_fare_is_bumped_first_with_latest_booking_on_a_tie() {
+        let bookings = [
+            booking(1, 1, 1, 500),
+            booking(2, 1, 1, 2000),
+            booking(3, 1, 1, 500),
+            booking(4, 1, 1, 800),
+        ];
+        let flight = flight(1, 1, 4);
+        assert_eq!(
+            select(&flight, &bookings, BumpPolicy::LowestFareFirst),
+            vec![3, 1, 4]
+        );
+    }
+
+    #[test]
+    fn group_bookings_are_bumped_whole() {
+        // One excess seat bumps the whole group of three, and nobody else.
+        let bookings = [booking(1, 1, 2, 1000), booking(2, 1, 3, 1000)];
+        let flight = flight(1, 4, 5);
+        assert_eq!(
+            select(&flight, &bookings, BumpPolicy::LatestBookingFirst),
+            vec![2]
+        );
+    }
+
+    #[test]
+    fn only_oversold_classes_of_the_flight_are_bumped() {
+        let mut flight = flight(1, 2, 3);
+        flight.cabins[2] = Cabin {
+            sold: 1,
+            ..Cabin::new(2, sgd(4000))
+        };
+        let mut business = booking(4, 1, 1, 100);
+        business.seat_class = SeatClass::Business;
+        let bookings = [
+            booking(1, 1, 1, 1000),
+            booking(2, 1, 2, 1000),
+            booking(3, 2, 1, 100),
+            business,
+        ];
+        assert_eq!(
+            select(&flight, &bookings, BumpPolicy::LowestFareFirst),
+            vec![2]
+        );
+    }
+
+    #[test]
+    fn flights_with_a_seat_map_are_never_overbooked() {
+        let mut flight = flight(1, 10, 0);
+        flight.overbooking_percent = 50;
+        flight.set_seat_map(
+            crate::seat_map::SeatMap::new("AB", vec![SeatClass::Economy], &[], &[]).unwrap(),
+        );
+        assert_eq!(flight.available_seats(Some(SeatClass::Economy)), 2);
+    }
+}
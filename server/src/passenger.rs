@@ -0,0 +1,252 @@
+// The details of the passenger travelling on one seat of a booking.
+#[derive(Clone)]
+pub struct Passenger {
+    pub name: String,
+    pub document_number: String,
+    // YYYY-MM-DD
+    pub date_of_birth: String,
+    // An email address or a phone number.
+    pub contact: String,
+}
+
+const MAX_NAME_LENGTH: usize = 64;
+const MAX_CONTACT_LENGTH: usize = 64;
+
+impl Passenger {
+    // Validates the details, given the current Unix time so that births in the future are rejected.
+    pub fn new(
+        name: &str,
+        document_number: &str,
+        date_of_birth: &str,
+        contact: &str,
+        now: u32,
+    ) -> Result<Passenger, String> {
+        let name = name.trim();
+        if name.is_empty()
+            || name.chars().count() > MAX_NAME_LENGTH
+            || !name.chars().any(char::is_alphabetic)
+            || !name
+                .chars()
+                .all(|c| c.is_alphabetic() || matches!(c, ' ' | '-' | '\'' | '.'))
+        {
+            return Err(format!("A name must be 1 to {MAX_NAME_LENGTH} letters, and may also have spaces, hyphens, apostrophes and full stops."));
+        }
+
+        let document_number = document_number.trim();
+        if !(5..=20).contains(&document_number.len())
+            || !document_number
+                .chars()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+        {
+            return Err(
+                "A document number must be 5 to 20 upper case letters and digits.".to_string(),
+            );
+        }
+
+        let date_of_birth = date_of_birth.trim();
+        match parse_date(date_of_birth) {
+            Some((year, _, _)) if year < 1900 => {
+                return Err("The date of birth must be in 1900 or later.".to_string())
+            }
+            Some(date) if days_from_civil(date) > i64::from(now / 86400) => {
+                return Err("The date of birth must not be in the future.".to_string())
+            }
+            Some(_) => {}
+            None => {
+                return Err(
+                    "The date of birth must be a real date in the format YYYY-MM-DD.".to_string(),
+                )
+            }
+        }
+
+        let contact = contact.trim();
+        if contact.len() > MAX_CONTACT_LENGTH || !(is_email(contact) || is_phone_number(contact)) {
+            return Err(format!("The contact must be an email address or a phone number of 7 to 15 digits, at most {MAX_CONTACT_LENGTH} characters long."));
+        }
+
+        Ok(Passenger {
+            name: name.to_string(),
+            document_number: document_number.to_string(),
+            date_of_birth: date_of_birth.to_string(),
+            contact: contact.to_string(),
+        })
+    }
+}
+
+// Parses a YYYY-MM-DD date into its year, month and day, checking that the day exists.
+fn parse_date(date: &str) -> Option<(i64, u32, u32)> {
+    let bytes = date.as_bytes();
+    if bytes.len() != 10
+        || bytes[4] != b'-'
+        || bytes[7] != b'-'
+        || [0, 1, 2, 3, 5, 6, 8, 9]
+            .iter()
+            .any(|index| !bytes[*index].is_ascii_digit())
+    {
+        return None;
+    }
+    let year = date[0..4].parse::<i64>().ok()?;
+    let month = date[5..7].parse::<u32>().ok()?;
+    let day = date[8..10].parse::<u32>().ok()?;
+    let is_leap_year = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+    let days_in_month = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if is_leap_year => 29,
+        2 => 28,
+        _ => return None,
+    };
+    (1..=days_in_month)
+        .contains(&day)
+        .then_some((year, month, day))
+}
+
+// Days from 1970-01-01 to the date, following Howard Hinnant's days_from_civil.
+pub fn days_from_civil((year, month, day): (i64, u32, u32)) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_from_march = i64::from((month + 9) % 12);
+    let day_of_year = (153 * month_from_march + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn is_email(contact: &str) -> bool {
+    match contact.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.contains('@')
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !contact.contains(char::is_whitespace)
+        }
+        None => false,
+    }
+}
+
+// An optional leading '+' and 7 to 15 digits, which may be grouped with spaces or hyphens.
+fn is_phone_number(contact: &str) -> bool {
+    let digits = contact.strip_prefix('+').unwrap_or(contact);
+    let num_digits = digits.chars().filter(char::is_ascii_digit).count();
+    (7..=15).contains(&num_digits)
+        && digits
+            .chars()
+            .all(|c| c.is_ascii_digit() || c == ' ' || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-06-15 00:00 UTC.
+    const NOW: u32 = 1_718_409_600;
+
+    fn passenger(
+        name: &str,
+        document_number: &str,
+        date_of_birth: &str,
+        contact: &str,
+    ) -> Result<Passenger, String> {
+        Passenger::new(name, document_number, date_of_birth, contact, NOW)
+    }
+
+    #[test]
+    fn valid_details_are_trimmed() {
+        let details = passenger(
+            " Mary-Ann O'Neil Jr. ",
+            " E1234567 ",
+            " 1990-01-31 ",
+            " mary@example.com ",
+        )
+        .unwrap();
+        assert_eq!(details.name, "Mary-Ann O'Neil Jr.");
+        assert_eq!(details.document_number, "E1234567");
+        assert_eq!(details.date_of_birth, "1990-01-31");
+        assert_eq!(details.contact, "mary@example.com");
+    }
+
+    #[test]
+    fn names_must_be_letters_with_some_punctuation() {
+        let valid = |name: &str| passenger(name, "E1234567", "1990-01-31", "+65 6123 4567").is_ok();
+        assert!(valid("José Müller"));
+        assert!(valid(&"a".repeat(MAX_NAME_LENGTH)));
+        assert!(valid(&"é".repeat(MAX_NAME_LENGTH)));
+        assert!(!valid(&"a".repeat(MAX_NAME_LENGTH + 1)));
+        assert!(!valid(""));
+        assert!(!valid("   "));
+        assert!(!valid("-'."));
+        assert!(!valid("R2D2"));
+        assert!(!valid("Mary_Lee"));
+    }
+
+    #[test]
+    fn document_numbers_must_be_5_to_20_upper_case_letters_and_digits() {
+        let valid = |document_number: &str| {
+            passenger("Mary Lee", document_number, "1990-01-31", "+65 6123 4567").is_ok()
+        };
+        assert!(valid("A1234"));
+        assert!(valid(&"9".repeat(20)));
+        assert!(!valid("A123"));
+        assert!(!valid(&"9".repeat(21)));
+        assert!(!valid("e1234567"));
+        assert!(!valid("E123-4567"));
+        assert!(!valid("É1234567"));
+    }
+
+    #[test]
+    fn dates_of_birth_must_be_real_dates_from_1900_until_today() {
+        let valid = |date_of_birth: &str| {
+            passenger("Mary Lee", "E1234567", date_of_birth, "+65 6123 4567").is_ok()
+        };
+        assert!(valid("1900-01-01"));
+        assert!(valid("2000-02-29"));
+        assert!(valid("2024-02-29"));
+        assert!(valid("2024-06-15"));
+        assert!(!valid("1899-12-31"));
+        assert!(!valid("2024-06-16"));
+        assert!(!valid("1900-02-29"));
+        assert!(!valid("2023-02-29"));
+        assert!(!valid("2023-04-31"));
+        assert!(!valid("2023-13-01"));
+        assert!(!valid("2023-00-10"));
+        assert!(!valid("2023-01-00"));
+        assert!(!valid("2023-1-01"));
+        assert!(!valid("2023/01/01"));
+        assert!(!valid("+023-01-01"));
+    }
+
+    #[test]
+    fn contacts_must_be_email_addresses_or_phone_numbers() {
+        let valid =
+            |contact: &str| passenger("Mary Lee", "E1234567", "1990-01-31", contact).is_ok();
+        assert!(valid("mary@example.com"));
+        assert!(valid("+65 6123-4567"));
+        assert!(valid("1234567"));
+        assert!(valid(&"1".repeat(15)));
+        assert!(!valid("123456"));
+        assert!(!valid(&"1".repeat(16)));
+        assert!(!valid("+65 (6123) 4567"));
+        assert!(!valid("mary@example"));
+        assert!(!valid("@example.com"));
+        assert!(!valid("mary@.example.com"));
+        assert!(!valid("mary@example.com."));
+        assert!(!valid("mary@ex@ample.com"));
+        assert!(!valid("mary lee@example.com"));
+        assert!(!valid(&format!(
+            "{}@example.com",
+            "m".repeat(MAX_CONTACT_LENGTH)
+        )));
+    }
+
+    #[test]
+    fn days_are_counted_from_the_unix_epoch() {
+        assert_eq!(days_from_civil((1970, 1, 1)), 0);
+        assert_eq!(days_from_civil((1969, 12, 31)), -1);
+        assert_eq!(days_from_civil((1900, 1, 1)), -25567);
+        assert_eq!(days_from_civil((2000, 3, 1)), 11017);
+        assert_eq!(days_from_civil((2024, 6, 15)), i64::from(NOW / 86400));
+        assert_eq!(parse_date("2024-06-15"), Some((2024, 6, 15)));
+    }
+}
@@ -0,0 +1,97 @@
+use marshaling::Money;
+
+use crate::flight::{Flight, SeatClass};
+
+// What a pricing rule can look at when pricing one class of a flight. The class itself sets the base fare
+// that the rules scale.
+pub struct PricingContext {
+    // Seats sold in the class over seats that can be sold, usually from 0 to 1.
+    pub load_factor: f32,
+    // Zero once the departure time has passed.
+    pub seconds_to_departure: u32,
+}
+
+// A rule scales the base fare of a class by a multiplier. Rules are independent of each other, and the
+// fare is the base fare multiplied by the multiplier of every rule.
+pub trait PricingRule {
+    fn multiplier(&self, context: &PricingContext) -> f32;
+}
+
+// Raises the fare as the class fills up. Uses the multiplier of the highest tier the load factor reaches.
+pub struct LoadFactorRule {
+    // Minimum load factor and multiplier, in increasing order of load factor.
+    pub tiers: Vec<(f32, f32)>,
+}
+
+impl PricingRule for LoadFactorRule {
+    fn multiplier(&self, context: &PricingContext) -> f32 {
+        self.tiers
+            .iter()
+            .rev()
+            .find(|(min_load_factor, _)| context.load_factor >= *min_load_factor)
+            .map_or(1.0, |(_, multiplier)| *multiplier)
+    }
+}
+
+// Changes the fare as departure gets closer. Uses the multiplier of the closest tier the flight is within.
+pub struct TimeToDepartureRule {
+    // Seconds before departure and multiplier, in increasing order of seconds.
+    pub tiers: Vec<(u32, f32)>,
+}
+
+impl PricingRule for TimeToDepartureRule {
+    fn multiplier(&self, context: &PricingContext) -> f32 {
+        self.tiers
+            .iter()
+            .find(|(within_seconds, _)| context.seconds_to_departure <= *within_seconds)
+            .map_or(1.0, |(_, multiplier)| *multiplier)
+    }
+}
+
+pub struct PricingEngine {
+    rules: Vec<Box<dyn PricingRule>>,
+}
+
+impl PricingEngine {
+    pub fn new(rules: Vec<Box<dyn PricingRule>>) -> PricingEngine {
+        PricingEngine { rules }
+    }
+
+    // The current fare of a seat in the class, in the currency of its base fare and rounded to its minor unit.
+    pub fn fare(&self, flight: &Flight, seat_class: SeatClass, now: u32) -> Money {
+        let cabin = flight.cabin(seat_class);
+        let sellable_seats = flight.sellable_seats(seat_class);
+        let context = PricingContext {
+            load_factor: if sellable_seats == 0 {
+                1.0
+            } else {
+                cabin.sold as f32 / sellable_seats as f32
+            },
+            seconds_to_departure: flight.departure_time.saturating_sub(now),
+        };
+        let multiplier = self.rules.iter().fold(1.0, |multiplier, rule| {
+            multiplier * f64::from(rule.multiplier(&context))
+        });
+        cabin.base_airfare.scale(multiplier)
+    }
+}
+
+impl Default for PricingEngine {
+    // Fares rise by up to half as a class fills up, rise in the last three days before departure and are
+    // discounted more than a month out.
+    fn default() -> PricingEngine {
+        PricingEngine::new(vec![
+            Box::new(LoadFactorRule {
+                tiers: vec![(0.5, 1.1), (0.75, 1.25), (0.9, 1.5)],
+            }),
+            Box::new(TimeToDepartureRule {
+                tiers: vec![
+                    (86400, 1.3),
+                    (3 * 86400, 1.15),
+                    (30 * 86400, 1.0),
+                    (u32::MAX, 0.9),
+                ],
+            }),
+        ])
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::{BTreeMap, HashMap};
+
+use marshaling::Money;
+
+use crate::flight::{Flight, SeatClass};
+
+// How long a quoted fare can be booked at, in seconds.
+pub const QUOTE_VALIDITY_SECONDS: u32 = 300;
+
+// How many quotes are kept. Beyond this, the oldest quotes are dropped before they expire.
+const MAX_QUOTES: usize = 10_000;
+
+// The fares of every class of a flight at the time they were shown to a user. Only that user can book at
+// them, once.
+pub struct Quote {
+    pub flight_id: u32,
+    pub user_id: u32,
+    airfares: [Money; 3], // Indexed by SeatClass
+    pub expires_at: u32,
+}
+
+impl Quote {
+    pub fn airfare(&self, seat_class: SeatClass) -> Money {
+        self.airfares[seat_class.to_u8() as usize]
+    }
+}
+
+pub struct QuoteStore {
+    // By quote ID, which only goes up, so the oldest quotes come first and also expire first.
+    quotes: BTreeMap<u32, Quote>,
+    // The latest quote of each user for each flight, which replaces any earlier one.
+    latest_quote_ids: HashMap<(u32, u32), u32>,
+    next_quote_id: u32,
+}
+
+impl QuoteStore {
+    pub fn new() -> QuoteStore {
+        QuoteStore {
+            quotes: BTreeMap::new(),
+            latest_quote_ids: HashMap::new(),
+            next_quote_id: 1,
+        }
+    }
+
+    // Records the current fares of the flight for the user and returns the quote ID. The user's earlier
+    // quote for the flight and expired quotes are dropped.
+    pub fn issue(&mut self, flight: &Flight, user_id: u32, now: u32) -> (u32, &Quote) {
+        while let Some((id, quote)) = self.quotes.first_key_value() {
+            if quote.expires_at > now && self.quotes.len() < MAX_QUOTES {
+                break;
+            }
+            self.remove(*id);
+        }
+        if let Some(id) = self.latest_quote_ids.get(&(user_id, flight.id)) {
+            self.remove(*id);
+        }
+
+        let id = self.next_quote_id;
+        self.next_quote_id += 1;
+        let quote = Quote {
+            flight_id: flight.id,
+            user_id,
+            airfares: SeatClass::ALL.map(|seat_class| flight.airfare(Some(seat_class))),
+            expires_at: now + QUOTE_VALIDITY_SECONDS,
+        };
+        self.latest_quote_ids.insert((user_id, flight.id), id);
+        (id, self.quotes.entry(id).or_insert(quote))
+    }
+
+    // The quote, if it was issued to the user for the flight and has not expired.
+    pub fn get_valid(
+        &self,
+        quote_id: u32,
+        flight_id: u32,
+        user_id: u32,
+        now: u32,
+    ) -> Result<&Quote, String> {
+        match self.quotes.get(&quote_id) {
+            Some(quote) if quote.user_id == user_id && quote.flight_id != flight_id => {
+                Err("The quote was given for a different flight.".to_string())
+            }
+            Some(quote) if quote.user_id == user_id && quote.expires_at > now => Ok(quote),
+            _ => Err(
+                "No such quote, or it has expired or been used. Get the flight summary again for a new quote."
+                    .to_string(),
+            ),
+        }
+    }
+
+    // Drops the quote, e.g. once a booking has used it.
+    pub fn remove(&mut self, quote_id: u32) {
+        if let Some(quote) = self.quotes.remove(&quote_id) {
+            let key = (quote.user_id, quote.flight_id);
+            if self.latest_quote_ids.get(&key) == Some(&quote_id) {
+                self.latest_quote_ids.remove(&key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use marshaling::{Currency, Money};
+
+    use super::*;
+    use crate::flight::{Cabin, FlightStatus};
+
+    const NOW: u32 = 1_000_000;
+
+    fn flight(id: u32) -> Flight {
+        let sgd = |minor_units| Money::new(minor_units, Currency::parse("SGD").unwrap());
+        Flight {
+            id,
+            source: "A".to_string(),
+            destination: "B".to_string(),
+            departure_time: NOW + 3600,
+            cabins: [
+                Cabin::new(10, sgd(1000)),
+                Cabin::new(10, sgd(2000)),
+                Cabin::new(10, sgd(3000)),
+            ],
+            baggage_capacity_kg: 0,
+            baggage_reserved_kg: 0,
+            status: FlightStatus::Scheduled,
+            gate: "A1".to_string(),
+            seat_map: None,
+            overbooking_percent: 0,
+        }
+    }
+
+    #[test]
+    fn quotes_are_only_valid_for_their_user_and_flight_until_they_expire() {
+        let mut quotes = QuoteStore::new();
+        let (id, _) = quotes.issue(&flight(1), 7, NOW);
+        assert!(quotes.get_valid(id, 1, 7, NOW).is_ok());
+        assert!(quotes.get_valid(id, 1, 8, NOW).is_err());
+        assert!(quotes.get_valid(id, 2, 7, NOW).is_err());
+        assert!(quotes
+            .get_valid(id, 1, 7, NOW + QUOTE_VALIDITY_SECONDS - 1)
+            .is_ok());
+        assert!(quotes
+            .get_valid(id, 1, 7, NOW + QUOTE_VALIDITY_SECONDS)
+            .is_err());
+
+        quotes.remove(id);
+        assert!(quotes.get_valid(id, 1, 7, NOW).is_err());
+    }
+
+    #[test]
+    fn a_new_quote_replaces_the_users_earlier_one_for_the_flight() {
+        let mut quotes = QuoteStore::new();
+        let (first, _) = quotes.issue(&flight(1), 7, NOW);
+        let (other_flight, _) = quotes.issue(&flight(2), 7, NOW);
+        let (other_user, _) = quotes.issue(&flight(1), 8, NOW);
+        let (second, _) = quotes.issue(&flight(1), 7, NOW);
+        assert!(quotes.get_valid(first, 1, 7, NOW).is_err());
+        assert!(quotes.get_valid(second, 1, 7, NOW).is_ok());
+        assert!(quotes.get_valid(other_flight, 2, 7, NOW).is_ok());
+        assert!(quotes.get_valid(other_user, 1, 8, NOW).is_ok());
+        assert_eq!(quotes.quotes.len(), 3);
+    }
+
+    #[test]
+    fn the_oldest_quotes_are_dropped_beyond_the_limit() {
+        let mut quotes = QuoteStore::new();
+        let flight = flight(1);
+        let ids = (0..MAX_QUOTES as u32 + 1)
+            .map(|user_id| quotes.issue(&flight, user_id, NOW).0)
+            .collect::<Vec<u32>>();
+        assert_eq!(quotes.quotes.len(), MAX_QUOTES);
+        assert_eq!(quotes.latest_quote_ids.len(), MAX_QUOTES);
+        assert!(quotes.get_valid(ids[0], 1, 0, NOW).is_err());
+        assert!(quotes.get_valid(ids[1], 1, 1, NOW).is_ok());
+
+        // Expired quotes are dropped when the next one is issued.
+        quotes.issue(&flight, 0, NOW + QUOTE_VALIDITY_SECONDS);
+        assert_eq!(quotes.quotes.len(), 1);
+        assert_eq!(quotes.latest_quote_ids.len(), 1);
+    }
+}
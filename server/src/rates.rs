@@ -0,0 +1,162 @@
+use std::{collections::HashMap, fs};
+
+use marshaling::{Currency, Money};
+
+// Exchange rates from the base currency, which every fare on the server is set and charged in, to the
+// currencies clients can ask to see fares in.
+pub struct RateTable {
+    base: Currency,
+    // Units of each currency that one unit of the base currency buys, including the base currency itself.
+    rates: HashMap<Currency, f64>,
+}
+
+impl RateTable {
+    // A table that only knows the base currency.
+    pub fn new(base: Currency) -> RateTable {
+        RateTable {
+            base,
+            rates: HashMap::from([(base, 1.0)]),
+        }
+    }
+
+    // Loads a rate file. The first line that is not blank or a comment gives the base currency, e.g.
+    // "base SGD", and every following line gives a currency and how many units of it one unit of the base
+    // currency buys, e.g. "USD 0.74". Comments start with '#'.
+    pub fn load(path: &str) -> Result<RateTable, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|error| format!("Could not read the rate file {path}: {error}"))?;
+
+        let mut table: Option<RateTable> = None;
+        for (line_index, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let line_error = |message: &str| format!("{path}:{}: {message}", line_index + 1);
+            let (key, value) = match line.split_whitespace().collect::<Vec<&str>>()[..] {
+                [key, value] => (key, value),
+                _ => return Err(line_error("Expected two fields.")),
+            };
+            match &mut table {
+                None => {
+                    let base = Some(value)
+                        .filter(|_| key == "base")
+                        .and_then(Currency::parse)
+                        .ok_or_else(|| {
+                            line_error(
+                                "The first line must give the base currency, e.g. \"base SGD\".",
+                            )
+                        })?;
+                    table = Some(RateTable::new(base));
+                }
+                Some(table) => {
+                    let currency = Currency::parse(key).ok_or_else(|| {
+                        line_error("A currency code must be three upper case letters.")
+                    })?;
+                    let rate = value
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|rate| rate.is_finite() && *rate > 0.0)
+                        .ok_or_else(|| line_error("A rate must be a positive number."))?;
+                    if currency == table.base {
+                        return Err(line_error("The base currency cannot be given a rate."));
+                    }
+                    table.rates.insert(currency, rate);
+                }
+            }
+        }
+        table.ok_or_else(|| format!("The rate file {path} does not give a base currency."))
+    }
+
+    pub fn base(&self) -> Currency {
+        self.base
+    }
+
+    // Converts an amount in any currency of the table to another one, rounding to the nearest minor unit.
+    pub fn convert(&self, money: Money, to: Currency) -> Result<Money, String> {
+        if money.currency == to {
+            return Ok(money);
+        }
+        let from_rate = self.rate(money.currency)?;
+        let to_rate = self.rate(to)?;
+        let units = money.minor_units as f64 / money.currency.minor_units_per_unit() as f64;
+        let minor_units = units / from_rate * to_rate * to.minor_units_per_unit() as f64;
+        Ok(Money::new(minor_units.round() as i64, to))
+    }
+
+    // A function showing fares of the base currency in the currency the client asked for, or in the base
+    // currency if it did not ask for one. Fails if the table has no rate for the currency.
+    pub fn display(
+        &self,
+        currency_code: Option<&str>,
+    ) -> Result<impl Fn(Money) -> Money + '_, String> {
+        let currency = match currency_code {
+            Some(code) => {
+                let currency = Currency::parse(code).ok_or_else(|| {
+                    "A currency code must be three upper case letters, e.g. SGD.".to_string()
+                })?;
+                self.rate(currency)?;
+                currency
+            }
+            None => self.base,
+        };
+        // Both currencies are known, so the conversion cannot fail.
+        Ok(move |money: Money| self.convert(money, currency).unwrap_or(money))
+    }
+
+    fn rate(&self, currency: Currency) -> Result<f64, String> {
+        self.rates
+            .get(&currency)
+            .copied()
+            .ok_or_else(|| format!("No exchange rate is known for {currency}."))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn currency(code: &str) -> Currency {
+        Currency::parse(code).unwrap()
+    }
+
+    fn table() -> RateTable {
+        let mut table = RateTable::new(currency("SGD"));
+        table.rates.insert(currency("USD"), 0.74);
+        table.rates.insert(currency("JPY"), 110.0);
+        table
+    }
+
+    #[test]
+    fn conversion_rounds_to_the_minor_unit_of_the_target_currency() {
+        let table = table();
+        let sgd = Money::new(1313, currency("SGD"));
+        assert_eq!(
+            table.convert(sgd, currency("USD")),
+            Ok(Money::new(972, currency("USD")))
+        );
+        assert_eq!(
+            table.convert(sgd, currency("JPY")),
+            Ok(Money::new(1444, currency("JPY")))
+        );
+        assert_eq!(table.convert(sgd, currency("SGD")), Ok(sgd));
+        assert_eq!(
+            table.convert(Money::new(1444, currency("JPY")), currency("SGD")),
+            Ok(Money::new(1313, currency("SGD")))
+        );
+    }
+
+    #[test]
+    fn unknown_currencies_are_errors() {
+        let table = table();
+        let sgd = Money::new(100, currency("SGD"));
+        assert!(table.convert(sgd, currency("EUR")).is_err());
+        assert!(table.display(Some("EUR")).is_err());
+        assert!(table.display(Some("usd")).is_err());
+        assert_eq!(
+            table.display(Some("USD")).unwrap()(sgd),
+            Money::new(74, currency("USD"))
+        );
+        assert_eq!(table.display(None).unwrap()(sgd), sgd);
+    }
+}
@@ -0,0 +1,326 @@
+use std::collections::{HashMap, HashSet};
+
+use marshaling::{marshal_string, marshal_u32, marshal_u8};
+
+use crate::flight::SeatClass;
+
+// States of a single seat, as sent to clients.
+const SEAT_FREE: u8 = 0;
+const SEAT_OCCUPIED: u8 = 1;
+const SEAT_BLOCKED: u8 = 2;
+
+// Upper bound on the bytes taken by the rows of a marshaled seat map, leaving room for the rest of the response.
+const MAX_MARSHALED_SEATS: usize = 1800;
+
+// The physical layout of a flight's cabin and which seats are taken. Seats are named by row number and
+// letter, e.g. "12A". Rows are numbered from 1.
+pub struct SeatMap {
+    // The seat letters of every row from left to right, with a space for each aisle, e.g. "ABC DEF".
+    letters: String,
+    // The seat class of each row, with row 1 first.
+    row_classes: Vec<SeatClass>,
+    exit_rows: HashSet<u32>,
+    blocked: HashSet<String>,
+    // Seat name to the booking it is assigned to.
+    occupied: HashMap<String, u32>,
+}
+
+impl SeatMap {
+    pub fn new(
+        letters: &str,
+        row_classes: Vec<SeatClass>,
+        exit_rows: &[u32],
+        blocked: &[String],
+    ) -> Result<SeatMap, String> {
+        if letters.is_empty() || !letters.chars().all(|c| c.is_ascii_uppercase() || c == ' ') {
+            return Err(
+                "Seat letters must be upper case letters, with spaces for aisles.".to_string(),
+            );
+        }
+        let seat_letters = letters.chars().filter(|c| *c != ' ').collect::<Vec<char>>();
+        if seat_letters.is_empty()
+            || seat_letters.iter().collect::<HashSet<&char>>().len() != seat_letters.len()
+        {
+            return Err("Seat letters must not repeat.".to_string());
+        }
+        if row_classes.is_empty() || row_classes.len() > 255 {
+            return Err("A seat map must have between 1 and 255 rows.".to_string());
+        }
+
+        // Every row is sent as a class byte, an exit row byte and a byte per seat, and must fit in one datagram.
+        if row_classes.len() * (2 + seat_letters.len()) > MAX_MARSHALED_SEATS {
+            return Err("The seat map is too large.".to_string());
+        }
+
+        let mut seat_map = SeatMap {
+            letters: letters.to_string(),
+            row_classes,
+            exit_rows: exit_rows.iter().copied().collect(),
+            blocked: HashSet::new(),
+            occupied: HashMap::new(),
+        };
+
+        if let Some(row) = seat_map
+            .exit_rows
+            .iter()
+            .find(|row| **row == 0 || **row as usize > seat_map.row_classes.len())
+        {
+            return Err(format!("Exit row {row} is not on the seat map."));
+        }
+
+        for seat in blocked {
+            let (row, letter) = seat_map
+                .parse_seat(seat)
+                .ok_or(format!("Blocked seat {seat} is not on the seat map."))?;
+            seat_map.blocked.insert(format!("{row}{letter}"));
+        }
+
+        Ok(seat_map)
+    }
+
+    // Splits a seat name into its row and letter, if the seat exists on this map. The row may be written with
+    // leading zeros or a plus sign, so seats are only compared by the name `format!("{row}{letter}")` gives.
+    fn parse_seat(&self, seat: &str) -> Option<(u32, char)> {
+        let letter = seat.chars().last()?;
+        let row = seat[..seat.len() - letter.len_utf8()].parse::<u32>().ok()?;
+        if row == 0 || row as usize > self.row_classes.len() || letter == ' ' {
+            return None;
+        }
+        if !self.letters.contains(letter) {
+            return None;
+        }
+        Some((row, letter))
+    }
+
+    fn row_class(&self, row: u32) -> SeatClass {
+        self.row_classes[row as usize - 1]
+    }
+
+    fn is_free(&self, seat: &str) -> bool {
+        !self.blocked.contains(seat) && !self.occupied.contains_key(seat)
+    }
+
+    // Number of seats in the class that are neither blocked nor occupied.
+    pub fn free_seats(&self, seat_class: SeatClass) -> u32 {
+        (1..=self.row_classes.len() as u32)
+            .filter(|row| self.row_class(*row) == seat_class)
+            .flat_map(|row| self.row_seats(row))
+            .filter(|seat| self.is_free(seat))
+            .count() as u32
+    }
+
+    fn row_seats(&self, row: u32) -> impl Iterator<Item = String> + '_ {
+        self.letters
+            .chars()
+            .filter(|c| *c != ' ')
+            .map(move |letter| format!("{row}{letter}"))
+    }
+
+    // Checks that every chosen seat exists, is in the class and is free, without taking any of them. Returns
+    // the seats by the names the map uses for them, e.g. "1A" for "01A".
+    pub fn check_seats(
+        &self,
+        seat_class: SeatClass,
+        seats: &[String],
+    ) -> Result<Vec<String>, String> {
+        let mut checked_seats = Vec::with_capacity(seats.len());
+        for seat in seats {
+            let (row, letter) = self
+                .parse_seat(seat)
+                .ok_or(format!("Seat {seat} does not exist on this flight."))?;
+            let seat = format!("{row}{letter}");
+            if self.row_class(row) != seat_class {
+                return Err(format!("Seat {seat} is not in {seat_class}."));
+            }
+            if !self.is_free(&seat) {
+                return Err(format!("Seat {seat} is not available."));
+            }
+            if checked_seats.contains(&seat) {
+                return Err("The same seat was chosen more than once.".to_string());
+            }
+            checked_seats.push(seat);
+        }
+        Ok(checked_seats)
+    }
+
+    // Picks free seats for a group in the class, without taking them. A block of adjacent seats in one
+    // row is preferred, where seats on either side of an aisle are not adjacent. If no row has such a
+    // block, the group is seated front to back, filling each row before moving to the next.
+    pub fn pick_seats(&self, seat_class: SeatClass, num_seats: u32) -> Result<Vec<String>, String> {
+        let num_seats = num_seats as usize;
+        if num_seats == 0 {
+            return Ok(vec![]);
+        }
+        let class_rows = (1..=self.row_classes.len() as u32)
+            .filter(|row| self.row_class(*row) == seat_class)
+            .collect::<Vec<u32>>();
+
+        for row in class_rows.iter() {
+            for block in self.letters.split(' ') {
+                let free_run = block
+                    .chars()
+                    .map(|letter| format!("{row}{letter}"))
+                    .collect::<Vec<String>>();
+                for window in free_run.windows(num_seats) {
+                    if window.iter().all(|seat| self.is_free(seat)) {
+                        return Ok(window.to_vec());
+                    }
+                }
+            }
+        }
+
+        let free_seats = class_rows
+            .iter()
+            .flat_map(|row| self.row_seats(*row))
+            .filter(|seat| self.is_free(seat))
+            .take(num_seats)
+            .collect::<Vec<String>>();
+        if free_seats.len() < num_seats {
+            return Err(format!(
+                "Not enough {seat_class} seats available on the seat map."
+            ));
+        }
+        Ok(free_seats)
+    }
+
+    // Takes the seats for the booking. The seats must have been checked or picked first.
+    pub fn occupy(&mut self, seats: &[String], booking_id: u32) {
+        for seat in seats {
+            self.occupied.insert(seat.clone(), booking_id);
+        }
+    }
+
+    // Frees the seats of a cancelled booking.
+    pub fn release(&mut self, seats: &[String]) {
+        for seat in seats {
+            self.occupied.remove(seat);
+        }
+    }
+
+    // Appends the seat map to the buffer: the seat letters, then for every row its class, whether it is
+    // an exit row and the state of each seat in letter order.
+    pub fn marshal(&self, buf: &mut Vec<u8>) {
+        marshal_string(&self.letters, buf);
+        marshal_u32(self.row_classes.len() as u32, buf);
+        for row in 1..=self.row_classes.len() as u32 {
+            marshal_u8(self.row_class(row).to_u8(), buf);
+            marshal_u8(self.exit_rows.contains(&row) as u8, buf);
+            for seat in self.row_seats(row) {
+                let state = if self.blocked.contains(&seat) {
+                    SEAT_BLOCKED
+                } else if self.occupied.contains_key(&seat) {
+                    SEAT_OCCUPIED
+                } else {
+                    SEAT_FREE
+                };
+                marshal_u8(state, buf);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use marshaling::{Currency, Money};
+
+    use super::*;
+    use crate::{
+        booking::BookingStore,
+        flight::{Cabin, Flight, FlightStatus, SeatSelection},
+    };
+
+    fn sgd(minor_units: i64) -> Money {
+        Money::new(minor_units, Currency::parse("SGD").unwrap())
+    }
+
+    // A flight with four economy rows of "AB CD", and seat 4D blocked.
+    fn flight() -> Flight {
+        let mut flight = Flight {
+            id: 1,
+            source: "A".to_string(),
+            destination: "B".to_string(),
+            departure_time: 0,
+            cabins: [
+                Cabin::new(0, sgd(1000)),
+                Cabin::new(0, sgd(0)),
+                Cabin::new(0, sgd(0)),
+            ],
+            baggage_capacity_kg: 0,
+            baggage_reserved_kg: 0,
+            status: FlightStatus::Scheduled,
+            gate: "A1".to_string(),
+            seat_map: None,
+            overbooking_percent: 0,
+        };
+        let seat_map = SeatMap::new(
+            "AB CD",
+            vec![SeatClass::Economy; 4],
+            &[],
+            &["04D".to_string()],
+        )
+        .unwrap();
+        flight.set_seat_map(seat_map);
+        flight
+    }
+
+    fn book(
+        bookings: &mut BookingStore,
+        flight: &mut Flight,
+        seats: &[&str],
+    ) -> Result<(u32, Vec<String>), String> {
+        let seats = seats.iter().map(|seat| seat.to_string()).collect();
+        bookings.book(
+            flight,
+            1,
+            SocketAddr::from(([127, 0, 0, 1], 9000)),
+            SeatClass::Economy,
+            &SeatSelection::Chosen(seats),
+            sgd(1000),
+        )
+    }
+
+    #[test]
+    fn seats_written_with_leading_zeros_are_the_same_seat() {
+        let mut flight = flight();
+        let mut bookings = BookingStore::new();
+        assert_eq!(
+            book(&mut bookings, &mut flight, &["1A"]),
+            Ok((1, vec!["1A".to_string()]))
+        );
+        assert!(book(&mut bookings, &mut flight, &["01A"]).is_err());
+        assert!(book(&mut bookings, &mut flight, &["+1A"]).is_err());
+        assert!(book(&mut bookings, &mut flight, &["2B", "002B"]).is_err());
+        assert_eq!(
+            book(&mut bookings, &mut flight, &["02B"]),
+            Ok((2, vec!["2B".to_string()]))
+        );
+        assert_eq!(flight.available_seats(Some(SeatClass::Economy)), 13);
+    }
+
+    #[test]
+    fn blocked_seats_cannot_be_booked_however_they_are_written() {
+        let mut flight = flight();
+        let mut bookings = BookingStore::new();
+        assert!(book(&mut bookings, &mut flight, &["4D"]).is_err());
+        assert!(book(&mut bookings, &mut flight, &["04D"]).is_err());
+        assert_eq!(flight.available_seats(Some(SeatClass::Economy)), 15);
+    }
+
+    #[test]
+    fn seats_must_be_on_the_map() {
+        let seat_map = flight().seat_map.unwrap();
+        for seat in ["0A", "5A", "1E", "1 ", "A", "", "-1A"] {
+            assert!(
+                seat_map
+                    .check_seats(SeatClass::Economy, &[seat.to_string()])
+                    .is_err(),
+                "{seat:?}"
+            );
+        }
+        assert!(seat_map
+            .check_seats(SeatClass::Premium, &["1A".to_string()])
+            .is_err());
+    }
+}
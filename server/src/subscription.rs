@@ -0,0 +1,609 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::{SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use marshaling::{marshal_money, marshal_string, marshal_u32, marshal_u8, Money};
+
+use crate::{
+    flight::{Flight, FlightStatus},
+    flight_store::FlightStore,
+};
+
+// How long to wait for the first acknowledgement of an update before sending it again. The wait doubles with
+// every attempt, and the update is dropped after the last one.
+const FIRST_RETRY_DELAY: Duration = Duration::from_millis(500);
+const MAX_SEND_ATTEMPTS: u32 = 5;
+
+// The fields a subscription can watch, as bits of the fields byte of the monitor service. Updates use the same
+// bits to say which fields changed.
+pub const WATCH_SEATS: u8 = 1;
+pub const WATCH_BAGGAGE: u8 = 2;
+pub const WATCH_AIRFARE: u8 = 4;
+pub const WATCH_STATUS: u8 = 8;
+pub const ALL_FIELDS: u8 = WATCH_SEATS | WATCH_BAGGAGE | WATCH_AIRFARE | WATCH_STATUS;
+
+// What happened to a flight on a monitored route. Resyncs list the flights on the route with no event.
+pub const ROUTE_FLIGHT_LISTED: u8 = 0;
+const ROUTE_FLIGHT_ADDED: u8 = 1;
+const ROUTE_FLIGHT_CANCELLED: u8 = 2;
+const ROUTE_SEATS_AVAILABLE: u8 = 3;
+
+// The kind of target a subscription has, as sent when listing or resyncing subscriptions.
+const FLIGHT_TARGET: u8 = 0;
+const ROUTE_TARGET: u8 = 1;
+
+// What a subscription watches. A change to a watched field is reported, unless the field has a threshold and
+// its new value is not below it, e.g. only when fewer than 5 seats are left.
+#[derive(Clone)]
+pub struct Watch {
+    pub fields: u8,
+    pub seats_below: Option<u32>,
+    pub baggage_kg_below: Option<u32>,
+    // In the currency the flight is priced in.
+    pub airfare_below: Option<Money>,
+}
+
+impl Watch {
+    // The watched fields that changed between the two values and are reported, as bits.
+    fn reported_changes(&self, before: &WatchedValues, after: &WatchedValues) -> u8 {
+        let is_below =
+            |value, threshold: Option<u32>| threshold.is_none_or(|threshold| value < threshold);
+        let mut changes = 0;
+        if before.available_seats != after.available_seats
+            && is_below(after.available_seats, self.seats_below)
+        {
+            changes |= WATCH_SEATS;
+        }
+        if before.baggage_capacity_kg != after.baggage_capacity_kg
+            && is_below(after.baggage_capacity_kg, self.baggage_kg_below)
+        {
+            changes |= WATCH_BAGGAGE;
+        }
+        if before.airfare != after.airfare
+            && self
+                .airfare_below
+                .is_none_or(|threshold| after.airfare.minor_units < threshold.minor_units)
+        {
+            changes |= WATCH_AIRFARE;
+        }
+        if before.status != after.status {
+            changes |= WATCH_STATUS;
+        }
+        changes & self.fields
+    }
+}
+
+// The values of a flight that subscriptions can watch.
+#[derive(Clone, Copy)]
+pub struct WatchedValues {
+    available_seats: u32,
+    baggage_capacity_kg: u32,
+    // The cheapest fare of any class.
+    airfare: Money,
+    status: FlightStatus,
+}
+
+impl WatchedValues {
+    pub fn of(flight: &Flight) -> WatchedValues {
+        WatchedValues {
+            available_seats: flight.available_seats(None),
+            baggage_capacity_kg: flight.remaining_baggage_kg(),
+            airfare: flight.airfare(None),
+            status: flight.status,
+        }
+    }
+}
+
+// What a subscription monitors, with the values as of the last update, or of subscribing, which changes are
+// found against.
+#[derive(Clone)]
+pub enum Target {
+    // Changes to the watched fields of one flight.
+    Flight {
+        flight_id: u32,
+        watch: Watch,
+        last_values: WatchedValues,
+    },
+    // Flights added to a route, cancelled, or with more seats available than before. The values are kept for
+    // each flight on the route.
+    Route {
+        source: String,
+        destination: String,
+        last_values: HashMap<u32, WatchedValues>,
+    },
+}
+
+impl Target {
+    // Whether both targets monitor the same flight or route.
+    fn is_same_as(&self, other: &Target) -> bool {
+        match (self, other) {
+            (
+                Target::Flight { flight_id, .. },
+                Target::Flight {
+                    flight_id: other, ..
+                },
+            ) => flight_id == other,
+            (
+                Target::Route {
+                    source,
+                    destination,
+                    ..
+                },
+                Target::Route {
+                    source: other_source,
+                    destination: other_destination,
+                    ..
+                },
+            ) => source == other_source && destination == other_destination,
+            _ => false,
+        }
+    }
+}
+
+// A client monitoring a flight or route until the subscription expires or is cancelled.
+#[derive(Clone)]
+pub struct Subscription {
+    pub id: u32,
+    // The user who subscribed, who is the only one who can cancel it.
+    pub user_id: u32,
+    // Where updates are sent.
+    pub client_addr: SocketAddr,
+    pub expires_at: u32,
+    pub target: Target,
+    // The sequence number of the last update sent, starting at 1 for the first. Clients notice missed updates
+    // by gaps in the sequence.
+    pub last_sequence: u32,
+}
+
+impl Subscription {
+    fn is_active(&self, now: u32) -> bool {
+        self.expires_at > now
+    }
+
+    // The flight monitored, unless the subscription is to a route.
+    pub fn flight_id(&self) -> Option<u32> {
+        match self.target {
+            Target::Flight { flight_id, .. } => Some(flight_id),
+            Target::Route { .. } => None,
+        }
+    }
+
+    // Sends the next update, made up of the handler byte, the subscription ID and the sequence number, then
+    // the values, and returns it to be kept until it is acknowledged.
+    fn send_update(
+        &mut self,
+        handler_byte: u8,
+        marshal_values: impl FnOnce(&mut Vec<u8>),
+        socket: &UdpSocket,
+    ) -> PendingUpdate {
+        self.last_sequence += 1;
+
+        // Create a buffer to store the data to send with capacity 2048 bytes
+        let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+        // Add the handler byte.
+        buffer_to_send.push(handler_byte);
+
+        // Add the subscription ID and sequence number, then the values.
+        marshal_u32(self.id, &mut buffer_to_send);
+        marshal_u32(self.last_sequence, &mut buffer_to_send);
+        marshal_values(&mut buffer_to_send);
+
+        networking::send_callback(buffer_to_send.clone(), socket, &self.client_addr);
+        PendingUpdate {
+            client_addr: self.client_addr,
+            payload: buffer_to_send,
+            num_attempts: 1,
+            retry_at: Instant::now() + FIRST_RETRY_DELAY,
+        }
+    }
+}
+
+// An update that the subscriber has not acknowledged yet.
+struct PendingUpdate {
+    client_addr: SocketAddr,
+    payload: Vec<u8>,
+    num_attempts: u32,
+    retry_at: Instant,
+}
+
+// The monitoring subscriptions, by subscription ID, and the updates sent to them that are waiting for an
+// acknowledgement.
+pub struct SubscriptionStore {
+    subscriptions: HashMap<u32, Subscription>,
+    next_subscription_id: u32,
+    // By subscription ID and sequence number.
+    pending_updates: HashMap<(u32, u32), PendingUpdate>,
+}
+
+impl SubscriptionStore {
+    pub fn new() -> SubscriptionStore {
+        SubscriptionStore {
+            subscriptions: HashMap::new(),
+            next_subscription_id: 1,
+            pending_updates: HashMap::new(),
+        }
+    }
+
+    // Subscribes the user to the flight's updates and returns the subscription ID.
+    pub fn subscribe(
+        &mut self,
+        flight: &Flight,
+        user_id: u32,
+        client_addr: SocketAddr,
+        expires_at: u32,
+        watch: Watch,
+    ) -> u32 {
+        let target = Target::Flight {
+            flight_id: flight.id,
+            watch,
+            last_values: WatchedValues::of(flight),
+        };
+        self.insert(user_id, client_addr, expires_at, target)
+    }
+
+    // Subscribes the user to the updates of the flights from the source to the destination, including ones
+    // added later, and returns the subscription ID.
+    pub fn subscribe_route(
+        &mut self,
+        flight_db: &FlightStore,
+        source: &str,
+        destination: &str,
+        user_id: u32,
+        client_addr: SocketAddr,
+        expires_at: u32,
+    ) -> u32 {
+        let target = Target::Route {
+            source: source.to_string(),
+            destination: destination.to_string(),
+            last_values: flight_db
+                .on_route(source, destination)
+                .map(|flight| (flight.id, WatchedValues::of(flight)))
+                .collect(),
+        };
+        self.insert(user_id, client_addr, expires_at, target)
+    }
+
+    // Adds a subscription and returns its ID. Subscribing again to the same flight or route replaces the
+    // user's earlier subscription, so that updates go to where they subscribed from last.
+    fn insert(
+        &mut self,
+        user_id: u32,
+        client_addr: SocketAddr,
+        expires_at: u32,
+        target: Target,
+    ) -> u32 {
+        self.subscriptions.retain(|_, subscription| {
+            subscription.user_id != user_id || !subscription.target.is_same_as(&target)
+        });
+        self.drop_orphaned_updates();
+
+        let id = self.next_subscription_id;
+        self.next_subscription_id += 1;
+        self.subscriptions.insert(
+            id,
+            Subscription {
+                id,
+                user_id,
+                client_addr,
+                expires_at,
+                target,
+                last_sequence: 0,
+            },
+        );
+        id
+    }
+
+    // Cancels the user's subscription before it expires. Returns None if the user has no such subscription.
+    pub fn unsubscribe(&mut self, id: u32, user_id: u32) -> Option<Subscription> {
+        let subscription = match self.subscriptions.get(&id) {
+            Some(subscription) if subscription.user_id == user_id => self.subscriptions.remove(&id),
+            _ => None,
+        };
+        self.drop_orphaned_updates();
+        subscription
+    }
+
+    // The user's subscription, for a client that missed updates and wants the current state instead. Updates
+    // still waiting for an acknowledgement are no longer sent, since the state replaces them.
+    pub fn resync(&mut self, id: u32, user_id: u32, now: u32) -> Option<&Subscription> {
+        self.pending_updates
+            .retain(|(subscription_id, _), _| *subscription_id != id);
+        self.subscriptions
+            .get(&id)
+            .filter(|subscription| subscription.user_id == user_id && subscription.is_active(now))
+    }
+
+    // Compares every active subscription's flight, or the flights on its route, with the values of its last
+    // update, and sends an update with the next sequence number of the subscription for every change to
+    // report. Each update is kept until it is acknowledged. Called after every request, so that changes from
+    // any service are reported.
+    pub fn publish_changes(&mut self, flight_db: &FlightStore, socket: &UdpSocket, now: u32) {
+        for subscription in self.subscriptions.values_mut() {
+            // Expired subscriptions are left for the sweeper, which tells the subscriber.
+            if !subscription.is_active(now) {
+                continue;
+            }
+            let mut updates = Vec::new();
+            match &mut subscription.target {
+                Target::Flight {
+                    flight_id,
+                    watch,
+                    last_values,
+                } => {
+                    let flight = match flight_db.get(flight_id) {
+                        Some(flight) => flight,
+                        None => continue,
+                    };
+                    let values = WatchedValues::of(flight);
+                    let changes = watch.reported_changes(last_values, &values);
+                    *last_values = values;
+                    if changes != 0 {
+                        updates.push((flight, changes));
+                    }
+                }
+                Target::Route {
+                    source,
+                    destination,
+                    last_values,
+                } => {
+                    // The route index gives the flights on the route without looking at any others.
+                    for flight in flight_db.on_route(source, destination) {
+                        let values = WatchedValues::of(flight);
+                        if let Some(event) =
+                            route_event(last_values.insert(flight.id, values), &values)
+                        {
+                            updates.push((flight, event));
+                        }
+                    }
+                }
+            }
+
+            for (flight, changes_or_event) in updates {
+                let pending = match &subscription.target {
+                    Target::Flight { .. } => subscription.send_update(
+                        4,
+                        |buf| marshal_watched_values(flight, changes_or_event, buf),
+                        socket,
+                    ),
+                    Target::Route {
+                        source,
+                        destination,
+                        ..
+                    } => {
+                        let (source, destination) = (source.clone(), destination.clone());
+                        subscription.send_update(
+                            26,
+                            |buf| {
+                                marshal_string(&source, buf);
+                                marshal_string(&destination, buf);
+                                marshal_route_flight(flight, changes_or_event, buf);
+                            },
+                            socket,
+                        )
+                    }
+                };
+                println!(
+                    "Informing client: {}, flight_id: {}, changes: {}, sequence: {}",
+                    subscription.client_addr,
+                    flight.id,
+                    changes_or_event,
+                    subscription.last_sequence
+                );
+                self.pending_updates
+                    .insert((subscription.id, subscription.last_sequence), pending);
+            }
+        }
+    }
+
+    // Stops sending an update once the subscriber acknowledges it. Acknowledgements from anywhere but the
+    // subscriber are ignored.
+    pub fn acknowledge(&mut self, client_addr: &SocketAddr, id: u32, sequence: u32) {
+        if self
+            .pending_updates
+            .get(&(id, sequence))
+            .is_some_and(|pending| pending.client_addr == *client_addr)
+        {
+            self.pending_updates.remove(&(id, sequence));
+        }
+    }
+
+    // Sends the unacknowledged updates that are due again, waiting twice as long for the next attempt.
+    // Updates are dropped after the last attempt; the client asks for the current state once it notices.
+    pub fn retransmit_due(&mut self, socket: &UdpSocket) {
+        let now = Instant::now();
+        self.pending_updates.retain(|(id, sequence), pending| {
+            if pending.retry_at > now {
+                return true;
+            }
+            if pending.num_attempts == MAX_SEND_ATTEMPTS {
+                println!("Gave up on update {sequence} of subscription {id}.");
+                return false;
+            }
+            println!(
+                "Resending update {sequence} of subscription {id} to {}.",
+                pending.client_addr
+            );
+            networking::send_callback(pending.payload.clone(), socket, &pending.client_addr);
+            pending.retry_at = now + FIRST_RETRY_DELAY * 2u32.pow(pending.num_attempts);
+            pending.num_attempts += 1;
+            true
+        });
+    }
+
+    // The subscriptions still active for the flight.
+    pub fn active_for_flight(&self, flight_id: u32, now: u32) -> Vec<Subscription> {
+        self.subscriptions
+            .values()
+            .filter(|subscription| {
+                subscription.flight_id() == Some(flight_id) && subscription.is_active(now)
+            })
+            .cloned()
+            .collect()
+    }
+
+    // The clients with an active subscription to the flight that watches its status.
+    pub fn status_watchers(&self, flight_id: u32, now: u32) -> HashSet<SocketAddr> {
+        self.subscriptions
+            .values()
+            .filter(|subscription| match &subscription.target {
+                Target::Flight {
+                    flight_id: subscribed_flight_id,
+                    watch,
+                    ..
+                } => {
+                    *subscribed_flight_id == flight_id
+                        && watch.fields & WATCH_STATUS != 0
+                        && subscription.is_active(now)
+                }
+                Target::Route { .. } => false,
+            })
+            .map(|subscription| subscription.client_addr)
+            .collect()
+    }
+
+    // The user's active subscriptions, sorted by subscription ID.
+    pub fn for_user(&self, user_id: u32, now: u32) -> Vec<&Subscription> {
+        let mut subscriptions: Vec<&Subscription> = self
+            .subscriptions
+            .values()
+            .filter(|subscription| subscription.user_id == user_id && subscription.is_active(now))
+            .collect();
+        subscriptions.sort_by_key(|subscription| subscription.id);
+        subscriptions
+    }
+
+    // Drops every subscription to the flight, once nothing more can happen to it. Subscriptions to its route
+    // are kept.
+    pub fn remove_flight(&mut self, flight_id: u32) {
+        self.subscriptions
+            .retain(|_, subscription| subscription.flight_id() != Some(flight_id));
+        self.drop_orphaned_updates();
+    }
+
+    // Drops the subscriptions that have expired and tells each subscriber, then reports how many
+    // subscriptions are left. Called on a timer, so that subscriptions to quiet flights and routes expire on
+    // time too.
+    pub fn expire_due(&mut self, socket: &UdpSocket, now: u32) {
+        let expired_ids: Vec<u32> = self
+            .subscriptions
+            .values()
+            .filter(|subscription| !subscription.is_active(now))
+            .map(|subscription| subscription.id)
+            .collect();
+        if expired_ids.is_empty() {
+            return;
+        }
+        for id in expired_ids {
+            let subscription = self.subscriptions.remove(&id).unwrap();
+            inform_subscription_expired(socket, &subscription);
+        }
+        self.drop_orphaned_updates();
+
+        let num_route_subscriptions = self
+            .subscriptions
+            .values()
+            .filter(|subscription| subscription.flight_id().is_none())
+            .count();
+        println!(
+            "Subscriptions: {} active, {} to flights and {} to routes, with {} updates waiting for an acknowledgement.",
+            self.subscriptions.len(),
+            self.subscriptions.len() - num_route_subscriptions,
+            num_route_subscriptions,
+            self.pending_updates.len()
+        );
+    }
+
+    // Drops the unacknowledged updates of subscriptions that no longer exist.
+    fn drop_orphaned_updates(&mut self) {
+        let subscriptions = &self.subscriptions;
+        self.pending_updates
+            .retain(|(id, _), _| subscriptions.contains_key(id));
+    }
+}
+
+// Appends the fields given as bits, then the flight ID, the seats across every class, the remaining baggage
+// capacity, the status, the departure time and the seats and airfare of each class, as sent in updates and
+// resyncs.
+pub fn marshal_watched_values(flight: &Flight, fields: u8, buf: &mut Vec<u8>) {
+    marshal_u8(fields, buf);
+    marshal_u32(flight.id, buf);
+    marshal_u32(flight.available_seats(None), buf);
+    marshal_u32(flight.remaining_baggage_kg(), buf);
+    marshal_u8(flight.status.to_u8(), buf);
+    marshal_u32(flight.departure_time, buf);
+    flight.marshal_cabins(&|airfare| airfare, buf);
+}
+
+// What happened to a flight on a monitored route since its last values, if anything worth reporting. A flight
+// with no last values was added to the route.
+fn route_event(before: Option<WatchedValues>, after: &WatchedValues) -> Option<u8> {
+    match before {
+        None => Some(ROUTE_FLIGHT_ADDED),
+        Some(before)
+            if before.status != FlightStatus::Cancelled
+                && after.status == FlightStatus::Cancelled =>
+        {
+            Some(ROUTE_FLIGHT_CANCELLED)
+        }
+        Some(before)
+            if after.available_seats > before.available_seats
+                && after.status != FlightStatus::Cancelled =>
+        {
+            Some(ROUTE_SEATS_AVAILABLE)
+        }
+        _ => None,
+    }
+}
+
+// Appends the event, then the flight ID, the departure time, the seats across every class, the cheapest
+// airfare and the status of a flight on a monitored route.
+pub fn marshal_route_flight(flight: &Flight, event: u8, buf: &mut Vec<u8>) {
+    marshal_u8(event, buf);
+    marshal_u32(flight.id, buf);
+    marshal_u32(flight.departure_time, buf);
+    marshal_u32(flight.available_seats(None), buf);
+    marshal_money(flight.airfare(None), buf);
+    marshal_u8(flight.status.to_u8(), buf);
+}
+
+// Appends the kind of target, then the flight ID or the source and destination of the route.
+pub fn marshal_target(target: &Target, buf: &mut Vec<u8>) {
+    match target {
+        Target::Flight { flight_id, .. } => {
+            marshal_u8(FLIGHT_TARGET, buf);
+            marshal_u32(*flight_id, buf);
+        }
+        Target::Route {
+            source,
+            destination,
+            ..
+        } => {
+            marshal_u8(ROUTE_TARGET, buf);
+            marshal_string(source, buf);
+            marshal_string(destination, buf);
+        }
+    }
+}
+
+// Sends a message to the subscriber to tell them that their subscription has expired.
+fn inform_subscription_expired(socket: &UdpSocket, subscription: &Subscription) {
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add the handler byte.
+    buffer_to_send.push(27);
+
+    // Add the expired subscription ID and the flight or route it was for.
+    marshal_u32(subscription.id, &mut buffer_to_send);
+    marshal_target(&subscription.target, &mut buffer_to_send);
+
+    println!(
+        "Informing client: {}, subscription {} expired",
+        subscription.client_addr, subscription.id
+    );
+    // Send the message to the client.
+    networking::send_callback(buffer_to_send, socket, &subscription.client_addr);
+}
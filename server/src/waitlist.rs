@@ -0,0 +1,216 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    net::{SocketAddr, UdpSocket},
+};
+
+use marshaling::{marshal_string, marshal_u32, marshal_u8};
+
+use crate::{
+    booking::BookingStore,
+    flight::{Flight, SeatClass, SeatSelection},
+};
+
+// A client waiting for seats on a sold out flight.
+pub struct WaitingClient {
+    pub id: u32,
+    // The user the seats are booked for.
+    pub user_id: u32,
+    pub client_addr: SocketAddr,
+    pub seat_class: SeatClass,
+    pub num_seats: u32,
+}
+
+// The clients waiting for seats on each flight, in the order they joined.
+pub struct Waitlist {
+    queues: HashMap<u32, VecDeque<WaitingClient>>,
+    next_waitlist_id: u32,
+}
+
+impl Waitlist {
+    pub fn new() -> Waitlist {
+        Waitlist {
+            queues: HashMap::new(),
+            next_waitlist_id: 1,
+        }
+    }
+
+    // Adds the client to the back of the flight's waitlist. Returns the waitlist reference and the
+    // client's position among those waiting for the same class, starting at 1.
+    pub fn join(
+        &mut self,
+        flight_id: u32,
+        user_id: u32,
+        client_addr: SocketAddr,
+        seat_class: SeatClass,
+        num_seats: u32,
+    ) -> (u32, u32) {
+        let id = self.next_waitlist_id;
+        self.next_waitlist_id += 1;
+
+        let queue = self.queues.entry(flight_id).or_default();
+        queue.push_back(WaitingClient {
+            id,
+            user_id,
+            client_addr,
+            seat_class,
+            num_seats,
+        });
+        let position = queue
+            .iter()
+            .filter(|waiting| waiting.seat_class == seat_class)
+            .count() as u32;
+        (id, position)
+    }
+
+    pub fn client_addrs(&self, flight_id: u32) -> HashSet<SocketAddr> {
+        match self.queues.get(&flight_id) {
+            Some(queue) => queue.iter().map(|waiting| waiting.client_addr).collect(),
+            None => HashSet::new(),
+        }
+    }
+
+    // Drops the flight's waitlist, once its seats can no longer be reserved.
+    pub fn remove_flight(&mut self, flight_id: u32) {
+        self.queues.remove(&flight_id);
+    }
+
+    // Books seats for waiting clients now that seats have freed up, and tells each of them. Clients are
+    // served in the order they joined, within each class: once a group does not fit, nobody behind it in its
+    // class is booked, so that smaller groups cannot skip ahead of it. Returns the number of clients booked.
+    pub fn promote(
+        &mut self,
+        flight: &mut Flight,
+        booking_db: &mut BookingStore,
+        socket: &UdpSocket,
+    ) -> u32 {
+        let queue = match self.queues.get_mut(&flight.id) {
+            Some(queue) => queue,
+            None => return 0,
+        };
+
+        let mut num_promoted = 0;
+        let mut full_classes = HashSet::new();
+        queue.retain(|waiting| {
+            if full_classes.contains(&waiting.seat_class) {
+                return true;
+            }
+            match booking_db.book(
+                flight,
+                waiting.user_id,
+                waiting.client_addr,
+                waiting.seat_class,
+                &SeatSelection::Auto(waiting.num_seats),
+                flight.airfare(Some(waiting.seat_class)),
+            ) {
+                Ok((booking_id, seats)) => {
+                    inform_waitlist_booked(socket, waiting, flight.id, booking_id, &seats);
+                    num_promoted += 1;
+                    false
+                }
+                Err(_) => {
+                    full_classes.insert(waiting.seat_class);
+                    true
+                }
+            }
+        });
+
+        if queue.is_empty() {
+            self.queues.remove(&flight.id);
+        }
+        num_promoted
+    }
+}
+
+// Sends a message to the socket to tell them that the seats they waited for have been booked.
+fn inform_waitlist_booked(
+    socket: &UdpSocket,
+    waiting: &WaitingClient,
+    flight_id: u32,
+    booking_id: u32,
+    seats: &[String],
+) {
+    // Create a buffer to store the data to send with capacity 2048 bytes
+    let mut buffer_to_send: Vec<u8> = Vec::with_capacity(2048);
+
+    // Add the handler byte, the same as the waitlist service.
+    buffer_to_send.push(14);
+
+    // Add the waitlist reference, the flight ID, the booking reference and the booked seats.
+    marshal_u32(waiting.id, &mut buffer_to_send);
+    marshal_u32(flight_id, &mut buffer_to_send);
+    marshal_u32(booking_id, &mut buffer_to_send);
+    marshal_u8(waiting.seat_class.to_u8(), &mut buffer_to_send);
+    marshal_u32(waiting.num_seats, &mut buffer_to_send);
+    marshal_u8(seats.len() as u8, &mut buffer_to_send);
+    for seat in seats {
+        marshal_string(seat, &mut buffer_to_send);
+    }
+
+    println!(
+        "Informing client: {}, flight_id: {}, waitlist booked: {}",
+        waiting.client_addr, flight_id, booking_id
+    );
+    // Send the message to the client.
+    networking::send_callback(buffer_to_send, socket, &waiting.client_addr);
+}
+
+#[cfg(test)]
+mod tests {
+    use marshaling::{Currency, Money};
+
+    use super::*;
+    use crate::flight::{Cabin, FlightStatus};
+
+    fn flight(economy_seats: u32, business_seats: u32) -> Flight {
+        let sgd = |minor_units| Money::new(minor_units, Currency::parse("SGD").unwrap());
+        Flight {
+            id: 1,
+            source: "A".to_string(),
+            destination: "B".to_string(),
+            departure_time: 0,
+            cabins: [
+                Cabin::new(economy_seats, sgd(1000)),
+                Cabin::new(0, sgd(2000)),
+                Cabin::new(business_seats, sgd(3000)),
+            ],
+            baggage_capacity_kg: 0,
+            baggage_reserved_kg: 0,
+            status: FlightStatus::Scheduled,
+            gate: "A1".to_string(),
+            seat_map: None,
+            overbooking_percent: 0,
+        }
+    }
+
+    // The users still waiting on the flight, in order.
+    fn waiting_users(waitlist: &Waitlist, flight_id: u32) -> Vec<u32> {
+        waitlist.queues[&flight_id]
+            .iter()
+            .map(|waiting| waiting.user_id)
+            .collect()
+    }
+
+    #[test]
+    fn groups_that_do_not_fit_are_not_skipped_within_their_class() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client_addr = socket.local_addr().unwrap();
+        let mut flight = flight(3, 1);
+        let mut booking_db = BookingStore::new();
+        let mut waitlist = Waitlist::new();
+        waitlist.join(1, 1, client_addr, SeatClass::Economy, 2);
+        waitlist.join(1, 2, client_addr, SeatClass::Economy, 4);
+        waitlist.join(1, 3, client_addr, SeatClass::Economy, 1);
+        waitlist.join(1, 4, client_addr, SeatClass::Business, 1);
+
+        // The group of 4 does not fit in the one economy seat left, so the group of 1 behind it waits, but
+        // business is booked.
+        assert_eq!(waitlist.promote(&mut flight, &mut booking_db, &socket), 2);
+        assert_eq!(waiting_users(&waitlist, 1), [2, 3]);
+        assert_eq!(flight.available_seats(Some(SeatClass::Economy)), 1);
+
+        // Once the group of 4 fits, both are booked in order.
+        flight.cabins[SeatClass::Economy.to_u8() as usize].seats = 8;
+        assert_eq!(waitlist.promote(&mut flight, &mut booking_db, &socket), 2);
+        assert!(!waitlist.queues.contains_key(&1));
+    }
+}